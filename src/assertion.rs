@@ -0,0 +1,278 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::error_category::ErrorCategory;
+use core::fmt;
+use hyper::header::HeaderName;
+use hyper::{HeaderMap, StatusCode};
+use regex::Regex;
+use sha1::{Digest, Sha1};
+use std::fmt::Write;
+
+/// A check of a response, besides its status code.
+#[derive(Clone, Debug)]
+pub enum Assertion {
+    /// The body matches the regular expression.
+    Body(Regex),
+    /// The value at the JSON pointer equals the expected one.
+    Json {
+        pointer: String,
+        value: serde_json::Value,
+    },
+    /// The header is present.
+    Header(HeaderName),
+    /// The body size is in the range, both bounds included.
+    Size { min: usize, max: usize },
+    /// SHA-1 of the body, in hex.
+    Checksum(String),
+}
+
+/// How a response is reported.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResponseOutcome {
+    pub status: String,
+    pub is_success: bool,
+    pub fatal_error: bool,
+}
+
+/// Success criteria of responses. Failed assertions are reported as `assert:NAME` statuses.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Assertions {
+    /// Status codes of successful responses, instead of any `2xx`.
+    pub status: Vec<u16>,
+    pub checks: Vec<Assertion>,
+    /// Failed assertions stop the run, like `stop_on_errors`.
+    pub fatal: bool,
+}
+
+impl Assertion {
+    /// Reported as `assert:NAME`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Assertion::Body(_) => "body_regex",
+            Assertion::Json { .. } => "json_path",
+            Assertion::Header(_) => "header",
+            Assertion::Size { .. } => "body_size",
+            Assertion::Checksum(_) => "checksum",
+        }
+    }
+
+    fn check(&self, headers: &HeaderMap, body: &[u8], size: usize) -> bool {
+        match self {
+            Assertion::Body(regex) => {
+                std::str::from_utf8(body).is_ok_and(|body| regex.is_match(body))
+            }
+            Assertion::Json { pointer, value } => serde_json::from_slice::<serde_json::Value>(body)
+                .is_ok_and(|json| json.pointer(pointer) == Some(value)),
+            Assertion::Header(name) => headers.contains_key(name),
+            Assertion::Size { min, max } => (*min..=*max).contains(&size),
+            Assertion::Checksum(expected) => {
+                let mut checksum = String::new();
+                for byte in Sha1::digest(body) {
+                    let _ = write!(checksum, "{byte:02x}");
+                }
+                checksum.eq_ignore_ascii_case(expected)
+            }
+        }
+    }
+
+    /// Whether the body has to be kept to check it.
+    fn needs_body(&self) -> bool {
+        matches!(
+            self,
+            Assertion::Body(_) | Assertion::Json { .. } | Assertion::Checksum(_)
+        )
+    }
+}
+
+impl PartialEq for Assertion {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Assertion::Body(a), Assertion::Body(b)) => a.as_str() == b.as_str(),
+            (
+                Assertion::Json { pointer, value },
+                Assertion::Json {
+                    pointer: other_pointer,
+                    value: other_value,
+                },
+            ) => pointer == other_pointer && value == other_value,
+            (Assertion::Header(a), Assertion::Header(b)) => a == b,
+            (Assertion::Size { min, max }, Assertion::Size { min: a, max: b }) => {
+                min == a && max == b
+            }
+            (Assertion::Checksum(a), Assertion::Checksum(b)) => a.eq_ignore_ascii_case(b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Assertion {}
+
+impl Assertions {
+    pub fn is_success_status(&self, status: StatusCode) -> bool {
+        if self.status.is_empty() {
+            status.is_success()
+        } else {
+            self.status.contains(&status.as_u16())
+        }
+    }
+
+    /// Whether the body has to be kept to check the response.
+    pub fn needs_body(&self) -> bool {
+        self.checks.iter().any(Assertion::needs_body)
+    }
+
+    /// The first failed assertion, if any. `body` is empty unless [`Assertions::needs_body`].
+    pub fn check(&self, headers: &HeaderMap, body: &[u8], size: usize) -> Option<&Assertion> {
+        self.checks
+            .iter()
+            .find(|assertion| !assertion.check(headers, body, size))
+    }
+
+    /// The outcome of a response: by the status code (fatal if in `stop_on_errors`), then by a failure
+    /// to receive the body, and then by the other assertions (fatal if [`Assertions::fatal`]).
+    pub fn outcome(
+        &self,
+        status: StatusCode,
+        stop_on_errors: &[u16],
+        headers: &HeaderMap,
+        body: &[u8],
+        size: usize,
+        body_error: Option<&dyn fmt::Display>,
+    ) -> ResponseOutcome {
+        let is_success = self.is_success_status(status);
+        let outcome = ResponseOutcome {
+            status: status.to_string(),
+            is_success,
+            fatal_error: !is_success && stop_on_errors.contains(&status.as_u16()),
+        };
+        if let Some(e) = body_error {
+            ResponseOutcome {
+                status: ErrorCategory::BodyError.status(e),
+                is_success: false,
+                ..outcome
+            }
+        } else if let Some(failed) = is_success
+            .then(|| self.check(headers, body, size))
+            .flatten()
+        {
+            ResponseOutcome {
+                status: format!("assert:{}", failed.name()),
+                is_success: false,
+                fatal_error: self.fatal,
+            }
+        } else {
+            outcome
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assertion::{Assertion, Assertions, ResponseOutcome};
+    use hyper::header::HeaderName;
+    use hyper::{HeaderMap, StatusCode};
+    use regex::Regex;
+
+    #[test]
+    fn test_checks() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-request-id", "1".parse().unwrap());
+        let body = br#"{"status": "ok", "items": [1, 2]}"#;
+        let passed = [
+            Assertion::Body(Regex::new(r#""status": "ok""#).unwrap()),
+            Assertion::Json {
+                pointer: "/items/1".to_string(),
+                value: serde_json::json!(2),
+            },
+            Assertion::Header(HeaderName::from_static("x-request-id")),
+            Assertion::Size { min: 0, max: 100 },
+        ];
+        for assertion in &passed {
+            assert!(
+                assertion.check(&headers, body, body.len()),
+                "{}",
+                assertion.name()
+            );
+        }
+
+        let assertions = Assertions {
+            status: vec![200, 404],
+            checks: passed.to_vec(),
+            fatal: false,
+        };
+        assert!(assertions.is_success_status(StatusCode::NOT_FOUND));
+        assert!(!assertions.is_success_status(StatusCode::CREATED));
+        assert!(assertions.needs_body());
+        assert_eq!(None, assertions.check(&headers, body, body.len()));
+        assert_eq!(
+            "json_path",
+            assertions
+                .check(&headers, br#"{"status": "ok"}"#, 16)
+                .unwrap()
+                .name()
+        );
+        assert_eq!(
+            "body_regex",
+            assertions.check(&headers, b"", 0).unwrap().name()
+        );
+        assert!(Assertions::default().is_success_status(StatusCode::CREATED));
+    }
+
+    #[test]
+    fn test_outcome() {
+        let headers = HeaderMap::new();
+        let assertions = Assertions {
+            status: vec![],
+            checks: vec![Assertion::Size { min: 1, max: 10 }],
+            fatal: true,
+        };
+        let outcome = |status, size, body_error: Option<&dyn std::fmt::Display>| {
+            assertions.outcome(status, &[503], &headers, b"", size, body_error)
+        };
+        assert_eq!(
+            ResponseOutcome {
+                status: "200 OK".to_string(),
+                is_success: true,
+                fatal_error: false,
+            },
+            outcome(StatusCode::OK, 5, None)
+        );
+        assert_eq!(
+            ResponseOutcome {
+                status: "assert:body_size".to_string(),
+                is_success: false,
+                fatal_error: true,
+            },
+            outcome(StatusCode::OK, 20, None)
+        );
+        // the status is checked first, and the assertions only on successful responses
+        assert_eq!(
+            ResponseOutcome {
+                status: "503 Service Unavailable".to_string(),
+                is_success: false,
+                fatal_error: true,
+            },
+            outcome(StatusCode::SERVICE_UNAVAILABLE, 20, None)
+        );
+        assert_eq!(
+            ResponseOutcome {
+                status: "body_error".to_string(),
+                is_success: false,
+                fatal_error: false,
+            },
+            outcome(StatusCode::OK, 5, Some(&"stream reset"))
+        );
+    }
+
+    #[test]
+    fn test_checksum() {
+        let assertion = Assertion::Checksum("A9993E364706816ABA3E25717850C26C9CD0D89D".to_string());
+        assert!(assertion.check(&HeaderMap::new(), b"abc", 3));
+        assert!(!assertion.check(&HeaderMap::new(), b"abd", 3));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::metrics::RequestStats;
+use crate::metrics::{RequestStats, RequestStatsBuilder};
 use crate::rate_limiter::RateLimiter;
 /// Copyright 2020 Developers of the perf-gauge project.
 ///
@@ -8,14 +8,33 @@ use crate::rate_limiter::RateLimiter;
 /// option. This file may not be copied, modified, or distributed
 /// except according to those terms.
 use async_trait::async_trait;
+use core::fmt;
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use log::error;
+use std::any::Any;
 use std::future::Future;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc::Sender;
-use tokio::time::timeout;
-
-static STOP_ON_FATAL: AtomicBool = AtomicBool::new(false);
+use tokio::time::{sleep_until, timeout};
+
+/// The share of timeouts is not checked on fewer requests, as it's too noisy.
+const MIN_REQUESTS_FOR_TIMEOUT_RATE: usize = 100;
+
+/// Status of requests timed out after `request_timeout`. Unlike the `request_timeout` category
+/// reported by the clients (e.g. by hyper), it's counted towards `max_timeout_rate`.
+const TIMEOUT_STATUS: &str = "timeout";
+
+/// State shared by the clients of a batch: once one of them hits a fatal error,
+/// all of them stop sending requests.
+#[derive(Debug, Default)]
+pub struct BatchState {
+    stopped: AtomicBool,
+    /// Requests of the batch, to check the share of timeouts against `max_timeout_rate`.
+    requests_completed: AtomicUsize,
+    requests_timed_out: AtomicUsize,
+}
 
 #[derive(Clone, Debug)]
 pub struct BenchRun {
@@ -27,6 +46,11 @@ pub struct BenchRun {
     max_requests: Option<usize>,
     max_duration: Option<Duration>,
     rate_limiter: RateLimiter,
+    open_model: bool,
+    /// If set, timed-out requests are recorded and the batch continues,
+    /// unless more than this percentage of requests time out.
+    max_timeout_rate: Option<f64>,
+    batch_state: Arc<BatchState>,
 }
 
 #[async_trait]
@@ -35,22 +59,118 @@ pub trait BenchmarkProtocolAdapter {
 
     async fn build_client(&self) -> Result<Self::Client, String>;
     async fn send_request(&self, client: &Self::Client) -> RequestStats;
+
+    /// If requests of a client can be in flight at the same time, as `--open_model` sends them on a schedule.
+    /// Adapters handling one request at a time per client (e.g. over a single connection) would delay
+    /// the scheduled ones, and hide the delay from the latency.
+    fn supports_open_model(&self) -> bool {
+        true
+    }
 }
 
-impl BenchRun {
-    /// Reset the global stop flag before each batch to prevent
-    /// a fatal error in one batch from silently skipping all subsequent batches.
-    pub fn reset_stop_flag() {
-        STOP_ON_FATAL.store(false, Ordering::Relaxed);
+/// Object-safe counterpart of [`BenchmarkProtocolAdapter`], so adapters can be plugged in at runtime
+/// (see [`BenchmarkMode::Custom`](crate::configuration::BenchmarkMode::Custom)). Implemented for every adapter with a `Send + Sync` client.
+#[async_trait]
+pub trait DynProtocolAdapter: Send + Sync + fmt::Display {
+    async fn build_dyn_client(&self) -> Result<Box<dyn Any + Send + Sync>, String>;
+    async fn send_dyn_request(&self, client: &(dyn Any + Send + Sync)) -> RequestStats;
+    fn supports_dyn_open_model(&self) -> bool;
+}
+
+#[async_trait]
+impl<A> DynProtocolAdapter for A
+where
+    A: BenchmarkProtocolAdapter + Send + Sync + fmt::Display,
+    A::Client: Send + Sync + 'static,
+{
+    async fn build_dyn_client(&self) -> Result<Box<dyn Any + Send + Sync>, String> {
+        Ok(Box::new(self.build_client().await?))
+    }
+
+    async fn send_dyn_request(&self, client: &(dyn Any + Send + Sync)) -> RequestStats {
+        let client = client
+            .downcast_ref::<A::Client>()
+            .expect("Client must be built by the same adapter");
+        self.send_request(client).await
+    }
+
+    fn supports_dyn_open_model(&self) -> bool {
+        self.supports_open_model()
+    }
+}
+
+#[async_trait]
+impl<'a> BenchmarkProtocolAdapter for Arc<dyn DynProtocolAdapter + 'a> {
+    type Client = Box<dyn Any + Send + Sync>;
+
+    async fn build_client(&self) -> Result<Self::Client, String> {
+        // dispatch to the wrapped adapter, as `Arc` itself is an adapter too
+        self.as_ref().build_dyn_client().await
+    }
+
+    async fn send_request(&self, client: &Self::Client) -> RequestStats {
+        self.as_ref().send_dyn_request(client.as_ref()).await
+    }
+
+    fn supports_open_model(&self) -> bool {
+        self.as_ref().supports_dyn_open_model()
+    }
+}
+
+impl BatchState {
+    /// Stops sending requests in all clients of the batch.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
     }
 
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Counts the request towards the share of timeouts in the batch. Returns `true` once it's above `max_timeout_rate`.
+    fn too_many_timeouts(
+        &self,
+        request_stats: &RequestStats,
+        max_timeout_rate: Option<f64>,
+    ) -> bool {
+        let Some(max_timeout_rate) = max_timeout_rate else {
+            return false;
+        };
+        let completed = self.requests_completed.fetch_add(1, Ordering::Relaxed) + 1;
+        let timed_out = if request_stats.status == TIMEOUT_STATUS {
+            self.requests_timed_out.fetch_add(1, Ordering::Relaxed) + 1
+        } else {
+            self.requests_timed_out.load(Ordering::Relaxed)
+        };
+        let timeout_rate = timed_out as f64 * 100. / completed as f64;
+        if completed >= MIN_REQUESTS_FOR_TIMEOUT_RATE && timeout_rate > max_timeout_rate {
+            error!(
+                "{timeout_rate:.3}% of requests timed out, above {max_timeout_rate:.3}%. Aborting."
+            );
+            return true;
+        }
+        false
+    }
+}
+
+impl BenchRun {
     pub fn from_request_limit(
         index: usize,
         max_requests: usize,
         rate_limiter: RateLimiter,
         timeout: Option<Duration>,
+        open_model: bool,
+        max_timeout_rate: Option<f64>,
     ) -> Self {
-        Self::new(index, Some(max_requests), None, rate_limiter, timeout)
+        Self::new(
+            index,
+            Some(max_requests),
+            None,
+            rate_limiter,
+            timeout,
+            open_model,
+            max_timeout_rate,
+        )
     }
 
     pub fn from_duration_limit(
@@ -58,8 +178,18 @@ impl BenchRun {
         max_duration: Duration,
         rate_limiter: RateLimiter,
         timeout: Option<Duration>,
+        open_model: bool,
+        max_timeout_rate: Option<f64>,
     ) -> Self {
-        Self::new(index, None, Some(max_duration), rate_limiter, timeout)
+        Self::new(
+            index,
+            None,
+            Some(max_duration),
+            rate_limiter,
+            timeout,
+            open_model,
+            max_timeout_rate,
+        )
     }
 
     fn new(
@@ -68,6 +198,8 @@ impl BenchRun {
         max_duration: Option<Duration>,
         rate_limiter: RateLimiter,
         timeout: Option<Duration>,
+        open_model: bool,
+        max_timeout_rate: Option<f64>,
     ) -> Self {
         assert!(
             max_duration.is_some() || max_requests.is_some(),
@@ -82,9 +214,18 @@ impl BenchRun {
             max_requests,
             max_duration,
             rate_limiter,
+            open_model,
+            max_timeout_rate,
+            batch_state: Arc::default(),
         }
     }
 
+    /// Shares the `batch_state` with other clients of the batch. By default each run has its own.
+    pub fn in_batch(mut self, batch_state: Arc<BatchState>) -> Self {
+        self.batch_state = batch_state;
+        self
+    }
+
     pub fn has_more_work(&mut self) -> bool {
         let has_more_work = if let Some(max_requests) = self.max_requests {
             self.requests_sent < max_requests
@@ -100,6 +241,21 @@ impl BenchRun {
     }
 
     pub async fn send_load(
+        self,
+        bench_protocol_adapter: &impl BenchmarkProtocolAdapter,
+        metrics_channel: Sender<RequestStats>,
+    ) -> Result<(), String> {
+        if self.open_model {
+            self.send_open_model_load(bench_protocol_adapter, metrics_channel)
+                .await
+        } else {
+            self.send_closed_model_load(bench_protocol_adapter, metrics_channel)
+                .await
+        }
+    }
+
+    /// Each client waits for the previous request to complete before sending the next one.
+    async fn send_closed_model_load(
         mut self,
         bench_protocol_adapter: &impl BenchmarkProtocolAdapter,
         metrics_channel: Sender<RequestStats>,
@@ -109,38 +265,155 @@ impl BenchRun {
         while self.has_more_work() {
             self.rate_limiter.acquire_one().await;
 
-            if STOP_ON_FATAL.load(Ordering::Relaxed) {
+            if self.batch_state.is_stopped() {
                 break;
             }
 
-            let timed_request = self
-                .timed_operation(bench_protocol_adapter.send_request(&client))
-                .await;
+            let timed_request = BenchRun::timed_request(
+                self.timeout,
+                self.max_timeout_rate,
+                bench_protocol_adapter.send_request(&client),
+            )
+            .await;
+
+            if !self.publish_stats(timed_request, &metrics_channel).await {
+                break;
+            }
+        }
+
+        Ok(())
+    }
 
-            let fatal_error = match timed_request {
-                Ok(request_stats) => {
-                    let failed = request_stats.fatal_error;
-                    if metrics_channel.send(request_stats).await.is_err() {
-                        error!("Metrics channel closed");
-                        break;
+    /// Requests are sent on a fixed timeline, regardless of how long the previous ones take.
+    /// Apart from the service time, the latency is also measured from the intended send time,
+    /// so a stalled target doesn't hide behind the fact that fewer requests were sent
+    /// (a.k.a. coordinated omission).
+    async fn send_open_model_load(
+        mut self,
+        bench_protocol_adapter: &impl BenchmarkProtocolAdapter,
+        metrics_channel: Sender<RequestStats>,
+    ) -> Result<(), String> {
+        if self.rate_limiter.is_unlimited() {
+            return Err("Open-model load requires a request rate".to_string());
+        }
+        let client = bench_protocol_adapter.build_client().await?;
+        let request_timeout = self.timeout;
+        let max_timeout_rate = self.max_timeout_rate;
+
+        let mut in_flight = FuturesUnordered::new();
+        let mut next_send = tokio::time::Instant::now();
+        let mut stopped = false;
+
+        while !stopped && self.has_more_work() {
+            // collect completed requests while waiting for the next scheduled one
+            loop {
+                tokio::select! {
+                    _ = sleep_until(next_send) => break,
+                    Some(timed_request) = in_flight.next(), if !in_flight.is_empty() => {
+                        if !self.publish_stats(timed_request, &metrics_channel).await {
+                            stopped = true;
+                            break;
+                        }
                     }
-                    failed
                 }
-                Err(_) => true,
-            };
+            }
 
-            if fatal_error {
-                STOP_ON_FATAL.store(true, Ordering::Relaxed);
+            if stopped || self.batch_state.is_stopped() {
                 break;
             }
+
+            let intended_start = next_send.into_std();
+            let client = &client;
+            in_flight.push(async move {
+                let timed_request = BenchRun::timed_request(
+                    request_timeout,
+                    max_timeout_rate,
+                    bench_protocol_adapter.send_request(client),
+                )
+                .await;
+                timed_request.map(|mut request_stats| {
+                    request_stats.response_time =
+                        Some(Instant::now().duration_since(intended_start));
+                    request_stats
+                })
+            });
+
+            next_send += self
+                .rate_limiter
+                .next_inter_arrival()
+                .expect("Rate is limited at this point");
+        }
+
+        while let Some(timed_request) = in_flight.next().await {
+            if !stopped && !self.publish_stats(timed_request, &metrics_channel).await {
+                stopped = true;
+            }
         }
 
         Ok(())
     }
 
+    /// Sends request stats to the metrics aggregator.
+    /// Returns `false` if the client should stop sending requests.
+    async fn publish_stats(
+        &self,
+        timed_request: Result<RequestStats, ()>,
+        metrics_channel: &Sender<RequestStats>,
+    ) -> bool {
+        let fatal_error = match timed_request {
+            Ok(request_stats) => {
+                let failed = request_stats.fatal_error
+                    || self
+                        .batch_state
+                        .too_many_timeouts(&request_stats, self.max_timeout_rate);
+                if metrics_channel.send(request_stats).await.is_err() {
+                    error!("Metrics channel closed");
+                    return false;
+                }
+                failed
+            }
+            Err(_) => true,
+        };
+
+        if fatal_error {
+            self.batch_state.stop();
+            return false;
+        }
+
+        true
+    }
+
+    /// A request bound by the `request_timeout`. If `max_timeout_rate` is set,
+    /// a timed-out request is a failed one with the `timeout` status, instead of an error.
+    async fn timed_request(
+        request_timeout: Option<Duration>,
+        max_timeout_rate: Option<f64>,
+        request: impl Future<Output = RequestStats>,
+    ) -> Result<RequestStats, ()> {
+        let start = Instant::now();
+        match BenchRun::with_timeout(request_timeout, request).await {
+            Err(()) if max_timeout_rate.is_some() => Ok(RequestStatsBuilder::default()
+                .bytes_processed(0)
+                .status(TIMEOUT_STATUS.to_string())
+                .is_success(false)
+                .duration(Instant::now().duration_since(start))
+                .fatal_error(false)
+                .build()
+                .expect("RequestStatsBuilder failed")),
+            timed_request => timed_request,
+        }
+    }
+
     /// Each async operation must be time-bound.
     pub async fn timed_operation<T: Future>(&self, f: T) -> Result<<T as Future>::Output, ()> {
-        if let Some(timeout_value) = self.timeout {
+        BenchRun::with_timeout(self.timeout, f).await
+    }
+
+    async fn with_timeout<T: Future>(
+        request_timeout: Option<Duration>,
+        f: T,
+    ) -> Result<<T as Future>::Output, ()> {
+        if let Some(timeout_value) = request_timeout {
             timeout(timeout_value, f).await.map_err(|_| ())
         } else {
             Ok(f.await)
@@ -150,24 +423,19 @@ impl BenchRun {
 
 #[cfg(test)]
 mod tests {
-    use crate::bench_run::STOP_ON_FATAL;
+    use crate::bench_run::MIN_REQUESTS_FOR_TIMEOUT_RATE;
     use crate::bench_session::RateLadderBuilder;
     use crate::configuration::BenchmarkMode::Http;
     use crate::configuration::{BenchmarkConfig, BenchmarkConfigBuilder};
     use crate::http_bench_session::{
         HttpBenchAdapterBuilder, HttpClientConfigBuilder, HttpRequestBuilder,
     };
-    use crate::metrics::BenchRunMetrics;
-    use std::sync::atomic::Ordering;
+    use crate::metrics::{BenchRunMetrics, HistogramStatsExt};
     use std::thread::sleep;
     use std::time::{Duration, Instant};
 
-    static TEST_MUTEX: std::sync::Mutex<()> = std::sync::Mutex::new(());
-
     #[tokio::test]
-    #[allow(clippy::await_holding_lock)]
     async fn test_send_load() {
-        let _guard = TEST_MUTEX.lock().unwrap();
         let body = "world";
         let request_count = 100;
         let mut server = mockito::Server::new_async().await;
@@ -221,15 +489,86 @@ mod tests {
 
         let bench_run_stats = BenchRunMetrics::new();
 
-        STOP_ON_FATAL.store(false, Ordering::Relaxed);
+        let batch = session.next().expect("Must have runs");
+        let state = batch.state.clone();
+        let bench_result = batch.run(bench_run_stats).await;
 
-        let bench_result = session
-            .next()
-            .expect("Must have runs")
-            .run(bench_run_stats)
+        assert!(!state.is_stopped());
+        assert!(bench_result.is_ok());
+
+        let elapsed = Instant::now().duration_since(start).as_secs_f64();
+        let time_delta = (elapsed - 1.).abs();
+        assert!(
+            time_delta < 0.3,
+            "Expected to finish in ~1s, but it took: {elapsed}"
+        );
+
+        let stats = bench_result.unwrap();
+
+        assert_eq!(body.len() * request_count, stats.combined.total_bytes);
+        assert_eq!(request_count, stats.combined.total_requests);
+        assert_eq!(
+            stats.combined.summary.get("200 OK"),
+            Some(&(request_count as i32))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_load_open_model() {
+        let body = "world";
+        let request_count = 100;
+        let mut server = mockito::Server::new_async().await;
+
+        let _m = server
+            .mock("GET", "/1")
+            .with_status(200)
+            .with_header("content-type", "text/plain")
+            .with_body(body)
+            .expect(request_count)
+            .create_async()
             .await;
 
-        assert!(!STOP_ON_FATAL.load(Ordering::Relaxed));
+        let url = server.url();
+        println!("Url: {url}");
+        let http_adapter = HttpBenchAdapterBuilder::default()
+            .request(
+                HttpRequestBuilder::default()
+                    .url(vec![format!("{url}/1")])
+                    .build()
+                    .unwrap(),
+            )
+            .config(HttpClientConfigBuilder::default().build().unwrap())
+            .build()
+            .unwrap();
+
+        let benchmark_config: BenchmarkConfig = BenchmarkConfigBuilder::default()
+            .rate_ladder(
+                RateLadderBuilder::default()
+                    .start(request_count as f64)
+                    .end(request_count as f64)
+                    .rate_increment(None)
+                    .step_duration(None)
+                    .step_requests(Some(request_count))
+                    .build()
+                    .expect("RateLadderBuilder failed"),
+            )
+            .mode(Http(http_adapter.clone()))
+            .request_timeout(None)
+            .open_model(true)
+            .build()
+            .expect("BenchmarkConfig failed");
+
+        let start = Instant::now();
+
+        let mut session = benchmark_config.clone().new_bench_session();
+
+        let bench_run_stats = BenchRunMetrics::new();
+
+        let batch = session.next().expect("Must have runs");
+        let state = batch.state.clone();
+        let bench_result = batch.run(bench_run_stats).await;
+
+        assert!(!state.is_stopped());
         assert!(bench_result.is_ok());
 
         let elapsed = Instant::now().duration_since(start).as_secs_f64();
@@ -241,18 +580,22 @@ mod tests {
 
         let stats = bench_result.unwrap();
 
-        assert_eq!(body.len() * request_count, stats.combined.total_bytes);
         assert_eq!(request_count, stats.combined.total_requests);
         assert_eq!(
             stats.combined.summary.get("200 OK"),
             Some(&(request_count as i32))
         );
+        let corrected = stats
+            .combined
+            .response_time
+            .into_iter()
+            .map(|b| b.count())
+            .sum::<u64>();
+        assert_eq!(request_count as u64, corrected);
     }
 
     #[tokio::test]
-    #[allow(clippy::await_holding_lock)]
     async fn test_send_load_fatal_code() {
-        let _guard = TEST_MUTEX.lock().unwrap();
         let body = "world";
         let request_count = 100;
         let mut server = mockito::Server::new_async().await;
@@ -304,24 +647,18 @@ mod tests {
 
         let bench_run_stats = BenchRunMetrics::new();
 
-        STOP_ON_FATAL.store(false, Ordering::Relaxed);
-
-        let bench_result = session
-            .next()
-            .expect("Must have runs")
-            .run(bench_run_stats)
-            .await;
+        let batch = session.next().expect("Must have runs");
+        let state = batch.state.clone();
+        let bench_result = batch.run(bench_run_stats).await;
 
         // must stop on fatal
-        assert!(STOP_ON_FATAL.load(Ordering::Relaxed));
+        assert!(state.is_stopped());
         assert!(bench_result.is_ok());
     }
 
     #[tokio::test]
-    #[allow(clippy::await_holding_lock)]
     #[allow(deprecated)]
     async fn test_send_load_with_timeout() {
-        let _guard = TEST_MUTEX.lock().unwrap();
         let request_count = 100;
         let mut server = mockito::Server::new_async().await;
 
@@ -370,16 +707,81 @@ mod tests {
 
         let bench_run_stats = BenchRunMetrics::new();
 
-        STOP_ON_FATAL.store(false, Ordering::Relaxed);
-
-        let bench_result = session
-            .next()
-            .expect("Must have runs")
-            .run(bench_run_stats)
-            .await;
+        let batch = session.next().expect("Must have runs");
+        let state = batch.state.clone();
+        let bench_result = batch.run(bench_run_stats).await;
 
         // must stop on timeout, treated as fatal
-        assert!(STOP_ON_FATAL.load(Ordering::Relaxed));
+        assert!(state.is_stopped());
         assert!(bench_result.is_ok());
     }
+
+    /// Runs a batch against a server that never responds.
+    async fn run_with_timeouts(
+        request_count: usize,
+        max_timeout_rate: Option<f64>,
+    ) -> (BenchRunMetrics, bool) {
+        // connections are accepted by the OS, but requests are never read
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/1", listener.local_addr().unwrap());
+        let http_adapter = HttpBenchAdapterBuilder::default()
+            .request(
+                HttpRequestBuilder::default()
+                    .url(vec![url])
+                    .build()
+                    .unwrap(),
+            )
+            .config(HttpClientConfigBuilder::default().build().unwrap())
+            .build()
+            .unwrap();
+
+        let benchmark_config: BenchmarkConfig = BenchmarkConfigBuilder::default()
+            .rate_ladder(
+                RateLadderBuilder::default()
+                    .start(0.)
+                    .end(0.)
+                    .rate_increment(None)
+                    .step_duration(None)
+                    .step_requests(Some(request_count))
+                    .build()
+                    .expect("RateLadderBuilder failed"),
+            )
+            .request_timeout(Some(Duration::from_millis(5)))
+            .max_timeout_rate(max_timeout_rate)
+            .mode(Http(http_adapter))
+            .build()
+            .expect("BenchmarkConfig failed");
+
+        let batch = benchmark_config
+            .clone()
+            .new_bench_session()
+            .next()
+            .expect("Must have runs");
+        let state = batch.state.clone();
+        let stats = batch
+            .run(BenchRunMetrics::new())
+            .await
+            .expect("Batch must complete");
+        (stats, state.is_stopped())
+    }
+
+    #[tokio::test]
+    async fn test_send_load_with_non_fatal_timeouts() {
+        let (stats, stopped) = run_with_timeouts(10, Some(100.)).await;
+
+        assert!(!stopped);
+        assert_eq!(10, stats.combined.total_requests);
+        assert_eq!(0, stats.combined.successful_requests);
+        assert_eq!(Some(&10), stats.combined.summary.get("timeout"));
+        assert!(stats.combined.error_latency.minimum().unwrap() >= 5_000);
+    }
+
+    #[tokio::test]
+    async fn test_send_load_above_max_timeout_rate() {
+        let (stats, stopped) = run_with_timeouts(1_000, Some(5.)).await;
+
+        // aborted once there are enough requests to tell the rate
+        assert!(stopped);
+        assert_eq!(MIN_REQUESTS_FOR_TIMEOUT_RATE, stats.combined.total_requests);
+    }
 }
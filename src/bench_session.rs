@@ -1,7 +1,9 @@
-use crate::bench_run::BenchRun;
+use crate::bench_run::{BatchState, BenchRun};
+use crate::circuit_breaker::CircuitBreaker;
 use crate::configuration::BenchmarkMode;
+use crate::load_profile::LoadProfile;
 use crate::metrics::{BenchRunMetrics, RequestStats};
-use crate::rate_limiter::RateLimiter;
+use crate::rate_limiter::{ArrivalProcess, RateLimiter};
 use core::fmt;
 /// Copyright 2020 Developers of the perf-gauge project.
 ///
@@ -14,7 +16,7 @@ use derive_builder::Builder;
 use log::error;
 use log::info;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
 
@@ -26,11 +28,25 @@ pub struct BenchSession {
     #[builder(setter(skip))]
     current_iteration: usize,
     request_timeout: Option<Duration>,
+    /// Timeouts are recorded instead of being fatal, unless more than this percentage of requests time out.
+    #[builder(default)]
+    max_timeout_rate: Option<f64>,
+    #[builder(default)]
+    open_model: bool,
+    #[builder(default)]
+    arrival_process: ArrivalProcess,
+    /// If set, rates and durations of batches are taken from the profile instead of the rate ladder.
+    #[builder(default)]
+    load_profile: Option<LoadProfile>,
+    #[builder(default)]
+    circuit_breaker: Option<CircuitBreaker>,
 }
 
 pub struct BenchBatch {
     runs: Vec<BenchRun>,
     mode: Arc<BenchmarkMode>,
+    circuit_breaker: Option<CircuitBreaker>,
+    pub(crate) state: Arc<BatchState>,
 }
 
 #[derive(Builder, Debug, Clone)]
@@ -52,44 +68,90 @@ impl Iterator for BenchSession {
     type Item = BenchBatch;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.rate_ladder.complete {
-            return None;
+        let (current, step_requests, step_duration) =
+            if let Some(load_profile) = self.load_profile.as_mut() {
+                let step = load_profile.next_step()?;
+                (step.rate, None, Some(step.duration))
+            } else if self.rate_ladder.complete {
+                return None;
+            } else {
+                (
+                    self.rate_ladder.get_current(),
+                    self.rate_ladder.step_requests,
+                    self.rate_ladder.step_duration,
+                )
+            };
+
+        let batch = self.build_batch(current, step_requests, step_duration);
+
+        if self.load_profile.is_none() {
+            self.rate_ladder.increment_rate();
         }
 
-        let current = self.rate_ladder.get_current();
+        Some(batch)
+    }
+}
 
+impl BenchSession {
+    /// A batch with the given rate, limited by the step duration or requests of the rate ladder.
+    /// Used to drive the session adaptively, e.g. for the throughput search.
+    pub fn batch_at(&mut self, rate: f64) -> BenchBatch {
+        self.build_batch(
+            rate,
+            self.rate_ladder.step_requests,
+            self.rate_ladder.step_duration,
+        )
+    }
+
+    fn build_batch(
+        &mut self,
+        current: f64,
+        step_requests: Option<usize>,
+        step_duration: Option<Duration>,
+    ) -> BenchBatch {
         let mut items = vec![];
+        let state = Arc::new(BatchState::default());
 
         let rate_per_second = current / self.concurrency as f64;
 
         for i in 0..self.concurrency {
             let idx = i + self.current_iteration * self.concurrency;
-            items.push(if let Some(requests) = self.rate_ladder.step_requests {
-                BenchRun::from_request_limit(
-                    idx,
-                    requests,
-                    RateLimiter::build_rate_limiter(rate_per_second),
-                    self.request_timeout,
-                )
-            } else if let Some(duration) = self.rate_ladder.step_duration {
-                BenchRun::from_duration_limit(
-                    idx,
-                    duration,
-                    RateLimiter::build_rate_limiter(rate_per_second),
-                    self.request_timeout,
-                )
-            } else {
-                unreachable!();
-            });
+            let rate_limiter =
+                RateLimiter::build_rate_limiter(rate_per_second, self.arrival_process.clone());
+            items.push(
+                if let Some(requests) = step_requests {
+                    BenchRun::from_request_limit(
+                        idx,
+                        requests,
+                        rate_limiter,
+                        self.request_timeout,
+                        self.open_model,
+                        self.max_timeout_rate,
+                    )
+                } else if let Some(duration) = step_duration {
+                    BenchRun::from_duration_limit(
+                        idx,
+                        duration,
+                        rate_limiter,
+                        self.request_timeout,
+                        self.open_model,
+                        self.max_timeout_rate,
+                    )
+                } else {
+                    unreachable!();
+                }
+                .in_batch(state.clone()),
+            );
         }
 
-        self.rate_ladder.increment_rate();
         self.current_iteration += 1;
 
-        Some(BenchBatch {
+        BenchBatch {
             runs: items,
             mode: self.mode.clone(),
-        })
+            circuit_breaker: self.circuit_breaker.clone(),
+            state,
+        }
     }
 }
 
@@ -97,14 +159,38 @@ impl BenchBatch {
     pub async fn run(self, mut metrics: BenchRunMetrics) -> Result<BenchRunMetrics, String> {
         let (metrics_sender, mut metrics_receiver) = mpsc::channel(1_000);
 
-        // Reset the global stop flag so a fatal error from a previous batch
-        // doesn't prevent this batch from executing.
-        BenchRun::reset_stop_flag();
-
-        // single consumer to aggregate metrics
+        // single consumer to aggregate metrics (and to check the circuit breaker)
+        let circuit_breaker = self.circuit_breaker.clone();
+        let state = self.state.clone();
         let metrics_aggregator = tokio::spawn(async move {
-            while let Some(request_stats) = metrics_receiver.recv().await {
-                metrics.report_request(request_stats);
+            let mut window = circuit_breaker
+                .as_ref()
+                .map(|circuit_breaker| circuit_breaker.start_window(Instant::now()));
+            // not polled without a circuit breaker
+            let mut checks = tokio::time::interval(
+                circuit_breaker
+                    .as_ref()
+                    .map_or(Duration::MAX, CircuitBreaker::check_interval),
+            );
+            loop {
+                tokio::select! {
+                    request_stats = metrics_receiver.recv() => match request_stats {
+                        Some(request_stats) => {
+                            if let Some(window) = window.as_mut() {
+                                window.record(&request_stats, Instant::now());
+                            }
+                            metrics.report_request(request_stats);
+                        }
+                        None => break,
+                    },
+                    _ = checks.tick(), if window.is_some() && metrics.aborted.is_none() => {
+                        if let Some(abort) = window.as_mut().and_then(|window| window.check(Instant::now())) {
+                            error!("Aborting the batch: {}", abort.reason);
+                            state.stop();
+                            metrics.aborted = Some(abort);
+                        }
+                    }
+                }
             }
             metrics
         });
@@ -134,6 +220,9 @@ impl BenchBatch {
                     BenchmarkMode::Http3(adapter) => {
                         bench_run.send_load(adapter, metrics_channel).await
                     }
+                    BenchmarkMode::Custom(adapter) => {
+                        bench_run.send_load(adapter, metrics_channel).await
+                    }
                 }
             }));
         }
@@ -201,7 +290,13 @@ impl fmt::Display for BenchBatch {
 
 #[cfg(test)]
 mod tests {
-    use crate::bench_session::RateLadderBuilder;
+    use crate::bench_session::{BenchSessionBuilder, RateLadderBuilder};
+    use crate::configuration::BenchmarkMode;
+    use crate::http_bench_session::{
+        HttpBenchAdapterBuilder, HttpClientConfigBuilder, HttpRequestBuilder,
+    };
+    use crate::load_profile::LoadProfile;
+    use std::sync::Arc;
 
     #[test]
     fn test_rate_ladder_with_increment() {
@@ -301,4 +396,55 @@ mod tests {
             assert_eq!(i + 1 == max_iterations, rate_ladder.complete);
         }
     }
+
+    #[test]
+    fn test_session_with_load_profile() {
+        let load_profile = LoadProfile::from_yaml(
+            r#"
+step: 1m
+stages:
+  - type: ramp
+    from: 10
+    to: 100
+    duration: 3m
+  - type: spike
+    rate: 500
+    duration: 30s
+"#,
+        )
+        .expect("Valid profile");
+
+        let http_adapter = HttpBenchAdapterBuilder::default()
+            .request(
+                HttpRequestBuilder::default()
+                    .url(vec!["http://localhost/1".to_string()])
+                    .build()
+                    .unwrap(),
+            )
+            .config(HttpClientConfigBuilder::default().build().unwrap())
+            .build()
+            .unwrap();
+
+        let session = BenchSessionBuilder::default()
+            .concurrency(2)
+            .rate_ladder(
+                RateLadderBuilder::default()
+                    .start(0.)
+                    .end(0.)
+                    .rate_increment(None)
+                    .step_duration(None)
+                    .step_requests(None)
+                    .build()
+                    .expect("RateLadderBuilder failed"),
+            )
+            .mode(Arc::new(BenchmarkMode::Http(http_adapter)))
+            .request_timeout(None)
+            .load_profile(Some(load_profile))
+            .build()
+            .expect("BenchSessionBuilder failed");
+
+        let batches: Vec<_> = session.collect();
+        assert_eq!(4, batches.len());
+        assert!(batches.iter().all(|b| b.runs.len() == 2));
+    }
 }
@@ -0,0 +1,261 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::metrics::RequestStats;
+use derive_builder::Builder;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Error rate and p99 latency are not checked on fewer requests in the window, as they are too noisy.
+const MIN_WINDOW_REQUESTS: usize = 10;
+
+/// The rules are checked several times per window, but not more often than needed for long windows.
+const CHECKS_PER_WINDOW: u32 = 10;
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Rules aborting a batch (or the whole session), checked on the requests completed within the last `window`.
+/// Unlike `stop_on_errors`, a single request doesn't trip them.
+#[derive(Builder, Clone, Debug)]
+pub struct CircuitBreaker {
+    #[builder(default = "Duration::from_secs(10)")]
+    window: Duration,
+    /// In percents, e.g. `10.0` for 10%.
+    #[builder(default)]
+    max_error_rate: Option<f64>,
+    #[builder(default)]
+    max_p99: Option<Duration>,
+    /// Completed requests per second.
+    #[builder(default)]
+    min_rate: Option<f64>,
+    /// Stop the whole session, not only the current batch.
+    #[builder(default)]
+    stop_session: bool,
+}
+
+/// Why a batch was aborted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Abort {
+    pub reason: String,
+    pub stop_session: bool,
+}
+
+/// Requests of a batch completed within the window of the [`CircuitBreaker`].
+pub struct RequestWindow<'a> {
+    breaker: &'a CircuitBreaker,
+    started: Instant,
+    /// Completion time, success and latency.
+    requests: VecDeque<(Instant, bool, Duration)>,
+}
+
+impl CircuitBreaker {
+    /// The window of a batch started `now`.
+    pub fn start_window(&self, now: Instant) -> RequestWindow<'_> {
+        RequestWindow {
+            breaker: self,
+            started: now,
+            requests: VecDeque::new(),
+        }
+    }
+
+    /// How often to check the rules, a fraction of the window.
+    pub fn check_interval(&self) -> Duration {
+        (self.window / CHECKS_PER_WINDOW).clamp(MIN_CHECK_INTERVAL, MAX_CHECK_INTERVAL)
+    }
+}
+
+impl RequestWindow<'_> {
+    pub fn record(&mut self, stats: &RequestStats, now: Instant) {
+        self.requests
+            .push_back((now, stats.is_success, stats.duration));
+    }
+
+    /// Returns the reason to abort, if any rule is tripped. Until the batch has run for the whole window,
+    /// the rules are checked on its elapsed part, once it holds enough requests (so steps shorter than the window
+    /// are checked too). A throughput too low to complete enough requests trips `min_rate` after the whole window.
+    pub fn check(&mut self, now: Instant) -> Option<Abort> {
+        let window = self.breaker.window;
+        while self
+            .requests
+            .front()
+            .is_some_and(|(completed, _, _)| now.duration_since(*completed) > window)
+        {
+            self.requests.pop_front();
+        }
+        // reported in whole milliseconds
+        let elapsed =
+            Duration::from_millis(now.duration_since(self.started).as_millis() as u64).min(window);
+        if elapsed < window && self.requests.len() < MIN_WINDOW_REQUESTS {
+            return None;
+        }
+
+        self.tripped_rule(elapsed).map(|reason| Abort {
+            reason: format!("{reason} over the last {elapsed:?}"),
+            stop_session: self.breaker.stop_session,
+        })
+    }
+
+    fn tripped_rule(&self, elapsed: Duration) -> Option<String> {
+        let total = self.requests.len();
+
+        if let Some(min_rate) = self.breaker.min_rate {
+            let rate = total as f64 / elapsed.as_secs_f64();
+            if rate < min_rate {
+                return Some(format!(
+                    "Throughput {rate:.3} rps is below {min_rate:.3} rps"
+                ));
+            }
+        }
+
+        if total < MIN_WINDOW_REQUESTS {
+            return None;
+        }
+
+        if let Some(max_error_rate) = self.breaker.max_error_rate {
+            let failed = self.requests.iter().filter(|(_, ok, _)| !ok).count();
+            let error_rate = failed as f64 * 100. / total as f64;
+            if error_rate > max_error_rate {
+                return Some(format!(
+                    "Error rate {error_rate:.3}% is above {max_error_rate:.3}%"
+                ));
+            }
+        }
+
+        if let Some(max_p99) = self.breaker.max_p99 {
+            let mut latency: Vec<Duration> = self.requests.iter().map(|(_, _, d)| *d).collect();
+            let index = (total * 99).div_ceil(100) - 1;
+            let (_, p99, _) = latency.select_nth_unstable(index);
+            if *p99 > max_p99 {
+                return Some(format!("p99 latency {p99:?} is above {max_p99:?}"));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit_breaker::CircuitBreakerBuilder;
+    use crate::metrics::{RequestStats, RequestStatsBuilder};
+    use std::time::{Duration, Instant};
+
+    fn stats(is_success: bool, duration: Duration) -> RequestStats {
+        RequestStatsBuilder::default()
+            .bytes_processed(0)
+            .status("200 OK".to_string())
+            .is_success(is_success)
+            .duration(duration)
+            .fatal_error(false)
+            .build()
+            .expect("RequestStatsBuilder failed")
+    }
+
+    #[test]
+    fn test_error_rate() {
+        let breaker = CircuitBreakerBuilder::default()
+            .window(Duration::from_secs(1))
+            .max_error_rate(Some(10.))
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        let mut window = breaker.start_window(start);
+        for i in 0..100 {
+            window.record(&stats(i % 10 != 0, Duration::ZERO), start);
+        }
+        assert_eq!(None, window.check(start + Duration::from_secs(1)));
+
+        window.record(&stats(false, Duration::ZERO), start);
+        let abort = window.check(start + Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            "Error rate 10.891% is above 10.000% over the last 1s",
+            abort.reason
+        );
+        assert!(!abort.stop_session);
+
+        // the failures slid out of the window
+        assert_eq!(None, window.check(start + Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn test_elapsed_part_of_window() {
+        let breaker = CircuitBreakerBuilder::default()
+            .window(Duration::from_secs(10))
+            .max_error_rate(Some(10.))
+            .build()
+            .unwrap();
+        assert_eq!(Duration::from_secs(1), breaker.check_interval());
+        let start = Instant::now();
+        let mut window = breaker.start_window(start);
+        let now = start + Duration::from_millis(500);
+        for _ in 0..9 {
+            window.record(&stats(false, Duration::ZERO), now);
+        }
+        // too few requests to tell yet
+        assert_eq!(None, window.check(now));
+
+        window.record(&stats(false, Duration::ZERO), now);
+        assert_eq!(
+            "Error rate 100.000% is above 10.000% over the last 500ms",
+            window.check(now).unwrap().reason
+        );
+
+        // a stalled batch trips the throughput rule once the whole window has passed
+        let breaker = CircuitBreakerBuilder::default()
+            .window(Duration::from_secs(10))
+            .min_rate(Some(50.))
+            .build()
+            .unwrap();
+        let mut window = breaker.start_window(start);
+        assert_eq!(None, window.check(start + Duration::from_secs(5)));
+        assert_eq!(
+            "Throughput 0.000 rps is below 50.000 rps over the last 10s",
+            window
+                .check(start + Duration::from_secs(10))
+                .unwrap()
+                .reason
+        );
+
+        let short = CircuitBreakerBuilder::default()
+            .window(Duration::from_millis(500))
+            .build()
+            .unwrap();
+        assert_eq!(Duration::from_millis(50), short.check_interval());
+    }
+
+    #[test]
+    fn test_p99_and_rate() {
+        let breaker = CircuitBreakerBuilder::default()
+            .window(Duration::from_secs(1))
+            .max_p99(Some(Duration::from_millis(50)))
+            .min_rate(Some(50.))
+            .stop_session(true)
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        let end = start + Duration::from_secs(1);
+        let mut window = breaker.start_window(start);
+        for i in 0..100 {
+            window.record(&stats(true, Duration::from_millis(i)), end);
+        }
+        let abort = window.check(end).unwrap();
+        assert_eq!(
+            "p99 latency 98ms is above 50ms over the last 1s",
+            abort.reason
+        );
+        assert!(abort.stop_session);
+
+        let mut window = breaker.start_window(start);
+        for _ in 0..20 {
+            window.record(&stats(true, Duration::ZERO), end);
+        }
+        assert_eq!(
+            "Throughput 20.000 rps is below 50.000 rps over the last 1s",
+            window.check(end).unwrap().reason
+        );
+    }
+}
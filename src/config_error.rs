@@ -0,0 +1,87 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use core::fmt;
+
+/// A single problem found while validating the configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The value of `flag` cannot be used.
+    InvalidValue {
+        flag: &'static str,
+        value: String,
+        reason: String,
+    },
+    /// `flag` must be specified, e.g. because another flag depends on it.
+    MissingValue { flag: &'static str, reason: String },
+    /// `flag` cannot be combined with `other`.
+    Conflict {
+        flag: &'static str,
+        other: &'static str,
+    },
+    /// `flag` is not supported by this build.
+    Unsupported { flag: &'static str, reason: String },
+    /// The config file cannot be read or parsed.
+    ConfigFile { filename: String, reason: String },
+    /// The subcommand (or config file section) is not a known protocol.
+    UnknownProtocol { name: String, known: Vec<String> },
+}
+
+/// All problems found in the configuration, so they can be fixed at once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidValue {
+                flag,
+                value,
+                reason,
+            } => write!(f, "Invalid value `{value}` of --{flag}: {reason}"),
+            ConfigError::MissingValue { flag, reason } => {
+                write!(f, "Missing --{flag}: {reason}")
+            }
+            ConfigError::Conflict { flag, other } => {
+                write!(f, "--{flag} cannot be used with --{other}")
+            }
+            ConfigError::Unsupported { flag, reason } => {
+                write!(f, "--{flag} is not supported: {reason}")
+            }
+            ConfigError::ConfigFile { filename, reason } => {
+                write!(f, "Invalid config file {filename}: {reason}")
+            }
+            ConfigError::UnknownProtocol { name, known } => {
+                write!(
+                    f,
+                    "Unknown protocol `{name}`, expected one of: {}",
+                    known.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for error in self.0.iter() {
+            write!(f, "\n  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ConfigError> for ConfigErrors {
+    fn from(error: ConfigError) -> Self {
+        ConfigErrors(vec![error])
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl std::error::Error for ConfigErrors {}
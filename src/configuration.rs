@@ -1,6 +1,7 @@
+use crate::assertion::{Assertion, Assertions};
+use crate::bench_run::{BenchmarkProtocolAdapter, DynProtocolAdapter};
 use crate::bench_session::{BenchSession, BenchSessionBuilder, RateLadder, RateLadderBuilder};
-#[cfg(feature = "http3")]
-use crate::h3_bench_session::{H3BenchAdapter, H3BenchAdapterBuilder};
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerBuilder};
 /// Copyright 2020 Developers of the perf-gauge project.
 ///
 /// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
@@ -8,29 +9,47 @@ use crate::h3_bench_session::{H3BenchAdapter, H3BenchAdapterBuilder};
 /// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 /// option. This file may not be copied, modified, or distributed
 /// except according to those terms.
+use crate::config_error::{ConfigError, ConfigErrors};
+use crate::feeder::{Feeder, FeederOrder, Row};
+#[cfg(feature = "http3")]
+use crate::h3_bench_session::{H3BenchAdapter, H3BenchAdapterBuilder};
 use crate::http_bench_session::{
     HttpBenchAdapter, HttpBenchAdapterBuilder, HttpClientConfigBuilder, HttpRequestBuilder,
+    HttpTarget,
 };
+use crate::load_profile::LoadProfile;
 use crate::metrics::{DefaultConsoleReporter, ExternalMetricsServiceReporter};
+use crate::protocol_registry::ProtocolRegistry;
+use crate::rate_limiter::ArrivalProcess;
+use crate::selection::Selection;
+use crate::template::Template;
+use crate::throughput_search::{SloCriteriaBuilder, ThroughputSearch, ThroughputSearchBuilder};
 use bytes::Bytes;
-use clap::{Args, Parser, Subcommand};
+use bytesize::ByteSize;
+use clap::parser::ValueSource;
+use clap::{
+    ArgMatches, Args, Command, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
 use core::fmt;
 use derive_builder::Builder;
-use hyper::Method;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Method, Uri};
 use rand::Rng;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs;
-use std::fs::File;
-use std::io::Read;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
-use tokio::io;
 
 #[derive(Clone)]
 pub enum BenchmarkMode {
     Http(HttpBenchAdapter),
     #[cfg(feature = "http3")]
     Http3(H3BenchAdapter),
+    /// Any other protocol, e.g. registered in [`ProtocolRegistry`].
+    Custom(Arc<dyn DynProtocolAdapter>),
 }
 
 #[derive(Clone, Builder)]
@@ -47,17 +66,33 @@ pub struct BenchmarkConfig {
     pub concurrency: usize,
     pub rate_ladder: RateLadder,
     pub mode: BenchmarkMode,
+    #[builder(default)]
     request_timeout: Option<Duration>,
     #[builder(default)]
+    pub max_timeout_rate: Option<f64>,
+    #[builder(default)]
+    pub circuit_breaker: Option<CircuitBreaker>,
+    #[builder(default)]
+    pub open_model: bool,
+    #[builder(default)]
+    pub arrival_process: ArrivalProcess,
+    #[builder(default)]
+    pub load_profile: Option<LoadProfile>,
+    #[builder(default)]
+    pub throughput_search: Option<ThroughputSearch>,
+    #[builder(default)]
     pub reporters: Vec<Arc<dyn ExternalMetricsServiceReporter + Send + Sync + 'static>>,
 }
 
-#[derive(Parser, Debug)]
+/// Command line options. A config file for `perf-gauge run --config` has the same structure,
+/// with long option names as keys and the mode (e.g. `http`) as a nested section.
+#[derive(Parser, Deserialize, Debug)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct Cli {
     /// Concurrent clients. Default `1`.
     #[arg(short, long, default_value_t = 1)]
+    #[serde(default = "default_one")]
     concurrency: usize,
     /// Duration of the test.
     #[arg(short, long)]
@@ -77,15 +112,71 @@ struct Cli {
     /// Max rate per second. Requires --rate-step
     #[arg(long = "rate_max")]
     rate_max: Option<f64>,
+    /// Load profile file (YAML or JSON) with a sequence of stages (ramp, hold, spike, ramp_down, sine).
+    /// Replaces --rate, --rate_step, --rate_max, --max_iter, --duration and --num_req.
+    #[arg(long = "load_profile", conflicts_with_all = ["rate", "rate_step", "rate_max", "duration", "num_req"])]
+    load_profile: Option<String>,
     /// takes_value "The number of iterations with the max rate. By default `1`.
     #[arg(short, long = "max_iter", default_value_t = 1)]
+    #[serde(default = "default_one")]
     max_iter: usize,
     /// If it's a part of a continuous run. In this case metrics are not reset at the end to avoid saw-like plots.
     #[arg(long)]
+    #[serde(default)]
     continuous: bool,
-    /// Timeout of a single request. E.g. "--request_timeout 30s". Timeouts are treated as fatal errors.
+    /// Timeout of a single request. E.g. "--request_timeout 30s". Timeouts are treated as fatal errors, unless --max_timeout_rate is set.
     #[arg(long = "request_timeout")]
     request_timeout: Option<String>,
+    /// Record requests timed out after --request_timeout with the `timeout` status and continue, instead of stopping at the first one.
+    /// The batch is aborted once more than this percentage of its requests time out (after at least 100 requests),
+    /// e.g. `5`, or `100` to never abort.
+    #[arg(long = "max_timeout_rate", requires = "request_timeout")]
+    max_timeout_rate: Option<f64>,
+    /// Abort the batch once the error rate (in percents) over --abort_window is above this, e.g. `10`.
+    #[arg(long = "abort_error_rate")]
+    abort_error_rate: Option<f64>,
+    /// Abort the batch once the p99 latency over --abort_window is above this, e.g. `500ms`.
+    #[arg(long = "abort_p99")]
+    abort_p99: Option<String>,
+    /// Abort the batch once fewer requests per second complete over --abort_window, e.g. `100`.
+    #[arg(long = "abort_min_rate")]
+    abort_min_rate: Option<f64>,
+    /// The sliding window to check the abort conditions on, e.g. `1s`. Default `10s`.
+    /// Until the batch has run for that long, they are checked on its elapsed part, once it holds enough requests.
+    #[arg(long = "abort_window")]
+    abort_window: Option<String>,
+    /// Stop the whole session (e.g. the rest of the rate ladder) instead of the batch, once an abort condition is met.
+    #[arg(long = "abort_session")]
+    #[serde(default)]
+    abort_session: bool,
+    /// Send requests on a fixed schedule, regardless of whether previous ones completed (open model).
+    /// Latency is additionally reported from the intended send time, to correct coordinated omission. Requires --rate.
+    /// Not supported by the `scenario`, `tcp`, `ws`, `redis` and `dns` modes, which send one request at a time per client.
+    #[arg(long = "open_model")]
+    #[serde(default)]
+    open_model: bool,
+    /// Distribution of gaps between requests (the average rate is preserved):
+    /// `constant` (default), `poisson`, `uniform[:JITTER]` (e.g. `uniform:0.5` for ±50%) or `bursty:ON:OFF` (e.g. `bursty:2s:1s`).
+    #[arg(long)]
+    arrival: Option<String>,
+    /// Search for the highest rate meeting the SLO (--slo_p99 and/or --slo_error_rate), starting from --rate.
+    /// The rate is doubled until the SLO is violated, then the range is bisected.
+    #[arg(long, requires = "rate", conflicts_with_all = ["rate_step", "rate_max", "load_profile"])]
+    #[serde(default)]
+    search: bool,
+    /// Max p99 latency for --search, e.g. `50ms`.
+    #[arg(long = "slo_p99")]
+    slo_p99: Option<String>,
+    /// Max error rate (in percents) for --search, e.g. `0.1`.
+    #[arg(long = "slo_error_rate")]
+    slo_error_rate: Option<f64>,
+    /// Upper bound of the rate for --search.
+    #[arg(long = "search_max")]
+    search_max: Option<f64>,
+    /// Stop --search once the range is narrower than this (in percents of the rate). Default `5`.
+    #[arg(long = "search_precision", default_value_t = 5.0)]
+    #[serde(default = "default_search_precision")]
+    search_precision: f64,
     /// If you'd like to send metrics to Prometheus PushGateway, specify the server URL. E.g. 10.0.0.1:9091
     #[arg(long)]
     prometheus: Option<String>,
@@ -93,79 +184,599 @@ struct Cli {
     #[arg(long = "prometheus_job")]
     prometheus_job: Option<String>,
     #[command(subcommand)]
+    #[serde(flatten)]
     command: Commands,
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Subcommand, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
 enum Commands {
-    Http(HttpOptions),
+    Http(Box<HttpOptions>),
+    #[serde(skip)]
+    Run(RunOptions),
+    /// A protocol from the [`ProtocolRegistry`]: its name followed by its options.
+    #[command(external_subcommand)]
+    #[serde(skip)]
+    Registered(Vec<String>),
+    /// Config file sections not recognized as general options, one of them is a registered protocol.
+    #[command(skip)]
+    #[serde(untagged, deserialize_with = "deserialize_sections")]
+    RegisteredSection(BTreeMap<String, serde_json::Value>),
 }
 
 #[derive(Args, Debug)]
+#[command(about = "Run a benchmark defined in a config file", long_about = None)]
+struct RunOptions {
+    /// YAML (or TOML, by the `.toml` extension) file with the benchmark definition.
+    /// `${VAR}` and `${VAR:-default}` in values are substituted with environment variables.
+    /// Other options cannot be given on the command line.
+    #[arg(long)]
+    config: String,
+}
+
+#[derive(Args, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 #[command(about = "Run in HTTP(S) mode", long_about = None)]
 struct HttpOptions {
     /// Target, e.g. https://my-service.com:8443/8kb Can be multiple ones (with random choice balancing).
-    #[arg()]
-    target: Vec<String>,
+    /// Named targets, e.g. `8kb=https://my-service.com:8443/8kb`, are also reported separately.
+    #[arg(value_parser = TargetEntry::from_arg)]
+    #[serde(default)]
+    target: Vec<TargetEntry>,
     /// Headers in "Name:Value1" form. E.g. `-H "Authentication:Bearer token" -H "Date:2022-03-17"`
     /// It can contain multiple values, e.g. "Name:Value1:Value2:Value3". In this case a random one is chosen for each request.
-    #[arg(short = 'H', long)]
-    header: Vec<String>,
+    #[arg(short = 'H', long, value_parser = HeaderEntry::from_arg)]
+    #[serde(default)]
+    header: Vec<HeaderEntry>,
     /// Method. By default GET.
     #[arg(short = 'M', long)]
     method: Option<String>,
     /// Stop immediately on error codes. E.g. `-E 401 -E 403`
     #[arg(short = 'E', long = "error_stop")]
+    #[serde(default)]
     error_stop: Vec<u16>,
     /// Body of the request. Could be either `random://[0-9]+`, `file://$filename` or `base64://${valid_base64}`. Optional.
     #[arg(short = 'B', long)]
     body: Option<String>,
     /// Allow self signed certificates.
     #[arg(long = "ignore_cert")]
+    #[serde(default)]
     ignore_cert: bool,
     /// If connections should be re-used.
     #[arg(long = "conn_reuse")]
+    #[serde(default)]
     conn_reuse: bool,
     /// Enforce HTTP/2 only.
     #[arg(long = "http2_only")]
+    #[serde(default)]
     http2_only: bool,
     /// Use HTTP/3 (QUIC). Requires https:// URLs.
     #[arg(long = "http3")]
+    #[serde(default)]
     http3: bool,
+    /// How targets and header values are chosen for each request, by their weights (1 by default).
+    #[arg(long, value_enum, default_value = "random")]
+    #[serde(default)]
+    selection: SelectionMode,
+    /// Seed of the random selection, to make runs reproducible.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// CSV (with a header row) or JSONL (`.jsonl`, `.ndjson`) file with values of `{{column}}` placeholders,
+    /// a row for each request.
+    #[arg(long)]
+    feeder: Option<String>,
+    /// How the feeder rows are iterated. Partitioned gives each client its own share of them.
+    #[arg(long = "feeder_order", value_enum, default_value = "sequential")]
+    #[serde(default)]
+    feeder_order: FeederOrder,
+    /// Stop once each feeder row is used, instead of starting over.
+    #[arg(long = "feeder_stop")]
+    #[serde(default)]
+    feeder_stop: bool,
+    #[command(flatten)]
+    #[serde(default)]
+    assert: AssertOptions,
+}
+
+/// Success criteria of responses, besides a `2xx` status.
+#[derive(Args, Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct AssertOptions {
+    /// Expected status codes instead of any `2xx`. E.g. `--assert_status 200 --assert_status 404`
+    #[arg(id = "assert_status", long = "assert_status")]
+    #[serde(default)]
+    status: Vec<u16>,
+    /// Regular expression the body must match.
+    #[arg(id = "assert_body", long = "assert_body")]
+    body: Option<String>,
+    /// Expected value at a JSON pointer of the body, e.g. `/status=ok` or `/items/0/id=42`.
+    #[arg(id = "assert_json", long = "assert_json")]
+    #[serde(default)]
+    json: Vec<String>,
+    /// Header the response must have.
+    #[arg(id = "assert_header", long = "assert_header")]
+    #[serde(default)]
+    header: Vec<String>,
+    /// Range of the body size in bytes, e.g. `1024..8kb` or `..1mb`.
+    #[arg(id = "assert_size", long = "assert_size")]
+    size: Option<String>,
+    /// SHA-1 of the body, in hex.
+    #[arg(id = "assert_checksum", long = "assert_checksum")]
+    checksum: Option<String>,
+    /// Stop immediately if an assertion fails.
+    #[arg(id = "assert_fatal", long = "assert_fatal")]
+    #[serde(default)]
+    fatal: bool,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+enum SelectionMode {
+    #[default]
+    Random,
+    RoundRobin,
+}
+
+/// A target URL (`[name=]url[#weight]`), or a config file entry that can also override
+/// the method, headers and body of the requests to it.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum TargetEntry {
+    Url(String),
+    Detailed(TargetSection),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+struct TargetSection {
+    name: Option<String>,
+    url: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    method: Option<String>,
+    /// Headers in "Name:Value" form (a single value, which may contain `:`),
+    /// replacing the common ones with the same name.
+    #[serde(default)]
+    header: Vec<String>,
+    body: Option<String>,
+    /// Replaces the common assertions.
+    assert: Option<Box<AssertOptions>>,
+}
+
+impl TargetEntry {
+    fn from_arg(arg: &str) -> Result<TargetEntry, String> {
+        Ok(TargetEntry::Url(arg.to_string()))
+    }
+}
+
+/// A header in "Name:Value1:Value2" form, or a config file entry with weighted values.
+/// Weights are given only in the latter, as any suffix of a value could be a part of it.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum HeaderEntry {
+    Values(String),
+    Weighted(HeaderSection),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+struct HeaderSection {
+    name: String,
+    values: Vec<WeightedValue>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+struct WeightedValue {
+    value: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+}
+
+impl HeaderEntry {
+    fn from_arg(arg: &str) -> Result<HeaderEntry, String> {
+        Ok(HeaderEntry::Values(arg.to_string()))
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// A header name with alternative values.
+type Header = (String, Vec<String>);
+
+fn deserialize_sections<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, serde_json::Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let sections = BTreeMap::deserialize(deserializer)?;
+    if sections.is_empty() {
+        return Err(serde::de::Error::custom("a protocol section is required"));
+    }
+    Ok(sections)
+}
+
+fn default_one() -> usize {
+    1
+}
+
+fn default_search_precision() -> f64 {
+    5.0
+}
+
+impl Cli {
+    fn from_file(filename: &str, registry: &ProtocolRegistry) -> Result<Cli, ConfigError> {
+        let config_file_error = |reason: String| ConfigError::ConfigFile {
+            filename: filename.to_string(),
+            reason,
+        };
+        let content = fs::read_to_string(filename).map_err(|e| config_file_error(e.to_string()))?;
+        let value = if filename.ends_with(".toml") {
+            toml::from_str::<toml::Value>(&content)
+                .map_err(|e| e.to_string())
+                .and_then(|value| serde_yaml::to_value(value).map_err(|e| e.to_string()))
+        } else {
+            serde_yaml::from_str::<serde_yaml::Value>(&content).map_err(|e| e.to_string())
+        }
+        .map_err(config_file_error)?;
+        let value = Cli::interpolate_values(value, &|name| std::env::var(name).ok())
+            .map_err(config_file_error)?;
+        Cli::check_keys(&value, registry).map_err(config_file_error)?;
+        if let Some(http) = value.get("http") {
+            // otherwise it's reported as not matching any mode
+            HttpOptions::deserialize(http).map_err(|e| config_file_error(format!("http: {e}")))?;
+        }
+        serde_yaml::from_value(value).map_err(|e| config_file_error(e.to_string()))
+    }
+
+    /// Options given on the command line along with `run`, which would be silently ignored.
+    fn given_with_run(matches: &ArgMatches) -> Vec<ConfigError> {
+        static COMMAND: OnceLock<Command> = OnceLock::new();
+        COMMAND
+            .get_or_init(Cli::command)
+            .get_arguments()
+            .filter(|arg| {
+                matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            })
+            .filter_map(|arg| arg.get_long())
+            .map(|flag| ConfigError::Conflict {
+                flag,
+                other: "config",
+            })
+            .collect()
+    }
+
+    /// Rejects top-level keys which are neither options nor protocol sections, e.g. misspelled ones.
+    fn check_keys(value: &serde_yaml::Value, registry: &ProtocolRegistry) -> Result<(), String> {
+        let Some(mapping) = value.as_mapping() else {
+            return Ok(());
+        };
+        let command = Cli::command();
+        let protocols = registry.names();
+        let unknown: Vec<String> = mapping
+            .keys()
+            .filter_map(|key| key.as_str())
+            .filter(|key| {
+                !command
+                    .get_arguments()
+                    .any(|arg| arg.get_long() == Some(*key))
+                    && !protocols.iter().any(|name| name == key)
+            })
+            .map(|key| format!("`{key}`"))
+            .collect();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("unknown keys {}", unknown.join(", ")))
+        }
+    }
+
+    /// Substitutes variables in string values (but not in keys or comments), see [`Cli::interpolate_env`].
+    fn interpolate_values(
+        value: serde_yaml::Value,
+        lookup: &impl Fn(&str) -> Option<String>,
+    ) -> Result<serde_yaml::Value, String> {
+        use serde_yaml::Value;
+        Ok(match value {
+            Value::String(s) => {
+                let interpolated = Cli::interpolate_env(&s, lookup)?;
+                if interpolated == s {
+                    Value::String(s)
+                } else {
+                    // e.g. `rate: ${RATE:-100}` is a number once substituted
+                    match serde_yaml::from_str(&interpolated) {
+                        Ok(scalar @ (Value::Number(_) | Value::Bool(_))) => scalar,
+                        _ => Value::String(interpolated),
+                    }
+                }
+            }
+            Value::Sequence(items) => Value::Sequence(
+                items
+                    .into_iter()
+                    .map(|item| Cli::interpolate_values(item, lookup))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Mapping(mapping) => Value::Mapping(
+                mapping
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, Cli::interpolate_values(value, lookup)?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            value => value,
+        })
+    }
+
+    /// Substitutes `${VAR}` and `${VAR:-default}` with values of environment variables.
+    fn interpolate_env(
+        content: &str,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<String, String> {
+        let mut result = String::with_capacity(content.len());
+        let mut rest = content;
+        while let Some(start) = rest.find("${") {
+            result.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("Unterminated variable: {}", &rest[start..]))?;
+            let expression = &rest[start + 2..start + end];
+            let (name, default) = match expression.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (expression, None),
+            };
+            match lookup(name).or_else(|| default.map(String::from)) {
+                Some(value) => result.push_str(&value),
+                None => return Err(format!("Environment variable `{name}` is not set")),
+            }
+            rest = &rest[start + end + 1..];
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+}
+
+/// Parses a humantime duration (e.g. `10s`, `1h 30m`) of `flag`.
+pub(crate) fn parse_duration(
+    flag: &'static str,
+    value: &Option<String>,
+    errors: &mut Vec<ConfigError>,
+) -> Option<Duration> {
+    let value = value.as_ref()?;
+    humantime::Duration::from_str(value.as_str())
+        .map(Into::into)
+        .map_err(|e| {
+            errors.push(ConfigError::InvalidValue {
+                flag,
+                value: value.clone(),
+                reason: e.to_string(),
+            })
+        })
+        .ok()
 }
 
 impl BenchmarkConfig {
-    pub fn from_command_line() -> io::Result<BenchmarkConfig> {
-        let cli = Cli::parse();
+    /// Parses the command line (or the config file of `run`). All problems are collected in
+    /// [`ConfigErrors`] rather than stopping at the first one.
+    pub fn from_command_line() -> Result<BenchmarkConfig, ConfigErrors> {
+        BenchmarkConfig::from_command_line_with(&ProtocolRegistry::default())
+    }
+
+    /// Same as [`BenchmarkConfig::from_command_line`], with additional protocols.
+    pub fn from_command_line_with(
+        registry: &ProtocolRegistry,
+    ) -> Result<BenchmarkConfig, ConfigErrors> {
+        let mut command = Cli::command();
+        if let Some(help) = registry.help() {
+            command = command.after_help(help);
+        }
+        BenchmarkConfig::from_matches(&command.get_matches(), registry)
+    }
+
+    fn from_matches(
+        matches: &ArgMatches,
+        registry: &ProtocolRegistry,
+    ) -> Result<BenchmarkConfig, ConfigErrors> {
+        let cli = Cli::from_arg_matches(matches).unwrap_or_else(|e| e.exit());
+        match &cli.command {
+            Commands::Run(run) => {
+                let conflicts = Cli::given_with_run(matches);
+                if !conflicts.is_empty() {
+                    return Err(ConfigErrors(conflicts));
+                }
+                BenchmarkConfig::from_cli(Cli::from_file(&run.config, registry)?, registry)
+            }
+            _ => BenchmarkConfig::from_cli(cli, registry),
+        }
+    }
+
+    fn from_cli(cli: Cli, registry: &ProtocolRegistry) -> Result<BenchmarkConfig, ConfigErrors> {
+        let mut errors = vec![];
 
         let concurrency = cli.concurrency;
+        if concurrency == 0 {
+            errors.push(ConfigError::InvalidValue {
+                flag: "concurrency",
+                value: concurrency.to_string(),
+                reason: "at least one client is required".to_string(),
+            });
+        }
         let rate_per_second = cli.rate;
         let rate_step = cli.rate_step;
         let rate_max = cli.rate_max;
         let max_rate_iterations = cli.max_iter;
 
-        let duration = cli.duration.as_ref().map(|d| {
-            humantime::Duration::from_str(d.as_str())
-                .expect("Illegal duration")
-                .into()
-        });
+        let duration = parse_duration("duration", &cli.duration, &mut errors);
+        let request_timeout = parse_duration("request_timeout", &cli.request_timeout, &mut errors);
+
+        let number_of_requests = cli.num_req;
+
+        // clap checks these for the command line, but not for config files
+        let conflicts = [
+            (
+                "load_profile",
+                cli.load_profile.is_some(),
+                "rate",
+                rate_per_second.is_some(),
+            ),
+            (
+                "load_profile",
+                cli.load_profile.is_some(),
+                "rate_step",
+                rate_step.is_some(),
+            ),
+            (
+                "load_profile",
+                cli.load_profile.is_some(),
+                "rate_max",
+                rate_max.is_some(),
+            ),
+            (
+                "load_profile",
+                cli.load_profile.is_some(),
+                "duration",
+                cli.duration.is_some(),
+            ),
+            (
+                "load_profile",
+                cli.load_profile.is_some(),
+                "num_req",
+                number_of_requests.is_some(),
+            ),
+            ("search", cli.search, "rate_step", rate_step.is_some()),
+            ("search", cli.search, "rate_max", rate_max.is_some()),
+            (
+                "search",
+                cli.search,
+                "load_profile",
+                cli.load_profile.is_some(),
+            ),
+        ];
+        for (flag, is_set, other, other_is_set) in conflicts {
+            if is_set && other_is_set {
+                errors.push(ConfigError::Conflict { flag, other });
+            }
+        }
+
+        let arrival_process = match &cli.arrival {
+            Some(arrival) => ArrivalProcess::from_str(arrival.as_str()).unwrap_or_else(|e| {
+                errors.push(ConfigError::InvalidValue {
+                    flag: "arrival",
+                    value: arrival.clone(),
+                    reason: e,
+                });
+                ArrivalProcess::Constant
+            }),
+            None => ArrivalProcess::Constant,
+        };
 
-        let request_timeout = cli.request_timeout.as_ref().map(|d| {
-            humantime::Duration::from_str(d.as_str())
-                .expect("Illegal duration")
-                .into()
+        if cli.open_model && cli.load_profile.is_none() && rate_per_second.unwrap_or(0.0) == 0.0 {
+            errors.push(ConfigError::MissingValue {
+                flag: "rate",
+                reason: "the request rate must be specified for --open_model".to_string(),
+            });
+        }
+
+        if let Some(max_timeout_rate) = cli.max_timeout_rate {
+            if !(0.0..=100.0).contains(&max_timeout_rate) {
+                errors.push(ConfigError::InvalidValue {
+                    flag: "max_timeout_rate",
+                    value: max_timeout_rate.to_string(),
+                    reason: "expected a percentage between 0 and 100".to_string(),
+                });
+            } else if request_timeout.is_none() {
+                errors.push(ConfigError::MissingValue {
+                    flag: "request_timeout",
+                    reason: "a timeout is required for --max_timeout_rate".to_string(),
+                });
+            }
+        }
+
+        let circuit_breaker = BenchmarkConfig::build_circuit_breaker(&cli, &mut errors);
+
+        let load_profile = cli.load_profile.as_ref().and_then(|filename| {
+            LoadProfile::from_file(filename)
+                .map_err(|e| {
+                    errors.push(ConfigError::InvalidValue {
+                        flag: "load_profile",
+                        value: filename.clone(),
+                        reason: e,
+                    })
+                })
+                .ok()
         });
 
-        let number_of_requests = cli.num_req;
+        let throughput_search = if cli.search {
+            let slo_p99 = parse_duration("slo_p99", &cli.slo_p99, &mut errors);
+            if cli.slo_p99.is_none() && cli.slo_error_rate.is_none() {
+                errors.push(ConfigError::MissingValue {
+                    flag: "slo_p99",
+                    reason: "--search requires --slo_p99 and/or --slo_error_rate".to_string(),
+                });
+            }
+            let start_rate = match rate_per_second {
+                Some(rate) if rate <= 0.0 => {
+                    // the search doubles the rate, which never grows from zero
+                    errors.push(ConfigError::InvalidValue {
+                        flag: "rate",
+                        value: rate.to_string(),
+                        reason: "the start rate of --search must be positive".to_string(),
+                    });
+                    rate
+                }
+                Some(rate) => rate,
+                None => {
+                    errors.push(ConfigError::MissingValue {
+                        flag: "rate",
+                        reason: "the start rate is required for --search".to_string(),
+                    });
+                    0.0
+                }
+            };
+            Some(
+                ThroughputSearchBuilder::default()
+                    .slo(
+                        SloCriteriaBuilder::default()
+                            .max_p99(slo_p99)
+                            .max_error_rate(cli.slo_error_rate)
+                            .build()
+                            .expect("SloCriteriaBuilder failed"),
+                    )
+                    .start_rate(start_rate)
+                    .max_rate(cli.search_max)
+                    .precision(cli.search_precision)
+                    .build()
+                    .expect("ThroughputSearchBuilder failed"),
+            )
+        } else {
+            None
+        };
 
-        if duration.is_none() && number_of_requests.is_none() {
-            panic!("Either the number of requests or the test duration must be specified");
+        if cli.duration.is_none() && number_of_requests.is_none() && cli.load_profile.is_none() {
+            errors.push(ConfigError::MissingValue {
+                flag: "duration",
+                reason: "either the number of requests or the test duration must be specified"
+                    .to_string(),
+            });
         }
 
         let rate_ladder = if let Some(rate_max) = rate_max {
-            let rate_per_second =
-                rate_per_second.expect("RATE is required if RATE_MAX is specified");
-            let rate_step = rate_step.expect("RATE_STEP is required if RATE_MAX is specified");
+            let rate_per_second = rate_per_second.unwrap_or_else(|| {
+                errors.push(ConfigError::MissingValue {
+                    flag: "rate",
+                    reason: "RATE is required if RATE_MAX is specified".to_string(),
+                });
+                0.0
+            });
+            let rate_step = rate_step.unwrap_or_else(|| {
+                errors.push(ConfigError::MissingValue {
+                    flag: "rate_step",
+                    reason: "RATE_STEP is required if RATE_MAX is specified".to_string(),
+                });
+                0.0
+            });
             RateLadderBuilder::default()
                 .start(rate_per_second)
                 .end(rate_max)
@@ -188,32 +799,88 @@ impl BenchmarkConfig {
                 .expect("RateLadderBuilder failed")
         };
 
-        Ok(BenchmarkConfigBuilder::default()
-            .name(cli.name.clone())
-            .rate_ladder(rate_ladder)
-            .concurrency(concurrency)
-            .verbose(false)
-            .continuous(cli.continuous)
-            .request_timeout(request_timeout)
-            .mode(BenchmarkConfig::build_mode(&cli))
-            .reporters(BenchmarkConfig::build_metric_destinations(
-                cli.name.clone(),
-                &cli,
-            ))
-            .build()
-            .expect("BenchmarkConfig failed"))
+        let mode = BenchmarkConfig::build_mode(&cli, registry, &mut errors);
+        if cli.open_model
+            && mode
+                .as_ref()
+                .is_some_and(|mode| !mode.supports_open_model())
+        {
+            errors.push(ConfigError::Unsupported {
+                flag: "open_model",
+                reason: "the protocol sends one request at a time per client".to_string(),
+            });
+        }
+        let reporters =
+            BenchmarkConfig::build_metric_destinations(cli.name.clone(), &cli, &mut errors);
+
+        match mode {
+            Some(mode) if errors.is_empty() => Ok(BenchmarkConfigBuilder::default()
+                .name(cli.name.clone())
+                .rate_ladder(rate_ladder)
+                .concurrency(concurrency)
+                .verbose(false)
+                .continuous(cli.continuous)
+                .request_timeout(request_timeout)
+                .max_timeout_rate(cli.max_timeout_rate)
+                .circuit_breaker(circuit_breaker)
+                .open_model(cli.open_model)
+                .arrival_process(arrival_process)
+                .load_profile(load_profile)
+                .throughput_search(throughput_search)
+                .mode(mode)
+                .reporters(reporters)
+                .build()
+                .expect("BenchmarkConfig failed")),
+            _ => Err(ConfigErrors(errors)),
+        }
+    }
+
+    fn build_circuit_breaker(cli: &Cli, errors: &mut Vec<ConfigError>) -> Option<CircuitBreaker> {
+        let max_p99 = parse_duration("abort_p99", &cli.abort_p99, errors);
+        let window = parse_duration("abort_window", &cli.abort_window, errors);
+        if let Some(rate) = cli.abort_error_rate.filter(|r| !(0.0..=100.0).contains(r)) {
+            errors.push(ConfigError::InvalidValue {
+                flag: "abort_error_rate",
+                value: rate.to_string(),
+                reason: "expected a percentage between 0 and 100".to_string(),
+            });
+        }
+        if cli.abort_error_rate.is_none() && cli.abort_p99.is_none() && cli.abort_min_rate.is_none()
+        {
+            if window.is_some() || cli.abort_session {
+                errors.push(ConfigError::MissingValue {
+                    flag: "abort_error_rate",
+                    reason: "--abort_window and --abort_session require --abort_error_rate, --abort_p99 and/or --abort_min_rate".to_string(),
+                });
+            }
+            return None;
+        }
+        let mut builder = CircuitBreakerBuilder::default();
+        if let Some(window) = window {
+            builder.window(window);
+        }
+        Some(
+            builder
+                .max_error_rate(cli.abort_error_rate)
+                .max_p99(max_p99)
+                .min_rate(cli.abort_min_rate)
+                .stop_session(cli.abort_session)
+                .build()
+                .expect("CircuitBreakerBuilder failed"),
+        )
     }
 
     #[cfg(not(feature = "report-to-prometheus"))]
     fn build_metric_destinations(
         test_case_name: Option<String>,
         args: &Cli,
+        errors: &mut Vec<ConfigError>,
     ) -> Vec<Arc<dyn ExternalMetricsServiceReporter + Send + Sync>> {
-        use std::process::exit;
-
         if args.prometheus.is_some() {
-            println!("Prometheus is not supported in this configuration");
-            exit(-1);
+            errors.push(ConfigError::Unsupported {
+                flag: "prometheus",
+                reason: "compile with --features report-to-prometheus".to_string(),
+            });
         }
 
         vec![Arc::new(DefaultConsoleReporter::new(test_case_name))]
@@ -223,6 +890,7 @@ impl BenchmarkConfig {
     fn build_metric_destinations(
         test_case_name: Option<String>,
         args: &Cli,
+        errors: &mut Vec<ConfigError>,
     ) -> Vec<Arc<dyn ExternalMetricsServiceReporter + Send + Sync>> {
         use crate::prometheus_reporter::PrometheusReporter;
         use std::net::SocketAddr;
@@ -234,8 +902,13 @@ impl BenchmarkConfig {
         ))];
 
         if let Some(prometheus_addr) = &args.prometheus {
-            if SocketAddr::from_str(prometheus_addr.as_str()).is_err() {
-                panic!("Illegal Prometheus Gateway addr `{prometheus_addr}`");
+            if let Err(e) = SocketAddr::from_str(prometheus_addr.as_str()) {
+                errors.push(ConfigError::InvalidValue {
+                    flag: "prometheus",
+                    value: prometheus_addr.clone(),
+                    reason: e.to_string(),
+                });
+                return metrics_destinations;
             }
             metrics_destinations.push(Arc::new(PrometheusReporter::new(
                 test_case_name,
@@ -253,58 +926,147 @@ impl BenchmarkConfig {
         metrics_destinations
     }
 
-    fn build_mode(args: &Cli) -> BenchmarkMode {
+    fn build_mode(
+        args: &Cli,
+        registry: &ProtocolRegistry,
+        errors: &mut Vec<ConfigError>,
+    ) -> Option<BenchmarkMode> {
         match &args.command {
             Commands::Http(config) => {
                 #[cfg(feature = "tls-boring")]
                 if config.ignore_cert {
-                    use std::process::exit;
+                    errors.push(ConfigError::Unsupported {
+                        flag: "ignore_cert",
+                        reason: "not available with BoringSSL".to_string(),
+                    });
+                }
 
-                    println!("--ignore_cert is not supported for BoringSSL");
-                    exit(-1);
+                if config.target.is_empty() {
+                    errors.push(ConfigError::MissingValue {
+                        flag: "target",
+                        reason: "at least one target URL is required".to_string(),
+                    });
                 }
 
+                if config.feeder_stop {
+                    if config.feeder.is_none() {
+                        errors.push(ConfigError::MissingValue {
+                            flag: "feeder",
+                            reason: "required by --feeder_stop".to_string(),
+                        });
+                    }
+                    if config.feeder_order == FeederOrder::Random {
+                        errors.push(ConfigError::Conflict {
+                            flag: "feeder_stop",
+                            other: "feeder_order",
+                        });
+                    }
+                }
+                let feeder = config.feeder.as_ref().and_then(|filename| {
+                    Feeder::from_file(
+                        filename,
+                        config.feeder_order,
+                        config.feeder_stop,
+                        args.concurrency,
+                    )
+                    .map(Arc::new)
+                    .map_err(|reason| {
+                        errors.push(ConfigError::InvalidValue {
+                            flag: "feeder",
+                            value: filename.clone(),
+                            reason,
+                        })
+                    })
+                    .ok()
+                });
+                // placeholders are checked with the first row
+                let row = feeder.as_ref().map(|feeder| feeder.first_row());
+
+                let assertions = BenchmarkConfig::parse_assertions(&config.assert, errors);
+                let targets: Vec<HttpTarget> = config
+                    .target
+                    .iter()
+                    .filter_map(|target| {
+                        BenchmarkConfig::parse_target(target, row, &assertions, errors)
+                    })
+                    .collect();
+
+                let method = config.method.as_ref().map_or("GET", |s| s.as_str());
+                let method = Method::from_bytes(method.as_bytes()).unwrap_or_else(|e| {
+                    errors.push(ConfigError::InvalidValue {
+                        flag: "method",
+                        value: method.to_string(),
+                        reason: e.to_string(),
+                    });
+                    Method::GET
+                });
+
+                let (headers, header_weights) = config
+                    .header
+                    .iter()
+                    .filter_map(|s| BenchmarkConfig::parse_weighted_header(s, row, errors))
+                    .unzip();
+
+                let selection = match (&config.selection, config.seed) {
+                    (SelectionMode::Random, None) => Selection::Random,
+                    (SelectionMode::Random, Some(seed)) => Selection::Seeded(seed),
+                    (SelectionMode::RoundRobin, None) => Selection::RoundRobin,
+                    (SelectionMode::RoundRobin, Some(_)) => {
+                        errors.push(ConfigError::Conflict {
+                            flag: "seed",
+                            other: "selection",
+                        });
+                        Selection::RoundRobin
+                    }
+                };
+
+                let has_targets = !targets.is_empty();
                 let mut request_builder = HttpRequestBuilder::default();
                 request_builder
-                    .url(config.target.clone())
-                    .method(
-                        Method::from_bytes(
-                            config
-                                .method
-                                .as_ref()
-                                .map_or("GET", |s| s.as_str())
-                                .as_bytes(),
-                        )
-                        .expect("Invalid HTTP method"),
-                    )
-                    .headers(
-                        config
-                            .header
-                            .iter()
-                            .map(|s| {
-                                let mut split = s.split(':');
-                                (
-                                    split.next().expect("Header name is missing").to_string(),
-                                    split.map(String::from).collect::<Vec<String>>(),
+                    .targets(targets)
+                    .method(method)
+                    .headers(headers)
+                    .header_weights(header_weights)
+                    .selection(selection)
+                    .feeder(feeder.clone())
+                    .body(
+                        BenchmarkConfig::generate_body("body", config.body.as_deref())
+                            .and_then(|body| {
+                                BenchmarkConfig::check_body_template(
+                                    config.body.as_deref().unwrap_or_default(),
+                                    body,
+                                    row,
                                 )
                             })
-                            .collect(),
-                    )
-                    .body(BenchmarkConfig::generate_body(config));
+                            .unwrap_or_else(|e| {
+                                errors.push(e);
+                                Bytes::new()
+                            }),
+                    );
+
+                if !has_targets {
+                    // reported above
+                    return None;
+                }
 
                 #[cfg(feature = "http3")]
                 if config.http3 {
                     if config.http2_only {
-                        eprintln!("Cannot use --http2_only with --http3");
-                        std::process::exit(1);
+                        errors.push(ConfigError::Conflict {
+                            flag: "http2_only",
+                            other: "http3",
+                        });
                     }
-                    return BenchmarkConfig::build_h3_mode(config, request_builder);
+                    return Some(BenchmarkConfig::build_h3_mode(config, request_builder));
                 }
 
                 #[cfg(not(feature = "http3"))]
                 if config.http3 {
-                    eprintln!("HTTP/3 is not enabled. Compile with --features http3");
-                    std::process::exit(1);
+                    errors.push(ConfigError::Unsupported {
+                        flag: "http3",
+                        reason: "compile with --features http3".to_string(),
+                    });
+                    return None;
                 }
 
                 let http_config = HttpBenchAdapterBuilder::default()
@@ -320,8 +1082,17 @@ impl BenchmarkConfig {
                     .request(request_builder.build().expect("HttpRequestBuilder failed"))
                     .build()
                     .expect("BenchmarkModeBuilder failed");
-                BenchmarkMode::Http(http_config)
+                Some(BenchmarkMode::Http(http_config))
             }
+            Commands::Registered(args) => registry
+                .build_from_args(args)
+                .map_err(|e| errors.extend(e.0))
+                .ok(),
+            Commands::RegisteredSection(sections) => registry
+                .build_from_sections(sections)
+                .map_err(|e| errors.extend(e.0))
+                .ok(),
+            Commands::Run(_) => unreachable!("Run options are resolved from the config file"),
         }
     }
 
@@ -342,45 +1113,344 @@ impl BenchmarkConfig {
         BenchmarkMode::Http3(h3_config)
     }
 
-    fn generate_body(args: &HttpOptions) -> Bytes {
+    /// Parses "Name:Value1:Value2" into the name and the values.
+    pub(crate) fn parse_header(header: &str, errors: &mut Vec<ConfigError>) -> Option<Header> {
+        let invalid_header = |reason: String| ConfigError::InvalidValue {
+            flag: "header",
+            value: header.to_string(),
+            reason,
+        };
+        let mut split = header.split(':');
+        let name = split.next().unwrap_or_default().to_string();
+        let values = split.map(String::from).collect::<Vec<String>>();
+
+        if let Err(e) = HeaderName::from_bytes(name.as_bytes()) {
+            errors.push(invalid_header(format!("invalid name: {e}")));
+            return None;
+        }
+        if values.is_empty() {
+            errors.push(invalid_header(
+                "expected the `Name:Value1` form".to_string(),
+            ));
+            return None;
+        }
+        if let Some(e) = values.iter().find_map(|v| HeaderValue::from_str(v).err()) {
+            errors.push(invalid_header(format!("invalid value: {e}")));
+            return None;
+        }
+        Some((name, values))
+    }
+
+    /// Targets without their own assertions have the common `assertions`.
+    fn parse_target(
+        target: &TargetEntry,
+        row: Option<Row>,
+        assertions: &Assertions,
+        errors: &mut Vec<ConfigError>,
+    ) -> Option<HttpTarget> {
+        let invalid_target = |value: String, reason: String| ConfigError::InvalidValue {
+            flag: "target",
+            value,
+            reason,
+        };
+        let section = match target {
+            TargetEntry::Url(url) => {
+                return HttpTarget::parse(url)
+                    .and_then(|parsed| {
+                        BenchmarkConfig::render_sample(&parsed.url, row)
+                            .and_then(|url| Uri::from_str(&url).map_err(|e| e.to_string()))
+                            .map(|_| HttpTarget {
+                                assertions: assertions.clone(),
+                                ..parsed
+                            })
+                    })
+                    .map_err(|reason| errors.push(invalid_target(url.clone(), reason)))
+                    .ok();
+            }
+            TargetEntry::Detailed(section) => section,
+        };
+
+        let errors_before = errors.len();
+        if let Err(reason) = BenchmarkConfig::render_sample(&section.url, row)
+            .and_then(|url| Uri::from_str(&url).map_err(|e| e.to_string()))
+        {
+            errors.push(invalid_target(section.url.clone(), reason));
+        }
+        if section.weight == 0 {
+            errors.push(invalid_target(
+                section.url.clone(),
+                "weight must be positive".to_string(),
+            ));
+        }
+        let method = section.method.as_ref().and_then(|method| {
+            Method::from_bytes(method.as_bytes())
+                .map_err(|e| {
+                    errors.push(ConfigError::InvalidValue {
+                        flag: "method",
+                        value: method.clone(),
+                        reason: e.to_string(),
+                    })
+                })
+                .ok()
+        });
+        let mut headers = vec![];
+        for header in &section.header {
+            match header.split_once(':') {
+                Some((name, value))
+                    if HeaderName::from_bytes(name.as_bytes()).is_ok()
+                        && HeaderValue::from_str(value).is_ok()
+                        && BenchmarkConfig::render_sample(value, row).is_ok() =>
+                {
+                    headers.push((name.to_string(), value.to_string()));
+                }
+                _ => errors.push(ConfigError::InvalidValue {
+                    flag: "header",
+                    value: header.clone(),
+                    reason: "expected a valid `Name:Value`".to_string(),
+                }),
+            }
+        }
+        let body = section.body.as_deref().and_then(|body| {
+            BenchmarkConfig::generate_body("body", Some(body))
+                .and_then(|generated| BenchmarkConfig::check_body_template(body, generated, row))
+                .map_err(|e| errors.push(e))
+                .ok()
+        });
+
+        let assertions = match &section.assert {
+            Some(options) => BenchmarkConfig::parse_assertions(options, errors),
+            None => assertions.clone(),
+        };
+
+        (errors.len() == errors_before).then(|| HttpTarget {
+            name: section.name.clone(),
+            url: section.url.clone(),
+            weight: section.weight,
+            method,
+            headers,
+            body,
+            assertions,
+            templates: Default::default(),
+        })
+    }
+
+    fn parse_assertions(options: &AssertOptions, errors: &mut Vec<ConfigError>) -> Assertions {
+        let mut checks = vec![];
+        if let Some(regex) = &options.body {
+            match Regex::new(regex) {
+                Ok(regex) => checks.push(Assertion::Body(regex)),
+                Err(e) => errors.push(ConfigError::InvalidValue {
+                    flag: "assert_body",
+                    value: regex.clone(),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        for json in &options.json {
+            match json.split_once('=') {
+                Some((pointer, value)) if pointer.starts_with('/') => {
+                    checks.push(Assertion::Json {
+                        pointer: pointer.to_string(),
+                        // a string, unless it is another JSON value
+                        value: serde_json::from_str(value)
+                            .unwrap_or_else(|_| serde_json::Value::String(value.to_string())),
+                    })
+                }
+                _ => errors.push(ConfigError::InvalidValue {
+                    flag: "assert_json",
+                    value: json.clone(),
+                    reason: "expected `/json/pointer=value`".to_string(),
+                }),
+            }
+        }
+        for header in &options.header {
+            match HeaderName::from_bytes(header.as_bytes()) {
+                Ok(name) => checks.push(Assertion::Header(name)),
+                Err(e) => errors.push(ConfigError::InvalidValue {
+                    flag: "assert_header",
+                    value: header.clone(),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        if let Some(size) = &options.size {
+            let parse_bound = |bound: &str, default: usize| match bound.trim() {
+                "" => Ok(default),
+                bound => bound.parse::<ByteSize>().map(|size| size.as_u64() as usize),
+            };
+            match size
+                .split_once("..")
+                .ok_or_else(|| "expected `MIN..MAX`".to_string())
+                .and_then(|(min, max)| Ok((parse_bound(min, 0)?, parse_bound(max, usize::MAX)?)))
+            {
+                Ok((min, max)) if min <= max => checks.push(Assertion::Size { min, max }),
+                result => errors.push(ConfigError::InvalidValue {
+                    flag: "assert_size",
+                    value: size.clone(),
+                    reason: result
+                        .err()
+                        .unwrap_or_else(|| "MIN must not be greater than MAX".to_string()),
+                }),
+            }
+        }
+        if let Some(checksum) = &options.checksum {
+            if checksum.len() == 40 && checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+                checks.push(Assertion::Checksum(checksum.clone()));
+            } else {
+                errors.push(ConfigError::InvalidValue {
+                    flag: "assert_checksum",
+                    value: checksum.clone(),
+                    reason: "expected SHA-1 in hex".to_string(),
+                });
+            }
+        }
+        if let Some(status) = options.status.iter().find(|s| !(100..=999).contains(*s)) {
+            errors.push(ConfigError::InvalidValue {
+                flag: "assert_status",
+                value: status.to_string(),
+                reason: "expected an HTTP status code".to_string(),
+            });
+        }
+        Assertions {
+            status: options.status.clone(),
+            checks,
+            fatal: options.fatal,
+        }
+    }
+
+    /// A value with `{{placeholders}}` rendered once with the feeder `row`, to validate it.
+    fn render_sample(value: &str, row: Option<Row>) -> Result<String, String> {
+        let template = Template::parse(value)?;
+        if let Some(variable) = template
+            .variables()
+            .find(|variable| row.and_then(|row| row.get(variable)).is_none())
+        {
+            return Err(format!(
+                "`{{{{{variable}}}}}` is not a column of the --feeder"
+            ));
+        }
+        Ok(template.render(1, row))
+    }
+
+    /// Checks the placeholders of a text body generated from `source`. Random bodies are not templates.
+    fn check_body_template(
+        source: &str,
+        body: Bytes,
+        row: Option<Row>,
+    ) -> Result<Bytes, ConfigError> {
+        if source.starts_with("random://") {
+            return Ok(body);
+        }
+        match std::str::from_utf8(&body).map(|body| BenchmarkConfig::render_sample(body, row)) {
+            Ok(Err(reason)) => Err(ConfigError::InvalidValue {
+                flag: "body",
+                value: source.to_string(),
+                reason,
+            }),
+            _ => Ok(body),
+        }
+    }
+
+    /// Parses a header into the name, the values and their weights (all 1 in the "Name:Value1:Value2" form).
+    fn parse_weighted_header(
+        header: &HeaderEntry,
+        row: Option<Row>,
+        errors: &mut Vec<ConfigError>,
+    ) -> Option<(Header, Vec<u32>)> {
+        let (source, (name, values), weights) = match header {
+            HeaderEntry::Values(header) => {
+                let (name, values) = BenchmarkConfig::parse_header(header, errors)?;
+                let weights = vec![1; values.len()];
+                (header.clone(), (name, values), weights)
+            }
+            HeaderEntry::Weighted(section) => {
+                let source = section.name.clone();
+                let (values, weights) = section
+                    .values
+                    .iter()
+                    .map(|value| (value.value.clone(), value.weight))
+                    .unzip();
+                (source, (section.name.clone(), values), weights)
+            }
+        };
+        let invalid_header = |reason: String| ConfigError::InvalidValue {
+            flag: "header",
+            value: source.clone(),
+            reason,
+        };
+        let reason = if let Err(e) = HeaderName::from_bytes(name.as_bytes()) {
+            Some(format!("invalid name: {e}"))
+        } else if values.is_empty() {
+            Some("at least one value is required".to_string())
+        } else if weights.contains(&0) {
+            Some("weight must be positive".to_string())
+        } else {
+            values.iter().find_map(|value| {
+                HeaderValue::from_str(value)
+                    .map_err(|e| format!("invalid value: {e}"))
+                    .and_then(|_| BenchmarkConfig::render_sample(value, row))
+                    .err()
+            })
+        };
+        match reason {
+            Some(reason) => {
+                errors.push(invalid_header(reason));
+                None
+            }
+            None => Some(((name, values), weights)),
+        }
+    }
+
+    /// Reads the payload of `flag` given as `random://SIZE`, `file://FILENAME` or `base64://DATA`.
+    pub(crate) fn generate_body(
+        flag: &'static str,
+        body: Option<&str>,
+    ) -> Result<Bytes, ConfigError> {
         const RANDOM_PREFIX: &str = "random://";
         const BASE64_PREFIX: &str = "base64://";
         const FILE_PREFIX: &str = "file://";
 
-        if let Some(body_value) = &args.body {
-            if let Some(body_size) = body_value.strip_prefix(RANDOM_PREFIX) {
-                Bytes::from(BenchmarkConfig::generate_random_vec(body_size))
-            } else if let Some(base64) = body_value.strip_prefix(BASE64_PREFIX) {
-                use base64::{prelude::BASE64_STANDARD, Engine as _};
-                Bytes::from(BASE64_STANDARD.decode(base64).expect("Invalid base64"))
-            } else if let Some(filename) = body_value.strip_prefix(FILE_PREFIX) {
-                Bytes::from(BenchmarkConfig::read_file_as_vec(filename))
-            } else {
-                panic!("Unsupported format: {body_value}");
-            }
+        let Some(body_value) = body else {
+            return Ok(Bytes::new());
+        };
+        let invalid_body = |reason: String| ConfigError::InvalidValue {
+            flag,
+            value: body_value.to_string(),
+            reason,
+        };
+
+        if let Some(body_size) = body_value.strip_prefix(RANDOM_PREFIX) {
+            BenchmarkConfig::generate_random_vec(body_size)
+                .map(Bytes::from)
+                .map_err(invalid_body)
+        } else if let Some(base64) = body_value.strip_prefix(BASE64_PREFIX) {
+            use base64::{prelude::BASE64_STANDARD, Engine as _};
+            BASE64_STANDARD
+                .decode(base64)
+                .map(Bytes::from)
+                .map_err(|e| invalid_body(format!("invalid base64: {e}")))
+        } else if let Some(filename) = body_value.strip_prefix(FILE_PREFIX) {
+            BenchmarkConfig::read_file_as_vec(filename)
+                .map(Bytes::from)
+                .map_err(invalid_body)
         } else {
-            Bytes::new()
+            Err(invalid_body(
+                "expected `random://SIZE`, `file://FILENAME` or `base64://DATA`".to_string(),
+            ))
         }
     }
 
-    fn generate_random_vec(size: &str) -> Vec<u8> {
+    fn generate_random_vec(size: &str) -> Result<Vec<u8>, String> {
         let body_size = size
             .parse::<u32>()
-            .expect("Body must have format 'RND:NUMBER', where NUMBER is a positive integer");
+            .map_err(|_| format!("the size must be a positive integer, got `{size}`"))?;
         let mut rng = rand::thread_rng();
         let random_data: Vec<u8> = (0..body_size).map(|_| rng.gen()).collect();
-        random_data
+        Ok(random_data)
     }
 
-    fn read_file_as_vec(filename: &str) -> Vec<u8> {
-        let mut f = File::open(filename).expect("File not found");
-        let metadata = fs::metadata(filename).expect("Cannot get metadata");
-        let mut buffer = vec![0; metadata.len() as usize];
-        f.read_exact(&mut buffer)
-            .map_err(|e| panic!("Error reading file {filename}: {e}"))
-            .unwrap();
-
-        buffer
+    fn read_file_as_vec(filename: &str) -> Result<Vec<u8>, String> {
+        fs::read(filename).map_err(|e| format!("cannot read file {filename}: {e}"))
     }
 
     pub fn new_bench_session(&mut self) -> BenchSession {
@@ -389,6 +1459,11 @@ impl BenchmarkConfig {
             .rate_ladder(self.rate_ladder.clone())
             .mode(Arc::new(self.mode.clone()))
             .request_timeout(self.request_timeout)
+            .max_timeout_rate(self.max_timeout_rate)
+            .circuit_breaker(self.circuit_breaker.clone())
+            .open_model(self.open_model)
+            .arrival_process(self.arrival_process.clone())
+            .load_profile(self.load_profile.clone())
             .build()
             .expect("BenchSessionBuilder failed")
     }
@@ -398,12 +1473,24 @@ impl fmt::Display for BenchmarkConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Mode={}, RateLadder={:?}, Concurrency={}",
-            self.mode, self.rate_ladder, self.concurrency
+            "Mode={}, RateLadder={:?}, Concurrency={}, OpenModel={}, Arrival={:?}",
+            self.mode, self.rate_ladder, self.concurrency, self.open_model, self.arrival_process
         )
     }
 }
 
+impl BenchmarkMode {
+    /// See [`BenchmarkProtocolAdapter::supports_open_model`].
+    pub fn supports_open_model(&self) -> bool {
+        match self {
+            BenchmarkMode::Http(adapter) => adapter.supports_open_model(),
+            #[cfg(feature = "http3")]
+            BenchmarkMode::Http3(adapter) => adapter.supports_open_model(),
+            BenchmarkMode::Custom(adapter) => adapter.supports_dyn_open_model(),
+        }
+    }
+}
+
 impl fmt::Display for BenchmarkMode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -414,6 +1501,9 @@ impl fmt::Display for BenchmarkMode {
             BenchmarkMode::Http3(mode) => {
                 writeln!(f, "{mode}")
             }
+            BenchmarkMode::Custom(mode) => {
+                writeln!(f, "{mode}")
+            }
         }
     }
 }
@@ -424,25 +1514,762 @@ mod test {
 
     #[test]
     fn test_generate_random_vec() {
-        let random_data = BenchmarkConfig::generate_random_vec("100");
+        let random_data = BenchmarkConfig::generate_random_vec("100").unwrap();
         assert_eq!(random_data.len(), 100);
     }
 
     #[test]
     fn test_read_file_as_vec() {
-        let random_data = BenchmarkConfig::read_file_as_vec("Cargo.toml");
+        let random_data = BenchmarkConfig::read_file_as_vec("Cargo.toml").unwrap();
         assert!(!random_data.is_empty());
     }
 
     #[test]
-    #[should_panic]
-    fn test_read_file_as_vec_panic() {
-        BenchmarkConfig::read_file_as_vec("not_existing_file");
+    fn test_read_file_as_vec_error() {
+        assert!(BenchmarkConfig::read_file_as_vec("not_existing_file").is_err());
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        let lookup = |name: &str| match name {
+            "HOST" => Some("localhost".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            Ok("http://localhost:8080/".to_string()),
+            Cli::interpolate_env("http://${HOST}:${PORT:-8080}/", lookup)
+        );
+        assert_eq!(
+            Ok("no variables".to_string()),
+            Cli::interpolate_env("no variables", lookup)
+        );
+        assert!(Cli::interpolate_env("${MISSING}", lookup).is_err());
+        assert!(Cli::interpolate_env("${HOST", lookup).is_err());
+    }
+
+    #[test]
+    fn test_config_from_yaml_file() {
+        let filename = std::env::temp_dir().join("perf-gauge-test-config.yaml");
+        fs::write(
+            &filename,
+            r#"
+# `${PERF_GAUGE_TEST_NAME}` is substituted in values only
+name: ${PERF_GAUGE_TEST_NAME:-yaml-test}
+concurrency: ${PERF_GAUGE_TEST_CONCURRENCY:-4}
+duration: 1m
+rate: 100
+rate_step: 100
+rate_max: 1000
+request_timeout: 5s
+http:
+  target:
+    - small=http://localhost/small
+    - name: 8kb
+      url: http://localhost/8kb
+  header:
+    - "x-header:value1:value2"
+  method: POST
+  body: base64://YWJjZA==
+  conn_reuse: true
+"#,
+        )
+        .unwrap();
+
+        let cli = Cli::from_file(filename.to_str().unwrap(), &ProtocolRegistry::default())
+            .expect("Valid config");
+        assert_eq!(Some("yaml-test".to_string()), cli.name);
+        assert_eq!(1, cli.max_iter);
+
+        let config =
+            BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default()).expect("Valid config");
+        assert_eq!(4, config.concurrency);
+        assert_eq!(Some(Duration::from_secs(5)), config.request_timeout);
+        assert!(config.to_string().contains("step_duration: Some(60s)"));
+        assert!(matches!(config.mode, BenchmarkMode::Http(_)));
+        let mode = config.mode.to_string();
+        assert!(mode.contains("conn_reuse: true"), "{mode}");
+        assert!(mode.contains("method=POST"), "{mode}");
+        assert!(mode.contains("body size=4"), "{mode}");
+        assert!(
+            mode.contains("first request=http://localhost/small"),
+            "{mode}"
+        );
+    }
+
+    #[test]
+    fn test_example_config() {
+        let registry = ProtocolRegistry::default();
+        let cli = Cli::from_file("examples/bench.yaml", &registry).expect("Valid example");
+        assert_eq!(Some("nginx-direct".to_string()), cli.name);
+        assert!(matches!(cli.command, Commands::Http(_)));
+        // e.g. --prometheus may be not compiled in
+        if let Err(errors) = BenchmarkConfig::from_cli(cli, &registry) {
+            for error in errors.0 {
+                assert!(matches!(error, ConfigError::Unsupported { .. }), "{error}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_config_keys() {
+        let filename = std::env::temp_dir().join("perf-gauge-test-unknown-keys.yaml");
+        let registry = ProtocolRegistry::default();
+        let parse = |content: &str| {
+            fs::write(&filename, content).unwrap();
+            Cli::from_file(filename.to_str().unwrap(), &registry)
+        };
+
+        assert!(parse("num_req: 1\ntcp:\n  target: localhost:1\n").is_ok());
+        match parse("num_reqs: 1\nhttp:\n  target: [http://localhost/]\n") {
+            Err(ConfigError::ConfigFile { reason, .. }) => {
+                assert_eq!("unknown keys `num_reqs`", reason)
+            }
+            r => panic!("Unexpected result {r:?}"),
+        }
+        let error = parse("num_req: 1\nhttp:\n  targets: [http://localhost/]\n").unwrap_err();
+        assert!(
+            error.to_string().contains("http: unknown field `targets`"),
+            "{error}"
+        );
+
+        // protocol sections are checked by the protocol
+        let cli = parse("num_req: 1\ntcp:\n  target: localhost:1\n  payloads: x\n").unwrap();
+        match BenchmarkConfig::from_cli(cli, &registry)
+            .err()
+            .unwrap()
+            .0
+            .as_slice()
+        {
+            [ConfigError::InvalidValue { flag, reason, .. }] => {
+                assert_eq!("tcp", *flag);
+                assert!(reason.contains("unknown field `payloads`"), "{reason}");
+            }
+            errors => panic!("Unexpected errors {errors:?}"),
+        }
     }
 
     #[test]
-    #[should_panic]
-    fn test_generate_random_vec_panic() {
-        BenchmarkConfig::generate_random_vec("not_a_number");
+    fn test_options_with_run() {
+        let registry = ProtocolRegistry::default();
+        let matches = Cli::command()
+            .try_get_matches_from([
+                "perf-gauge",
+                "--rate",
+                "10",
+                "-c",
+                "2",
+                "run",
+                "--config",
+                "examples/bench.yaml",
+            ])
+            .expect("Valid command line");
+        assert_eq!(
+            Some(vec![
+                ConfigError::Conflict {
+                    flag: "concurrency",
+                    other: "config"
+                },
+                ConfigError::Conflict {
+                    flag: "rate",
+                    other: "config"
+                },
+            ]),
+            BenchmarkConfig::from_matches(&matches, &registry)
+                .err()
+                .map(|e| e.0)
+        );
+    }
+
+    #[test]
+    fn test_detailed_targets() {
+        let entries: Vec<TargetEntry> = serde_yaml::from_str(
+            r#"
+- read=http://localhost/items/1#80
+- name: create
+  url: http://localhost/items
+  weight: 20
+  method: POST
+  header: ["content-type:application/json"]
+  body: base64://e30=
+- url: http://localhost/items/1
+  method: "DE LETE"
+  weight: 0
+  header: [no-value]
+"#,
+        )
+        .unwrap();
+
+        let mut errors = vec![];
+        let targets: Vec<Option<HttpTarget>> = entries
+            .iter()
+            .map(|entry| {
+                BenchmarkConfig::parse_target(entry, None, &Assertions::default(), &mut errors)
+            })
+            .collect();
+        assert_eq!(Some("read"), targets[0].as_ref().unwrap().name.as_deref());
+        let create = targets[1].as_ref().unwrap();
+        assert_eq!(20, create.weight);
+        assert_eq!(Some(Method::POST), create.method);
+        assert_eq!(
+            vec![("content-type".to_string(), "application/json".to_string())],
+            create.headers
+        );
+        assert_eq!(Some(Bytes::from_static(b"{}")), create.body);
+        assert!(targets[2].is_none());
+
+        let flags: Vec<&str> = errors
+            .iter()
+            .map(|e| match e {
+                ConfigError::InvalidValue { flag, .. } => *flag,
+                e => panic!("Unexpected error {e}"),
+            })
+            .collect();
+        assert_eq!(vec!["target", "method", "header"], flags);
+    }
+
+    #[test]
+    fn test_weighted_headers() {
+        let entries: Vec<HeaderEntry> = serde_yaml::from_str(
+            r#"
+- "x-ticket:JIRA#123:JIRA#7"
+- name: x-tenant
+  values:
+    - {value: a, weight: 3}
+    - {value: b}
+- name: x-empty
+  values: []
+- name: x-zero
+  values: [{value: a, weight: 0}]
+"#,
+        )
+        .unwrap();
+
+        let mut errors = vec![];
+        let headers: Vec<Option<(Header, Vec<u32>)>> = entries
+            .iter()
+            .map(|entry| BenchmarkConfig::parse_weighted_header(entry, None, &mut errors))
+            .collect();
+        assert_eq!(
+            Some((
+                (
+                    "x-ticket".to_string(),
+                    vec!["JIRA#123".to_string(), "JIRA#7".to_string()]
+                ),
+                vec![1, 1]
+            )),
+            headers[0]
+        );
+        assert_eq!(
+            Some((
+                (
+                    "x-tenant".to_string(),
+                    vec!["a".to_string(), "b".to_string()]
+                ),
+                vec![3, 1]
+            )),
+            headers[1]
+        );
+        assert_eq!(
+            vec![
+                ConfigError::InvalidValue {
+                    flag: "header",
+                    value: "x-empty".to_string(),
+                    reason: "at least one value is required".to_string(),
+                },
+                ConfigError::InvalidValue {
+                    flag: "header",
+                    value: "x-zero".to_string(),
+                    reason: "weight must be positive".to_string(),
+                },
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_config_from_toml_file() {
+        let filename = std::env::temp_dir().join("perf-gauge-test-config.toml");
+        fs::write(
+            &filename,
+            r#"
+num_req = 10
+open_model = true
+rate = 10.0
+
+[http]
+target = ["http://localhost/1"]
+"#,
+        )
+        .unwrap();
+
+        let config = BenchmarkConfig::from_cli(
+            Cli::from_file(filename.to_str().unwrap(), &ProtocolRegistry::default())
+                .expect("Valid config"),
+            &ProtocolRegistry::default(),
+        )
+        .expect("Valid config");
+        assert_eq!(1, config.concurrency);
+        assert!(config.open_model);
+        assert!(config.to_string().contains("step_requests: Some(10)"));
+    }
+
+    #[test]
+    fn test_config_file_errors() {
+        assert!(Cli::from_file("not_existing_file.yaml", &ProtocolRegistry::default()).is_err());
+
+        let filename = std::env::temp_dir().join("perf-gauge-test-invalid.yaml");
+        fs::write(
+            &filename,
+            "duration: 1m
+",
+        )
+        .unwrap();
+        assert!(Cli::from_file(filename.to_str().unwrap(), &ProtocolRegistry::default()).is_err());
+
+        fs::write(
+            &filename,
+            "search: true\nrate: 10\nrate_max: 100\nslo_error_rate: 1\nnum_req: 10\nhttp:\n  target: [http://localhost/]\n",
+        )
+        .unwrap();
+        let cli = Cli::from_file(filename.to_str().unwrap(), &ProtocolRegistry::default())
+            .expect("Valid config file");
+        assert_eq!(
+            Some(&ConfigError::Conflict {
+                flag: "search",
+                other: "rate_max"
+            }),
+            BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default())
+                .err()
+                .unwrap()
+                .0
+                .first()
+        );
+    }
+
+    #[test]
+    fn test_generate_random_vec_error() {
+        assert!(BenchmarkConfig::generate_random_vec("not_a_number").is_err());
+    }
+
+    #[test]
+    fn test_all_errors_are_reported() {
+        let cli = Cli::try_parse_from([
+            "perf-gauge",
+            "--concurrency",
+            "0",
+            "--duration",
+            "forever",
+            "--rate_max",
+            "100",
+            "--arrival",
+            "sometimes",
+            "--request_timeout",
+            "1s",
+            "--max_timeout_rate",
+            "150",
+            "--abort_p99",
+            "slow",
+            "http",
+            "http://localhost/",
+            "-H",
+            "no-value",
+            "-M",
+            "GE T",
+            "-B",
+            "base64://???",
+            "--assert_json",
+            "status",
+            "--assert_size",
+            "8kb..1kb",
+        ])
+        .expect("Valid command line");
+
+        let errors = match BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default()) {
+            Ok(_) => panic!("Configuration must be invalid"),
+            Err(ConfigErrors(errors)) => errors,
+        };
+        let flags: Vec<&str> = errors
+            .iter()
+            .map(|e| match e {
+                ConfigError::InvalidValue { flag, .. } | ConfigError::MissingValue { flag, .. } => {
+                    *flag
+                }
+                e => panic!("Unexpected error {e}"),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                "concurrency",
+                "duration",
+                "arrival",
+                "max_timeout_rate",
+                "abort_p99",
+                "rate",
+                "rate_step",
+                "assert_json",
+                "assert_size",
+                "method",
+                "header",
+                "body"
+            ],
+            flags
+        );
+        assert!(errors[1]
+            .to_string()
+            .starts_with("Invalid value `forever` of --duration"));
+    }
+
+    #[test]
+    fn test_valid_command_line() {
+        let cli = Cli::try_parse_from([
+            "perf-gauge",
+            "-n",
+            "10",
+            "http",
+            "http://localhost/",
+            "-H",
+            "x-header:a:b",
+            "-B",
+            "random://16",
+        ])
+        .expect("Valid command line");
+        let config =
+            BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default()).expect("Valid config");
+        assert!(config.mode.to_string().contains("body size=16"));
+    }
+
+    #[test]
+    fn test_search_start_rate() {
+        for rate in ["0", "-10"] {
+            let cli = Cli::try_parse_from([
+                "perf-gauge",
+                "-n",
+                "10",
+                "--search",
+                "--slo_error_rate",
+                "1",
+                &format!("--rate={rate}"),
+                "http",
+                "http://localhost/",
+            ])
+            .expect("Valid command line");
+            let errors = BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default())
+                .err()
+                .unwrap();
+            assert_eq!(
+                vec![ConfigError::InvalidValue {
+                    flag: "rate",
+                    value: rate.to_string(),
+                    reason: "the start rate of --search must be positive".to_string(),
+                }],
+                errors.0
+            );
+        }
+    }
+
+    #[test]
+    fn test_open_model_support() {
+        let parse = |protocol: &str| {
+            let cli = Cli::try_parse_from([
+                "perf-gauge",
+                "-n",
+                "10",
+                "--rate",
+                "10",
+                "--open_model",
+                protocol,
+                "localhost:8080",
+                "-B",
+                "random://16",
+            ])
+            .expect("Valid command line");
+            BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default())
+        };
+        assert!(parse("udp").is_ok());
+        assert_eq!(
+            vec![ConfigError::Unsupported {
+                flag: "open_model",
+                reason: "the protocol sends one request at a time per client".to_string(),
+            }],
+            parse("tcp").err().unwrap().0
+        );
+    }
+
+    #[test]
+    fn test_templates() {
+        let cli = Cli::try_parse_from([
+            "perf-gauge",
+            "-n",
+            "10",
+            "http",
+            "http://localhost/items/{{random_int 1 100}}",
+            "-H",
+            "x-request-id:{{uuid}}",
+            "-B",
+            "base64://eyJpZCI6IHt7Y291bnRlcn19fQ==",
+        ])
+        .expect("Valid command line");
+        assert!(BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default()).is_ok());
+
+        let cli = Cli::try_parse_from([
+            "perf-gauge",
+            "-n",
+            "10",
+            "http",
+            "http://localhost/items/{{random_int 100 1}}",
+            "-H",
+            "x-request-id:{{uuid",
+            "-B",
+            "base64://e3t1bmtub3dufX0=",
+        ])
+        .expect("Valid command line");
+        let errors = BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default())
+            .err()
+            .unwrap();
+        let flags: Vec<&str> = errors
+            .0
+            .iter()
+            .map(|e| match e {
+                ConfigError::InvalidValue { flag, .. } => *flag,
+                e => panic!("Unexpected error {e}"),
+            })
+            .collect();
+        assert_eq!(vec!["target", "header", "body"], flags);
+    }
+
+    #[test]
+    fn test_feeder() {
+        let filename = std::env::temp_dir().join("perf-gauge-test-feeder.csv");
+        fs::write(&filename, "user,query\n1,shoes\n2,hats\n").unwrap();
+        let feeder = filename.to_str().unwrap();
+
+        let cli = Cli::try_parse_from([
+            "perf-gauge",
+            "-c",
+            "2",
+            "-n",
+            "10",
+            "http",
+            "http://localhost/users/{{user}}?q={{query}}",
+            "--feeder",
+            feeder,
+            "--feeder_order",
+            "partitioned",
+            "--feeder_stop",
+        ])
+        .expect("Valid command line");
+        assert!(BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default()).is_ok());
+
+        let cli = Cli::try_parse_from([
+            "perf-gauge",
+            "-c",
+            "3",
+            "-n",
+            "10",
+            "http",
+            "http://localhost/users/{{id}}",
+            "-H",
+            "x-query:{{query}}",
+            "--feeder",
+            feeder,
+            "--feeder_order",
+            "partitioned",
+        ])
+        .expect("Valid command line");
+        let errors = BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default())
+            .err()
+            .unwrap();
+        let flags: Vec<&str> = errors
+            .0
+            .iter()
+            .map(|e| match e {
+                ConfigError::InvalidValue { flag, .. } => *flag,
+                e => panic!("Unexpected error {e}"),
+            })
+            .collect();
+        // no rows to check the placeholders with
+        assert_eq!(vec!["feeder", "target", "header"], flags);
+
+        let cli = Cli::try_parse_from([
+            "perf-gauge",
+            "-n",
+            "10",
+            "http",
+            "http://localhost/",
+            "--feeder_order",
+            "random",
+            "--feeder_stop",
+        ])
+        .expect("Valid command line");
+        let errors = BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec![
+                ConfigError::MissingValue {
+                    flag: "feeder",
+                    reason: "required by --feeder_stop".to_string()
+                },
+                ConfigError::Conflict {
+                    flag: "feeder_stop",
+                    other: "feeder_order"
+                }
+            ],
+            errors.0
+        );
+    }
+
+    #[test]
+    fn test_selection() {
+        let cli = Cli::try_parse_from([
+            "perf-gauge",
+            "-n",
+            "10",
+            "http",
+            "read=http://localhost/read#80",
+            "http://localhost/write#20",
+            "-H",
+            "x-ticket:JIRA#123",
+            "--seed",
+            "42",
+        ])
+        .expect("Valid command line");
+        let config =
+            BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default()).expect("Valid config");
+        let mode = config.mode.to_string();
+        assert!(mode.contains("selection=Seeded(42)"), "{mode}");
+        assert!(
+            mode.contains(r#"headers=[("x-ticket", ["JIRA#123"])]"#),
+            "{mode}"
+        );
+
+        let cli = Cli::try_parse_from([
+            "perf-gauge",
+            "-n",
+            "10",
+            "http",
+            "http://localhost/#0",
+            "-H",
+            "x-header",
+            "--selection",
+            "round_robin",
+            "--seed",
+            "42",
+        ])
+        .expect("Valid command line");
+        let errors = BenchmarkConfig::from_cli(cli, &ProtocolRegistry::default())
+            .err()
+            .unwrap();
+        let flags: Vec<&str> = errors
+            .0
+            .iter()
+            .map(|e| match e {
+                ConfigError::InvalidValue { flag, .. } | ConfigError::Conflict { flag, .. } => {
+                    *flag
+                }
+                e => panic!("Unexpected error {e}"),
+            })
+            .collect();
+        assert_eq!(vec!["target", "header", "seed"], flags);
+    }
+
+    #[derive(Args, Deserialize, Debug)]
+    #[command(about = "Echo the payload")]
+    struct EchoOptions {
+        #[arg(long)]
+        payload: String,
+    }
+
+    struct EchoAdapter {
+        payload: String,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::bench_run::BenchmarkProtocolAdapter for EchoAdapter {
+        type Client = ();
+
+        async fn build_client(&self) -> Result<Self::Client, String> {
+            Ok(())
+        }
+
+        async fn send_request(&self, _: &Self::Client) -> crate::metrics::RequestStats {
+            crate::metrics::RequestStatsBuilder::default()
+                .is_success(true)
+                .bytes_processed(self.payload.len())
+                .status("OK".to_string())
+                .duration(Duration::from_micros(1))
+                .fatal_error(false)
+                .build()
+                .expect("RequestStatsBuilder failed")
+        }
+    }
+
+    impl fmt::Display for EchoAdapter {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "echo {}", self.payload)
+        }
+    }
+
+    fn echo_registry() -> ProtocolRegistry {
+        let mut registry = ProtocolRegistry::default();
+        registry.register("echo", |options: EchoOptions| {
+            Ok(BenchmarkMode::Custom(Arc::new(EchoAdapter {
+                payload: options.payload,
+            })))
+        });
+        registry
+    }
+
+    #[tokio::test]
+    async fn test_registered_protocol() {
+        let registry = echo_registry();
+        assert!(registry
+            .help()
+            .unwrap()
+            .contains("echo        Echo the payload"));
+
+        let cli = Cli::try_parse_from(["perf-gauge", "-n", "3", "echo", "--payload", "abc"])
+            .expect("Valid command line");
+        let config = BenchmarkConfig::from_cli(cli, &registry).expect("Valid config");
+        assert_eq!("echo abc\n", config.mode.to_string());
+
+        let mut total_bytes = 0;
+        crate::runner::run_benchmark(config, |metrics| {
+            total_bytes += metrics.combined().total_bytes()
+        })
+        .await
+        .expect("Batches are run");
+        assert_eq!(9, total_bytes);
+    }
+
+    #[test]
+    fn test_registered_protocol_section() {
+        let registry = echo_registry();
+
+        let cli: Cli = serde_yaml::from_str("num_req: 1\necho:\n  payload: xyz\n").unwrap();
+        let config = BenchmarkConfig::from_cli(cli, &registry).expect("Valid config");
+        assert_eq!("echo xyz\n", config.mode.to_string());
+
+        let cli: Cli = serde_yaml::from_str("num_req: 1\necho:\n  size: 1\n").unwrap();
+        let errors = BenchmarkConfig::from_cli(cli, &registry).err().unwrap();
+        assert!(matches!(
+            errors.0.as_slice(),
+            [ConfigError::InvalidValue { flag: "echo", .. }]
+        ));
+
+        let cli = Cli::try_parse_from(["perf-gauge", "-n", "1", "echo", "--size", "1"])
+            .expect("Valid command line");
+        let errors = BenchmarkConfig::from_cli(cli, &registry).err().unwrap();
+        assert!(matches!(
+            errors.0.as_slice(),
+            [ConfigError::InvalidValue { flag: "echo", .. }]
+        ));
+
+        let cli: Cli = serde_yaml::from_str("num_req: 1\nsmtp:\n  payload: xyz\n").unwrap();
+        let errors = BenchmarkConfig::from_cli(cli, &registry).err().unwrap();
+        assert!(matches!(
+            errors.0.as_slice(),
+            [ConfigError::UnknownProtocol { .. }]
+        ));
     }
 }
@@ -0,0 +1,574 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::bench_run::BenchmarkProtocolAdapter;
+use crate::config_error::{ConfigError, ConfigErrors};
+use crate::configuration::parse_duration;
+use crate::error_category::ErrorCategory;
+use crate::metrics::RequestStats;
+use crate::socket_session::{is_host_port, request_stats, Connection, Outcome, SocketError};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use clap::{Args, ValueEnum};
+use core::fmt;
+use derive_builder::Builder;
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+#[derive(Args, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+#[command(about = "Run in DNS mode", long_about = None)]
+pub struct DnsOptions {
+    /// Resolver, e.g. 10.0.0.2 or my-resolver.com:5353 (port 53 by default).
+    resolver: String,
+    /// File with names to query, one per line. Each query uses a random one.
+    #[arg(long)]
+    names: String,
+    /// Query types. Can be multiple ones, e.g. `--type a --type aaaa`. In this case a random one is chosen for each query.
+    #[arg(long = "type", value_enum, default_value = "a")]
+    #[serde(rename = "type", default = "DnsOptions::default_query_type")]
+    query_type: Vec<QueryType>,
+    /// Query over TCP instead of UDP.
+    #[arg(long)]
+    #[serde(default)]
+    tcp: bool,
+    /// How long to wait for a response, e.g. 500ms
+    #[arg(long, default_value = "2s")]
+    #[serde(default = "DnsOptions::default_timeout")]
+    timeout: String,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryType {
+    A,
+    Aaaa,
+    Txt,
+    Srv,
+}
+
+impl DnsOptions {
+    fn default_query_type() -> Vec<QueryType> {
+        vec![QueryType::A]
+    }
+
+    fn default_timeout() -> String {
+        "2s".to_string()
+    }
+}
+
+impl QueryType {
+    fn name(&self) -> &'static str {
+        match self {
+            QueryType::A => "A",
+            QueryType::Aaaa => "AAAA",
+            QueryType::Txt => "TXT",
+            QueryType::Srv => "SRV",
+        }
+    }
+
+    fn code(&self) -> u16 {
+        match self {
+            QueryType::A => 1,
+            QueryType::Aaaa => 28,
+            QueryType::Txt => 16,
+            QueryType::Srv => 33,
+        }
+    }
+}
+
+/// Sends DNS queries for random names and types. The response code (e.g. `NXDOMAIN`) is the status,
+/// and the query type is the `operation_name`. `NOERROR` and `NXDOMAIN` are counted as success,
+/// truncated responses (to be retried over TCP) are failures with the `truncated` status.
+#[derive(Builder, Clone)]
+pub struct DnsBenchAdapter {
+    resolver: String,
+    names: Vec<String>,
+    query_types: Vec<QueryType>,
+    #[builder(default)]
+    tcp: bool,
+    timeout: Duration,
+}
+
+/// A UDP socket or a TCP connection of a single client. TCP connections are re-opened after an error.
+pub struct DnsClient {
+    connection: Connection<DnsConnection>,
+}
+
+enum DnsConnection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+const HEADER_SIZE: usize = 12;
+const STATUS_TRUNCATED: &str = "truncated";
+const RCODE_NAMES: [&str; 11] = [
+    "NOERROR", "FORMERR", "SERVFAIL", "NXDOMAIN", "NOTIMP", "REFUSED", "YXDOMAIN", "YXRRSET",
+    "NXRRSET", "NOTAUTH", "NOTZONE",
+];
+
+impl DnsBenchAdapter {
+    pub fn from_options(options: DnsOptions) -> Result<DnsBenchAdapter, ConfigErrors> {
+        let mut errors = vec![];
+
+        let resolver = if let Ok(ip) = options.resolver.parse::<IpAddr>() {
+            SocketAddr::new(ip, 53).to_string()
+        } else if !options.resolver.contains(':') {
+            format!("{}:53", options.resolver)
+        } else {
+            options.resolver.clone()
+        };
+        if !is_host_port(&resolver) {
+            errors.push(ConfigError::InvalidValue {
+                flag: "resolver",
+                value: options.resolver.clone(),
+                reason: "expected `host` or `host:port`".to_string(),
+            });
+        }
+
+        let names: Vec<String> = match fs::read_to_string(&options.names) {
+            Ok(content) => content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+                .collect(),
+            Err(e) => {
+                errors.push(ConfigError::InvalidValue {
+                    flag: "names",
+                    value: options.names.clone(),
+                    reason: format!("cannot read file: {e}"),
+                });
+                vec![]
+            }
+        };
+        for name in &names {
+            if let Err(reason) = DnsBenchAdapter::encode_name(&mut BytesMut::new(), name) {
+                errors.push(ConfigError::InvalidValue {
+                    flag: "names",
+                    value: name.clone(),
+                    reason,
+                });
+            }
+        }
+        if names.is_empty() && errors.is_empty() {
+            errors.push(ConfigError::MissingValue {
+                flag: "names",
+                reason: format!("no names in {}", options.names),
+            });
+        }
+        if options.query_type.is_empty() {
+            errors.push(ConfigError::MissingValue {
+                flag: "type",
+                reason: "at least one query type is required".to_string(),
+            });
+        }
+        let timeout = parse_duration("timeout", &Some(options.timeout), &mut errors);
+
+        match timeout {
+            Some(timeout) if errors.is_empty() => Ok(DnsBenchAdapterBuilder::default()
+                .resolver(resolver)
+                .names(names)
+                .query_types(options.query_type)
+                .tcp(options.tcp)
+                .timeout(timeout)
+                .build()
+                .expect("DnsBenchAdapterBuilder failed")),
+            _ => Err(ConfigErrors(errors)),
+        }
+    }
+
+    fn encode_name(buf: &mut BytesMut, name: &str) -> Result<(), String> {
+        let name = name.trim_end_matches('.');
+        if name.len() > 253 {
+            return Err("name is longer than 253 characters".to_string());
+        }
+        for label in name.split('.').filter(|label| !label.is_empty()) {
+            if label.len() > 63 {
+                return Err(format!("label `{label}` is longer than 63 characters"));
+            }
+            buf.put_u8(label.len() as u8);
+            buf.put_slice(label.as_bytes());
+        }
+        buf.put_u8(0);
+        Ok(())
+    }
+
+    /// A query with a single question and recursion desired.
+    fn encode_query(id: u16, name: &str, query_type: QueryType) -> Bytes {
+        let mut buf = BytesMut::with_capacity(HEADER_SIZE + name.len() + 6);
+        buf.put_u16(id);
+        // RD flag
+        buf.put_u16(0x0100);
+        // QDCOUNT, ANCOUNT, NSCOUNT, ARCOUNT
+        buf.put_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        DnsBenchAdapter::encode_name(&mut buf, name).expect("Names are validated");
+        buf.put_u16(query_type.code());
+        // IN class
+        buf.put_u16(1);
+        buf.freeze()
+    }
+
+    fn is_truncated(response: &[u8]) -> bool {
+        response[2] & 0x02 != 0
+    }
+
+    /// The size of the answer section, by the ANCOUNT of the header. Records of the other sections
+    /// (e.g. the EDNS OPT record) are not counted. `None` if the response is malformed.
+    fn answer_size(response: &[u8]) -> Option<usize> {
+        let count = |offset: usize| u16::from_be_bytes([response[offset], response[offset + 1]]);
+        let (questions, answers) = (count(4), count(6));
+        let mut position = HEADER_SIZE;
+        for _ in 0..questions {
+            // QTYPE and QCLASS
+            position = DnsBenchAdapter::skip_name(response, position)? + 4;
+        }
+        let answers_start = position;
+        for _ in 0..answers {
+            // TYPE, CLASS, TTL and RDLENGTH
+            let rdata = DnsBenchAdapter::skip_name(response, position)? + 10;
+            let rdlength = response.get(rdata - 2..rdata)?;
+            position = rdata + u16::from_be_bytes([rdlength[0], rdlength[1]]) as usize;
+        }
+        (position <= response.len()).then(|| position - answers_start)
+    }
+
+    /// The position after a (possibly compressed) name.
+    fn skip_name(response: &[u8], mut position: usize) -> Option<usize> {
+        loop {
+            let length = *response.get(position)?;
+            match length {
+                0 => return Some(position + 1),
+                // a pointer to a name earlier in the message
+                length if length & 0xc0 == 0xc0 => return Some(position + 2),
+                length => position += 1 + length as usize,
+            }
+        }
+    }
+
+    fn rcode_name(response: &[u8]) -> String {
+        let rcode = response[3] & 0x0f;
+        RCODE_NAMES
+            .get(rcode as usize)
+            .map_or_else(|| format!("RCODE {rcode}"), |name| name.to_string())
+    }
+
+    async fn connect(&self) -> Result<DnsConnection, SocketError> {
+        if self.tcp {
+            let stream = TcpStream::connect(&self.resolver)
+                .await
+                .map_err(SocketError::connect)?;
+            stream.set_nodelay(true).map_err(SocketError::connect)?;
+            return Ok(DnsConnection::Tcp(stream));
+        }
+        let addr = tokio::net::lookup_host(&self.resolver)
+            .await
+            .map_err(SocketError::connect)?
+            .next()
+            .ok_or_else(|| SocketError::connect(format!("Cannot resolve {}", self.resolver)))?;
+        let local_addr = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local_addr)
+            .await
+            .map_err(SocketError::connect)?;
+        socket.connect(addr).await.map_err(SocketError::connect)?;
+        Ok(DnsConnection::Udp(socket))
+    }
+
+    /// Sends the query and returns the response with the same id.
+    async fn query(
+        &self,
+        connection: &mut DnsConnection,
+        query: &[u8],
+    ) -> Result<Vec<u8>, SocketError> {
+        let id = &query[..2];
+        match connection {
+            DnsConnection::Udp(socket) => {
+                socket.send(query).await.map_err(SocketError::request)?;
+                let mut buf = vec![0; 65535];
+                loop {
+                    let size = socket.recv(&mut buf).await.map_err(SocketError::request)?;
+                    // skip late responses to earlier queries
+                    if size >= HEADER_SIZE && &buf[..2] == id {
+                        buf.truncate(size);
+                        return Ok(buf);
+                    }
+                }
+            }
+            DnsConnection::Tcp(stream) => {
+                let mut framed = BytesMut::with_capacity(2 + query.len());
+                framed.put_u16(query.len() as u16);
+                framed.put_slice(query);
+                stream
+                    .write_all(&framed)
+                    .await
+                    .map_err(SocketError::request)?;
+                let size = stream.read_u16().await.map_err(SocketError::request)?;
+                let mut buf = vec![0; size as usize];
+                stream
+                    .read_exact(&mut buf)
+                    .await
+                    .map_err(SocketError::request)?;
+                if buf.len() < HEADER_SIZE || &buf[..2] != id {
+                    return Err(SocketError::request(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid response",
+                    )));
+                }
+                Ok(buf)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BenchmarkProtocolAdapter for DnsBenchAdapter {
+    type Client = DnsClient;
+
+    async fn build_client(&self) -> Result<Self::Client, String> {
+        let connected = self.connect().await.map_err(|e| e.to_string())?;
+        Ok(DnsClient {
+            connection: Connection::new(Some(connected)),
+        })
+    }
+
+    /// Requests share the connection of a client, one at a time.
+    fn supports_open_model(&self) -> bool {
+        false
+    }
+
+    async fn send_request(&self, client: &Self::Client) -> RequestStats {
+        let (query, query_type) = {
+            let mut rng = thread_rng();
+            let name = self.names.choose(&mut rng).expect("Names are validated");
+            let query_type = *self
+                .query_types
+                .choose(&mut rng)
+                .expect("Query types are validated");
+            (
+                DnsBenchAdapter::encode_query(rng.gen(), name, query_type),
+                query_type,
+            )
+        };
+        let mut connection = client.connection.lock().await;
+        let start = Instant::now();
+
+        let mut connect = None;
+        let result = tokio::time::timeout(self.timeout, async {
+            let (connected, connect_time) = connection.open(self.connect()).await?;
+            connect = connect_time;
+            self.query(connected, &query).await
+        })
+        .await
+        .unwrap_or_else(|_| {
+            Err(SocketError::request(io::Error::from(
+                io::ErrorKind::TimedOut,
+            )))
+        });
+        if result.is_err() && self.tcp {
+            // re-connect on the next request
+            connection.close();
+        }
+
+        let rcode;
+        let result = match result {
+            Ok(response) if DnsBenchAdapter::is_truncated(&response) => {
+                Ok(Outcome::failure(STATUS_TRUNCATED))
+            }
+            Ok(response) => match DnsBenchAdapter::answer_size(&response) {
+                Some(size) => {
+                    rcode = DnsBenchAdapter::rcode_name(&response);
+                    Ok(Outcome {
+                        size,
+                        status: &rcode,
+                        is_success: rcode == RCODE_NAMES[0],
+                    })
+                }
+                None => Ok(Outcome::failure(ErrorCategory::ProtocolError.as_str())),
+            },
+            Err(e) => Err(e),
+        };
+        request_stats(Some(query_type.name()), result, start, connect)
+    }
+}
+
+impl fmt::Display for DnsBenchAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DNS {} over {}, {} names, types: {:?}",
+            self.resolver,
+            if self.tcp { "TCP" } else { "UDP" },
+            self.names.len(),
+            self.query_types
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_run::BenchmarkProtocolAdapter;
+    use crate::config_error::ConfigError;
+    use crate::dns_bench_session::{DnsBenchAdapter, DnsOptions, QueryType};
+    use crate::socket_session::tests::{parse_options, start_tcp_server};
+    use std::fs::File;
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UdpSocket;
+
+    /// An A record of 127.0.0.1 for the name of the question.
+    const ANSWER: [u8; 16] = [0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 1];
+    /// An EDNS OPT record, in the additional section.
+    const OPT: [u8; 11] = [0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0];
+
+    /// Responds with the query itself, an answer and an OPT record, with NXDOMAIN (and no answer) for names
+    /// starting with `missing`, with SERVFAIL for names starting with `broken`,
+    /// and with the TC flag for names starting with `large`.
+    fn respond(query: &[u8]) -> Vec<u8> {
+        let mut response = query.to_vec();
+        // QR flag
+        response[2] |= 0x80;
+        if query[13..].starts_with(b"missing") {
+            response[3] |= 3;
+        } else if query[13..].starts_with(b"broken") {
+            response[3] |= 2;
+        } else if query[13..].starts_with(b"large") {
+            response[2] |= 0x02;
+        } else {
+            // ANCOUNT
+            response[7] = 1;
+            response.extend_from_slice(&ANSWER);
+        }
+        // ARCOUNT
+        response[11] = 1;
+        response.extend_from_slice(&OPT);
+        response
+    }
+
+    async fn start_udp_resolver() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((size, peer)) = socket.recv_from(&mut buf).await {
+                socket.send_to(&respond(&buf[..size]), peer).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    async fn start_tcp_resolver() -> SocketAddr {
+        start_tcp_server(|mut stream| async move {
+            while let Ok(size) = stream.read_u16().await {
+                let mut query = vec![0; size as usize];
+                stream.read_exact(&mut query).await.unwrap();
+                let response = respond(&query);
+                stream.write_u16(response.len() as u16).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        })
+        .await
+    }
+
+    fn names_file(name: &str, names: &str) -> String {
+        let filename = std::env::temp_dir()
+            .join(format!("perf-gauge-dns-{name}-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        File::create(&filename)
+            .unwrap()
+            .write_all(names.as_bytes())
+            .unwrap();
+        filename
+    }
+
+    async fn assert_queries(addr: SocketAddr, tcp: bool) {
+        for (name, status, success, size) in [
+            ("example.com", "NOERROR", true, ANSWER.len()),
+            ("missing.example.com", "NXDOMAIN", false, 0),
+            ("broken.example.com", "SERVFAIL", false, 0),
+            ("large.example.com", "truncated", false, 0),
+        ] {
+            let filename = names_file(&format!("{name}-{tcp}"), name);
+            let mut args = vec![
+                "dns".to_string(),
+                addr.to_string(),
+                "--names".to_string(),
+                filename,
+                "--type".to_string(),
+                "srv".to_string(),
+            ];
+            if tcp {
+                args.push("--tcp".to_string());
+            }
+            let options: DnsOptions = parse_options(args);
+            let adapter = DnsBenchAdapter::from_options(options).unwrap();
+            assert!(adapter.to_string().starts_with("DNS"));
+
+            let client = adapter.build_client().await.unwrap();
+            let stats = adapter.send_request(&client).await;
+            assert_eq!(success, stats.is_success);
+            assert_eq!(status, stats.status);
+            assert_eq!(Some("SRV".to_string()), stats.operation_name);
+            assert_eq!(size, stats.bytes_processed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dns_udp() {
+        assert_queries(start_udp_resolver().await, false).await;
+    }
+
+    #[tokio::test]
+    async fn test_dns_tcp() {
+        assert_queries(start_tcp_resolver().await, true).await;
+    }
+
+    #[test]
+    fn test_answer_size() {
+        let query = DnsBenchAdapter::encode_query(1, "example.com", QueryType::A);
+        let response = respond(&query);
+        assert_eq!(Some(ANSWER.len()), DnsBenchAdapter::answer_size(&response));
+        // the answer is cut short
+        let mut response = respond(&query);
+        response.truncate(query.len() + ANSWER.len() - 1);
+        response[11] = 0;
+        assert_eq!(None, DnsBenchAdapter::answer_size(&response));
+    }
+
+    #[test]
+    fn test_encode_query() {
+        let query = DnsBenchAdapter::encode_query(0x1234, "a.io.", QueryType::Aaaa);
+        assert_eq!(
+            &[0x12, 0x34, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, b'a', 2, b'i', b'o', 0, 0, 28, 0, 1][..],
+            &query[..]
+        );
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let filename = names_file("invalid", &format!("# comment\n{}.com\n", "a".repeat(64)));
+        let options: DnsOptions = parse_options(["dns", "localhost:dns", "--names", &filename]);
+        let errors = DnsBenchAdapter::from_options(options).err().unwrap();
+        assert_eq!(2, errors.0.len());
+        assert!(matches!(
+            errors.0[1],
+            ConfigError::InvalidValue { flag: "names", .. }
+        ));
+    }
+}
@@ -0,0 +1,234 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use core::fmt;
+use log::debug;
+use std::error::Error;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A fixed set of categories of failed requests, reported as statuses instead of raw error messages
+/// (which are unique and would blow up e.g. the cardinality of Prometheus labels).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    ConnectRefused,
+    ConnectTimeout,
+    ConnectError,
+    DnsFailure,
+    TlsError,
+    ResetByPeer,
+    RequestTimeout,
+    BodyError,
+    ProtocolError,
+}
+
+/// Occurrences by category, to log a sample of raw messages.
+static OCCURRENCES: [AtomicU64; 9] = [const { AtomicU64::new(0) }; 9];
+
+impl ErrorCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::ConnectRefused => "connect_refused",
+            ErrorCategory::ConnectTimeout => "connect_timeout",
+            ErrorCategory::ConnectError => "connect_error",
+            ErrorCategory::DnsFailure => "dns_failure",
+            ErrorCategory::TlsError => "tls_error",
+            ErrorCategory::ResetByPeer => "reset_by_peer",
+            ErrorCategory::RequestTimeout => "request_timeout",
+            ErrorCategory::BodyError => "body_error",
+            ErrorCategory::ProtocolError => "protocol_error",
+        }
+    }
+
+    /// Classifies an error by its chain of sources. Connection errors of other kinds
+    /// (e.g. an unreachable network) are `connect_error`, and anything else is a `protocol_error`.
+    pub fn classify(error: &(dyn Error + 'static)) -> ErrorCategory {
+        ErrorCategory::classify_with(error, false)
+    }
+
+    /// Same as [`ErrorCategory::classify`] for errors known to happen while opening a connection.
+    pub(crate) fn classify_connect(error: &(dyn Error + 'static)) -> ErrorCategory {
+        ErrorCategory::classify_with(error, true)
+    }
+
+    fn classify_with(error: &(dyn Error + 'static), mut connecting: bool) -> ErrorCategory {
+        let mut source = Some(error);
+        while let Some(error) = source {
+            if let Some(error) = error.downcast_ref::<hyper_util::client::legacy::Error>() {
+                connecting |= error.is_connect();
+            }
+            if let Some(error) = error.downcast_ref::<hyper::Error>() {
+                if error.is_timeout() {
+                    return ErrorCategory::RequestTimeout;
+                }
+                if error.is_incomplete_message() {
+                    return ErrorCategory::ResetByPeer;
+                }
+            }
+            if let Some(category) = error
+                .downcast_ref::<io::Error>()
+                .and_then(|error| ErrorCategory::classify_io(error.kind(), connecting))
+            {
+                return category;
+            }
+            if let Some(category) = ErrorCategory::classify_message(&error.to_string(), connecting)
+            {
+                return category;
+            }
+            source = error.source();
+        }
+        if connecting {
+            ErrorCategory::ConnectError
+        } else {
+            ErrorCategory::ProtocolError
+        }
+    }
+
+    fn classify_io(kind: io::ErrorKind, connecting: bool) -> Option<ErrorCategory> {
+        match kind {
+            io::ErrorKind::ConnectionRefused => Some(ErrorCategory::ConnectRefused),
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => Some(ErrorCategory::ResetByPeer),
+            io::ErrorKind::TimedOut if connecting => Some(ErrorCategory::ConnectTimeout),
+            io::ErrorKind::TimedOut => Some(ErrorCategory::RequestTimeout),
+            _ => None,
+        }
+    }
+
+    /// For errors without a type to downcast to, e.g. of the TLS or QUIC libraries.
+    fn classify_message(message: &str, connecting: bool) -> Option<ErrorCategory> {
+        let message = message.to_ascii_lowercase();
+        let mentions = |words: &[&str]| words.iter().any(|word| message.contains(word));
+        if message.starts_with("dns error") || mentions(&["failed to lookup address"]) {
+            Some(ErrorCategory::DnsFailure)
+        } else if mentions(&["tls", "ssl", "certificate", "handshake"]) {
+            Some(ErrorCategory::TlsError)
+        } else if mentions(&["timed out", "timeout"]) {
+            Some(if connecting {
+                ErrorCategory::ConnectTimeout
+            } else {
+                ErrorCategory::RequestTimeout
+            })
+        } else if mentions(&["reset", "connection lost", "closed by peer"]) {
+            Some(ErrorCategory::ResetByPeer)
+        } else {
+            None
+        }
+    }
+
+    /// The status of a request failed with the `error`. A sample of raw messages
+    /// (the 1st, 2nd, 4th, 8th... of each category) is logged at the debug level.
+    pub fn status(self, error: &dyn fmt::Display) -> String {
+        let occurrence = OCCURRENCES[self as usize].fetch_add(1, Ordering::Relaxed) + 1;
+        if occurrence.is_power_of_two() {
+            debug!("{} #{}: {}", self, occurrence, error);
+        }
+        self.as_str().to_string()
+    }
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error_category::ErrorCategory;
+    use std::io;
+
+    #[derive(Debug)]
+    struct Wrapped(&'static str, io::Error);
+
+    impl std::fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl std::error::Error for Wrapped {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.1)
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let classify = |message: &'static str, kind: io::ErrorKind| {
+            ErrorCategory::classify(&Wrapped(message, io::Error::new(kind, "cause")))
+        };
+        assert_eq!(
+            ErrorCategory::ConnectRefused,
+            classify("tcp connect error", io::ErrorKind::ConnectionRefused)
+        );
+        assert_eq!(
+            ErrorCategory::ResetByPeer,
+            classify("error reading", io::ErrorKind::ConnectionReset)
+        );
+        assert_eq!(
+            ErrorCategory::RequestTimeout,
+            classify("error reading", io::ErrorKind::TimedOut)
+        );
+        assert_eq!(
+            ErrorCategory::DnsFailure,
+            classify("dns error", io::ErrorKind::Other)
+        );
+        assert_eq!(
+            ErrorCategory::TlsError,
+            classify("the TLS handshake failed", io::ErrorKind::Other)
+        );
+        assert_eq!(
+            ErrorCategory::ProtocolError,
+            classify("unexpected frame", io::ErrorKind::InvalidData)
+        );
+
+        let classify_connect = |kind: io::ErrorKind| {
+            let error = Wrapped("tcp connect error", io::Error::from(kind));
+            ErrorCategory::classify_with(&error, true)
+        };
+        assert_eq!(
+            ErrorCategory::ConnectTimeout,
+            classify_connect(io::ErrorKind::TimedOut)
+        );
+        assert_eq!(
+            ErrorCategory::ConnectError,
+            classify_connect(io::ErrorKind::AddrNotAvailable)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_classify_connect_errors() {
+        use bytes::Bytes;
+        use http_body_util::Empty;
+        use hyper_util::client::legacy::Client;
+        use hyper_util::rt::TokioExecutor;
+
+        // a port nobody listens on
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+        let error = client
+            .get(format!("http://{address}/").parse().unwrap())
+            .await
+            .expect_err("Nobody listens");
+        assert_eq!(
+            ErrorCategory::ConnectRefused,
+            ErrorCategory::classify(&error)
+        );
+
+        let error = client
+            .get("http://perf-gauge.invalid/".parse().unwrap())
+            .await
+            .expect_err("The name doesn't resolve");
+        assert_eq!(ErrorCategory::DnsFailure, ErrorCategory::classify(&error));
+    }
+}
@@ -0,0 +1,334 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use clap::ValueEnum;
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How the rows of a feeder are iterated.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeederOrder {
+    /// Row by row, shared by all clients.
+    #[default]
+    Sequential,
+    /// A random row for each request.
+    Random,
+    /// Each client goes through its own share of the rows, e.g. rows 1, 3, 5... and 2, 4, 6... for two clients.
+    Partitioned,
+}
+
+/// Rows of a CSV or JSONL file, which values are bound to the `{{column}}` placeholders of requests.
+pub struct Feeder {
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+    order: FeederOrder,
+    /// Stop once each row is used, instead of starting over.
+    stop_when_exhausted: bool,
+    /// The next row of each partition (a single one unless partitioned).
+    cursors: Vec<AtomicUsize>,
+}
+
+/// A row with the values by column.
+#[derive(Clone, Copy, Debug)]
+pub struct Row<'a> {
+    columns: &'a [String],
+    values: &'a [String],
+}
+
+impl<'a> Row<'a> {
+    /// Values by column, e.g. ones extracted from responses.
+    pub fn new(columns: &'a [String], values: &'a [String]) -> Row<'a> {
+        Row { columns, values }
+    }
+
+    pub fn get(&self, column: &str) -> Option<&'a str> {
+        self.columns
+            .iter()
+            .position(|c| c == column)
+            .map(|i| self.values[i].as_str())
+    }
+}
+
+impl Feeder {
+    /// Reads a JSONL file (`.jsonl` or `.ndjson`) with an object per line, or a CSV one with a header row.
+    /// Rows are partitioned by `partitions` clients.
+    pub fn from_file(
+        filename: &str,
+        order: FeederOrder,
+        stop_when_exhausted: bool,
+        partitions: usize,
+    ) -> Result<Feeder, String> {
+        let content =
+            fs::read_to_string(filename).map_err(|e| format!("cannot read {filename}: {e}"))?;
+        let (columns, rows) = if filename.ends_with(".jsonl") || filename.ends_with(".ndjson") {
+            parse_jsonl(&content)?
+        } else {
+            parse_csv(&content)?
+        };
+        Feeder::new(columns, rows, order, stop_when_exhausted, partitions)
+    }
+
+    pub fn new(
+        columns: Vec<String>,
+        rows: Vec<Vec<String>>,
+        order: FeederOrder,
+        stop_when_exhausted: bool,
+        partitions: usize,
+    ) -> Result<Feeder, String> {
+        if rows.is_empty() {
+            return Err("no rows".to_string());
+        }
+        let partitions = if order == FeederOrder::Partitioned {
+            partitions.max(1)
+        } else {
+            1
+        };
+        if partitions > rows.len() {
+            return Err(format!(
+                "{} rows cannot be partitioned by {partitions} clients",
+                rows.len()
+            ));
+        }
+        Ok(Feeder {
+            columns,
+            rows,
+            order,
+            stop_when_exhausted,
+            cursors: (0..partitions).map(|_| AtomicUsize::new(0)).collect(),
+        })
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// The first row, e.g. to validate templates.
+    pub fn first_row(&self) -> Row<'_> {
+        self.row(0)
+    }
+
+    /// The row for the next request of the client `client_id`. `None` once exhausted.
+    pub fn next_row(&self, client_id: usize) -> Option<Row<'_>> {
+        let partitions = self.cursors.len();
+        let partition = client_id % partitions;
+        let size = match self.order {
+            FeederOrder::Random => {
+                return Some(self.row(thread_rng().gen_range(0..self.rows.len())))
+            }
+            FeederOrder::Sequential => self.rows.len(),
+            // the remaining rows go to the first partitions
+            FeederOrder::Partitioned => (self.rows.len() - partition).div_ceil(partitions),
+        };
+        let position = self.cursors[partition].fetch_add(1, Ordering::Relaxed);
+        if position >= size && self.stop_when_exhausted {
+            return None;
+        }
+        Some(self.row((position % size) * partitions + partition))
+    }
+
+    fn row(&self, index: usize) -> Row<'_> {
+        Row {
+            columns: &self.columns,
+            values: &self.rows[index],
+        }
+    }
+}
+
+/// Objects by line, with the keys as the columns. Missing values are empty.
+fn parse_jsonl(content: &str) -> Result<(Vec<String>, Vec<Vec<String>>), String> {
+    let mut columns: Vec<String> = vec![];
+    let mut objects = vec![];
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let object: serde_json::Map<String, Value> = serde_json::from_str(line)
+            .map_err(|e| format!("line {}: expected a JSON object: {e}", number + 1))?;
+        for key in object.keys() {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+        objects.push(object);
+    }
+    let rows = objects
+        .iter()
+        .map(|object| {
+            columns
+                .iter()
+                .map(|column| match object.get(column) {
+                    None | Some(Value::Null) => String::new(),
+                    Some(Value::String(value)) => value.clone(),
+                    Some(value) => value.to_string(),
+                })
+                .collect()
+        })
+        .collect();
+    Ok((columns, rows))
+}
+
+/// The first record is the header. Fields can be quoted, with `""` for a quote.
+fn parse_csv(content: &str) -> Result<(Vec<String>, Vec<Vec<String>>), String> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("a quoted field is not closed".to_string());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    // blank lines
+    records.retain(|record| record.len() > 1 || !record[0].is_empty());
+
+    let mut records = records.into_iter();
+    let columns = records.next().ok_or("no header row")?;
+    let rows = records
+        .enumerate()
+        .map(|(i, row)| {
+            if row.len() == columns.len() {
+                Ok(row)
+            } else {
+                Err(format!(
+                    "row {} has {} fields, expected {}",
+                    i + 1,
+                    row.len(),
+                    columns.len()
+                ))
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((columns, rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::feeder::{parse_csv, parse_jsonl, Feeder, FeederOrder};
+
+    fn numbered(order: FeederOrder, stop_when_exhausted: bool, partitions: usize) -> Feeder {
+        let rows = (1..=5).map(|i| vec![i.to_string()]).collect();
+        Feeder::new(
+            vec!["id".to_string()],
+            rows,
+            order,
+            stop_when_exhausted,
+            partitions,
+        )
+        .unwrap()
+    }
+
+    fn some(ids: &[&str]) -> Vec<Option<String>> {
+        ids.iter().map(|id| Some(id.to_string())).collect()
+    }
+
+    fn ids(feeder: &Feeder, client_id: usize, n: usize) -> Vec<Option<String>> {
+        (0..n)
+            .map(|_| {
+                feeder
+                    .next_row(client_id)
+                    .and_then(|row| row.get("id").map(String::from))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sequential() {
+        let feeder = numbered(FeederOrder::Sequential, false, 2);
+        assert_eq!(some(&["1", "2", "3", "4", "5", "1"]), ids(&feeder, 1, 6));
+
+        let feeder = numbered(FeederOrder::Sequential, true, 1);
+        assert_eq!(4, ids(&feeder, 0, 4).len());
+        assert_eq!(vec![Some("5".to_string()), None], ids(&feeder, 0, 2));
+    }
+
+    #[test]
+    fn test_partitioned() {
+        let feeder = numbered(FeederOrder::Partitioned, true, 2);
+        assert_eq!(some(&["2", "4"]), ids(&feeder, 1, 2));
+        assert_eq!(some(&["1", "3", "5"]), ids(&feeder, 2, 3));
+        assert_eq!(
+            None,
+            feeder
+                .next_row(0)
+                .map(|row| row.get("id").map(String::from))
+        );
+
+        assert!(Feeder::new(
+            vec!["id".to_string()],
+            vec![vec!["1".to_string()]],
+            FeederOrder::Partitioned,
+            false,
+            2
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_random() {
+        let feeder = numbered(FeederOrder::Random, true, 1);
+        assert!(ids(&feeder, 0, 100).iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let (columns, rows) =
+            parse_csv("user,query\r\n1,\"shoes, red\"\n\n2,\"say \"\"hi\"\"\nthere\"").unwrap();
+        assert_eq!(vec!["user", "query"], columns);
+        assert_eq!(
+            vec![vec!["1", "shoes, red"], vec!["2", "say \"hi\"\nthere"]],
+            rows
+        );
+        assert!(parse_csv("a,b\n1\n").is_err());
+        assert!(parse_csv("a\n\"1\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_jsonl() {
+        let (columns, rows) =
+            parse_jsonl("{\"user\": 1, \"query\": \"shoes\"}\n\n{\"user\": 2, \"tag\": null}\n")
+                .unwrap();
+        let feeder = Feeder::new(columns, rows, FeederOrder::Sequential, true, 1).unwrap();
+        let rows: Vec<Vec<Option<&str>>> = (0..2)
+            .map(|_| {
+                let row = feeder.next_row(0).unwrap();
+                vec![row.get("user"), row.get("query"), row.get("tag")]
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                vec![Some("1"), Some("shoes"), Some("")],
+                vec![Some("2"), Some(""), Some("")]
+            ],
+            rows
+        );
+        assert!(parse_jsonl("[1]").is_err());
+    }
+}
@@ -0,0 +1,417 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::bench_run::BenchmarkProtocolAdapter;
+use crate::config_error::{ConfigError, ConfigErrors};
+use crate::configuration::BenchmarkConfig;
+use crate::error_category::ErrorCategory;
+use crate::http_bench_session::{
+    HttpBenchAdapter, HttpBenchAdapterBuilder, HttpClientConfigBuilder, HttpRequestBuilder,
+};
+use crate::metrics::{RequestStats, RequestStatsBuilder};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use clap::Args;
+use core::fmt;
+use derive_builder::Builder;
+use http_body_util::BodyExt;
+use hyper::{HeaderMap, Method};
+use serde::Deserialize;
+use std::time::Instant;
+
+#[derive(Args, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+#[command(about = "Run in gRPC mode (unary calls)", long_about = None)]
+pub struct GrpcOptions {
+    /// Target, e.g. http://my-service.com:50051 Can be multiple ones (with random choice balancing).
+    #[arg(required = true)]
+    target: Vec<String>,
+    /// Full method name, e.g. `helloworld.Greeter/SayHello`.
+    #[arg(long)]
+    method: String,
+    /// Pre-encoded protobuf request message. Could be either `random://[0-9]+`, `file://$filename` or `base64://${valid_base64}`.
+    /// By default an empty message.
+    #[arg(short = 'B', long)]
+    message: Option<String>,
+    /// Metadata in "Name:Value1" form. E.g. `-H "authorization:Bearer token"`
+    /// It can contain multiple values, e.g. "Name:Value1:Value2:Value3". In this case a random one is chosen for each request.
+    #[arg(short = 'H', long)]
+    #[serde(default)]
+    header: Vec<String>,
+    /// Stop immediately on gRPC status codes. E.g. `-E 16` for UNAUTHENTICATED.
+    #[arg(short = 'E', long = "error_stop")]
+    #[serde(default)]
+    error_stop: Vec<u16>,
+    /// Allow self signed certificates.
+    #[arg(long = "ignore_cert")]
+    #[serde(default)]
+    ignore_cert: bool,
+}
+
+/// Unary gRPC calls over HTTP/2. The message is sent as is, so it must be encoded in advance,
+/// e.g. with `protoc --encode`.
+#[derive(Builder, Clone)]
+pub struct GrpcBenchAdapter {
+    /// Sends framed messages as HTTP/2 POST requests.
+    http: HttpBenchAdapter,
+    #[builder(default)]
+    stop_on_errors: Vec<u16>,
+}
+
+/// Canonical names of gRPC status codes, by code.
+const GRPC_STATUS_NAMES: [&str; 17] = [
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+impl GrpcBenchAdapter {
+    pub fn from_options(options: GrpcOptions) -> Result<GrpcBenchAdapter, ConfigErrors> {
+        let mut errors = vec![];
+
+        let method = options.method.trim_matches('/');
+        if method.split('/').count() != 2 {
+            errors.push(ConfigError::InvalidValue {
+                flag: "method",
+                value: options.method.clone(),
+                reason: "expected `package.Service/Method`".to_string(),
+            });
+        }
+
+        let mut headers: Vec<(String, Vec<String>)> = vec![
+            (
+                "content-type".to_string(),
+                vec!["application/grpc".to_string()],
+            ),
+            ("te".to_string(), vec!["trailers".to_string()]),
+        ];
+        headers.extend(
+            options
+                .header
+                .iter()
+                .filter_map(|h| BenchmarkConfig::parse_header(h, &mut errors)),
+        );
+
+        let message = BenchmarkConfig::generate_body("message", options.message.as_deref())
+            .unwrap_or_else(|e| {
+                errors.push(e);
+                Bytes::new()
+            });
+
+        if !errors.is_empty() {
+            return Err(ConfigErrors(errors));
+        }
+
+        let http = HttpBenchAdapterBuilder::default()
+            .config(
+                HttpClientConfigBuilder::default()
+                    .ignore_cert(options.ignore_cert)
+                    .conn_reuse(true)
+                    .http2_only(true)
+                    .build()
+                    .expect("HttpClientConfigBuilder failed"),
+            )
+            .request(
+                HttpRequestBuilder::default()
+                    .url(
+                        options
+                            .target
+                            .iter()
+                            .map(|target| format!("{}/{method}", target.trim_end_matches('/')))
+                            .collect(),
+                    )
+                    .method(Method::POST)
+                    .headers(headers)
+                    .body(GrpcBenchAdapter::frame_message(&message))
+                    .build()
+                    .map_err(|e| ConfigError::InvalidValue {
+                        flag: "target",
+                        value: options.target.join(" "),
+                        reason: e.to_string(),
+                    })?,
+            )
+            .build()
+            .expect("HttpBenchAdapterBuilder failed");
+
+        Ok(GrpcBenchAdapterBuilder::default()
+            .http(http)
+            .stop_on_errors(options.error_stop)
+            .build()
+            .expect("GrpcBenchAdapterBuilder failed"))
+    }
+
+    /// Length-prefixed message: the compression flag (none) and the length as u32 big-endian.
+    fn frame_message(message: &[u8]) -> Bytes {
+        let mut framed = BytesMut::with_capacity(5 + message.len());
+        framed.put_u8(0);
+        framed.put_u32(message.len() as u32);
+        framed.put_slice(message);
+        framed.freeze()
+    }
+
+    /// `grpc-status` from the trailers, or from the headers for Trailers-Only responses.
+    fn grpc_status(headers: &HeaderMap) -> Option<u16> {
+        headers
+            .get("grpc-status")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+    }
+
+    fn status_name(code: u16) -> String {
+        let name = GRPC_STATUS_NAMES
+            .get(code as usize)
+            .copied()
+            .unwrap_or("UNKNOWN_STATUS");
+        format!("{code} {name}")
+    }
+}
+
+#[async_trait]
+impl BenchmarkProtocolAdapter for GrpcBenchAdapter {
+    type Client = <HttpBenchAdapter as BenchmarkProtocolAdapter>::Client;
+
+    async fn build_client(&self) -> Result<Self::Client, String> {
+        self.http.build_client().await
+    }
+
+    async fn send_request(&self, client: &Self::Client) -> RequestStats {
+        let start = Instant::now();
+        let request = match self.http.build_request(client) {
+            Ok(request) => request,
+            Err(e) => return e.stats(None),
+        };
+        let response = client.pool.request(request).await;
+
+        match response {
+            Ok(r) if !r.status().is_success() => RequestStatsBuilder::default()
+                .bytes_processed(0)
+                .status(r.status().to_string())
+                .is_success(false)
+                .duration(Instant::now().duration_since(start))
+                .fatal_error(false)
+                .build()
+                .expect("RequestStatsBuilder failed"),
+            Ok(r) => {
+                let mut grpc_status = GrpcBenchAdapter::grpc_status(r.headers());
+                let mut body = r.into_body();
+                let mut total_size = 0;
+                let mut body_error = None;
+                while let Some(frame_result) = body.frame().await {
+                    match frame_result {
+                        Ok(frame) => {
+                            if let Some(data) = frame.data_ref() {
+                                total_size += data.len();
+                            } else if let Some(trailers) = frame.trailers_ref() {
+                                grpc_status = GrpcBenchAdapter::grpc_status(trailers);
+                            }
+                        }
+                        Err(e) => {
+                            body_error = Some(e);
+                            break;
+                        }
+                    }
+                }
+
+                // the stream failed before the trailers with the final status
+                let (status, fatal_error) = match &body_error {
+                    Some(e) => (ErrorCategory::BodyError.status(e), false),
+                    None => (
+                        grpc_status.map_or_else(
+                            || "missing grpc-status".to_string(),
+                            GrpcBenchAdapter::status_name,
+                        ),
+                        grpc_status
+                            .is_some_and(|code| code != 0 && self.stop_on_errors.contains(&code)),
+                    ),
+                };
+
+                RequestStatsBuilder::default()
+                    .bytes_processed(total_size)
+                    .status(status)
+                    .is_success(grpc_status == Some(0) && body_error.is_none())
+                    .duration(Instant::now().duration_since(start))
+                    .fatal_error(fatal_error)
+                    .build()
+                    .expect("RequestStatsBuilder failed")
+            }
+            Err(e) => RequestStatsBuilder::default()
+                .bytes_processed(0)
+                .status(ErrorCategory::classify(&e).status(&e))
+                .is_success(false)
+                .duration(Instant::now().duration_since(start))
+                .fatal_error(false)
+                .build()
+                .expect("RequestStatsBuilder failed"),
+        }
+    }
+}
+
+impl fmt::Display for GrpcBenchAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "gRPC {}", self.http)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_run::BenchmarkProtocolAdapter;
+    use crate::grpc_bench_session::{GrpcBenchAdapter, GrpcOptions};
+    use bytes::Bytes;
+    use clap::Parser;
+    use futures_util::stream::{self, StreamExt};
+    use http_body_util::combinators::BoxBody;
+    use http_body_util::{BodyExt, StreamBody};
+    use hyper::body::{Frame, Incoming};
+    use hyper::service::service_fn;
+    use hyper::{HeaderMap, Request, Response};
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use std::convert::Infallible;
+    use std::io;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        options: GrpcOptions,
+    }
+
+    type TestBody = BoxBody<Bytes, io::Error>;
+
+    /// Echoes the request message back with the `grpc-status` of the `x-status` header,
+    /// or fails the stream after the message if it's `fail`.
+    async fn handle(request: Request<Incoming>) -> Result<Response<TestBody>, Infallible> {
+        assert_eq!("/test.Echo/Say", request.uri().path());
+        assert_eq!("application/grpc", request.headers()["content-type"]);
+        assert_eq!("trailers", request.headers()["te"]);
+        let status = request.headers()["x-status"].clone();
+        let message = request.into_body().collect().await.unwrap().to_bytes();
+
+        let message = stream::iter([Ok(Frame::data(message))]);
+        let body = if status == "fail" {
+            // after the response headers are sent
+            let failure = stream::once(async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Err(io::Error::other("failed"))
+            });
+            BodyExt::boxed(StreamBody::new(message.chain(failure)))
+        } else {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", status);
+            let trailers = stream::iter([Ok(Frame::trailers(trailers))]);
+            BodyExt::boxed(StreamBody::new(message.chain(trailers)))
+        };
+        Ok(Response::new(body))
+    }
+
+    async fn start_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(
+                    hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service_fn(handle)),
+                );
+            }
+        });
+        addr
+    }
+
+    fn build_adapter(addr: SocketAddr, status: &str) -> GrpcBenchAdapter {
+        let cli = TestCli::try_parse_from([
+            "grpc",
+            &format!("http://{addr}"),
+            "--method",
+            "test.Echo/Say",
+            "-B",
+            "base64://CgV3b3JsZA==",
+            "-H",
+            &format!("x-status:{status}"),
+            "-E",
+            "16",
+        ])
+        .unwrap();
+        let adapter = GrpcBenchAdapter::from_options(cli.options).unwrap();
+        assert!(adapter.to_string().starts_with("gRPC"));
+        adapter
+    }
+
+    #[tokio::test]
+    async fn test_grpc_status() {
+        let addr = start_server().await;
+
+        let adapter = build_adapter(addr, "0");
+        let client = adapter.build_client().await.unwrap();
+        let stats = adapter.send_request(&client).await;
+        assert!(stats.is_success);
+        assert_eq!("0 OK", stats.status);
+        // 5 bytes of the prefix and the message
+        assert_eq!(12, stats.bytes_processed);
+
+        let adapter = build_adapter(addr, "14");
+        let stats = adapter.send_request(&client).await;
+        assert!(!stats.is_success);
+        assert!(!stats.fatal_error);
+        assert_eq!("14 UNAVAILABLE", stats.status);
+
+        let adapter = build_adapter(addr, "16");
+        let stats = adapter.send_request(&client).await;
+        assert!(stats.fatal_error);
+        assert_eq!("16 UNAUTHENTICATED", stats.status);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_stream_failure() {
+        let addr = start_server().await;
+
+        let adapter = build_adapter(addr, "fail");
+        let client = adapter.build_client().await.unwrap();
+        let stats = adapter.send_request(&client).await;
+        assert!(!stats.is_success);
+        assert!(!stats.fatal_error);
+        assert_eq!("body_error", stats.status);
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let cli = TestCli::try_parse_from([
+            "grpc",
+            "http://localhost:50051",
+            "--method",
+            "SayHello",
+            "-B",
+            "hex://00",
+        ])
+        .unwrap();
+        let errors = GrpcBenchAdapter::from_options(cli.options).err().unwrap();
+        assert_eq!(2, errors.0.len());
+    }
+
+    #[test]
+    fn test_frame_message() {
+        assert_eq!(
+            Bytes::from_static(&[0, 0, 0, 0, 2, 8, 1]),
+            GrpcBenchAdapter::frame_message(&[8, 1])
+        );
+    }
+}
@@ -6,10 +6,11 @@
 /// option. This file may not be copied, modified, or distributed
 /// except according to those terms.
 use crate::bench_run::BenchmarkProtocolAdapter;
-use crate::http_bench_session::{HttpClientConfig, HttpRequest};
+use crate::error_category::ErrorCategory;
+use crate::http_bench_session::{HttpClientConfig, HttpRequest, RequestParts};
 use crate::metrics::{RequestStats, RequestStatsBuilder};
 use async_trait::async_trait;
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use core::fmt;
 use derive_builder::Builder;
 use h3::client::SendRequest;
@@ -131,6 +132,8 @@ impl H3BenchAdapter {
 /// Wraps the SendRequest handle plus connection metadata needed per-request.
 pub struct H3Client {
     send_request: SendRequest<h3_quinn::OpenStreams, Bytes>,
+    /// E.g. for partitioned feeders.
+    id: usize,
     /// Keep endpoint alive for the lifetime of the client.
     _endpoint: quinn::Endpoint,
 }
@@ -169,6 +172,7 @@ impl BenchmarkProtocolAdapter for H3BenchAdapter {
 
         Ok(H3Client {
             send_request,
+            id: self.request.register_client(),
             _endpoint: endpoint,
         })
     }
@@ -176,23 +180,33 @@ impl BenchmarkProtocolAdapter for H3BenchAdapter {
     async fn send_request(&self, client: &Self::Client) -> RequestStats {
         let start = Instant::now();
 
-        let (url, method, headers, body) = self.request.request_parts();
+        let target = self.request.choose_target();
+        let RequestParts {
+            url,
+            method,
+            headers,
+            body,
+            ..
+        } = match self.request.request_parts(target, client.id) {
+            Ok(parts) => parts,
+            Err(e) => return e.stats(target.name.clone()),
+        };
 
-        let mut request_builder = Request::builder().method(method).uri(url);
+        let mut request_builder = Request::builder().method(method).uri(url.as_ref());
 
-        for (name, value) in headers {
-            request_builder = request_builder.header(name, value);
+        for (name, value) in &headers {
+            request_builder = request_builder.header(*name, value.as_ref());
         }
 
         let request = match request_builder.body(()) {
             Ok(r) => r,
             Err(e) => {
-                error!("Failed to build H3 request: {}", e);
                 return RequestStatsBuilder::default()
                     .bytes_processed(0)
-                    .status(e.to_string())
+                    .status(ErrorCategory::ProtocolError.status(&e))
                     .is_success(false)
                     .duration(Instant::now().duration_since(start))
+                    .operation_name(target.name.clone())
                     .fatal_error(false)
                     .build()
                     .expect("RequestStatsBuilder failed");
@@ -207,12 +221,12 @@ impl BenchmarkProtocolAdapter for H3BenchAdapter {
                 // Send body if present
                 if !body.is_empty() {
                     if let Err(e) = stream.send_data(body).await {
-                        error!("H3 send_data error: {}", e);
                         return RequestStatsBuilder::default()
                             .bytes_processed(0)
-                            .status(e.to_string())
+                            .status(ErrorCategory::classify(&e).status(&e))
                             .is_success(false)
                             .duration(Instant::now().duration_since(start))
+                            .operation_name(target.name.clone())
                             .fatal_error(false)
                             .build()
                             .expect("RequestStatsBuilder failed");
@@ -221,12 +235,12 @@ impl BenchmarkProtocolAdapter for H3BenchAdapter {
 
                 // Signal end of request
                 if let Err(e) = stream.finish().await {
-                    error!("H3 finish error: {}", e);
                     return RequestStatsBuilder::default()
                         .bytes_processed(0)
-                        .status(e.to_string())
+                        .status(ErrorCategory::classify(&e).status(&e))
                         .is_success(false)
                         .duration(Instant::now().duration_since(start))
+                        .operation_name(target.name.clone())
                         .fatal_error(false)
                         .build()
                         .expect("RequestStatsBuilder failed");
@@ -235,56 +249,66 @@ impl BenchmarkProtocolAdapter for H3BenchAdapter {
                 // Receive response
                 match stream.recv_response().await {
                     Ok(response) => {
-                        let status = response.status().to_string();
-                        let success = response.status().is_success();
-                        let fatal_error = !success
-                            && self
-                                .config
-                                .stop_on_errors
-                                .contains(&response.status().as_u16());
+                        let assertions = &target.assertions;
+                        let keep_body = assertions.needs_body();
 
                         // Read response body
                         let mut total_size = 0;
-                        let mut body_error = false;
-                        while let Ok(Some(data)) = stream.recv_data().await {
-                            total_size += data.remaining();
-                        }
-
-                        // Check for stream errors after body
-                        if stream.recv_data().await.is_err() {
-                            body_error = true;
+                        let mut body_error = None;
+                        let mut kept_body = BytesMut::new();
+                        loop {
+                            match stream.recv_data().await {
+                                Ok(Some(data)) => {
+                                    total_size += data.remaining();
+                                    if keep_body {
+                                        kept_body.put(data);
+                                    }
+                                }
+                                Ok(None) => break,
+                                Err(e) => {
+                                    body_error = Some(e);
+                                    break;
+                                }
+                            }
                         }
 
+                        let outcome = assertions.outcome(
+                            response.status(),
+                            &self.config.stop_on_errors,
+                            response.headers(),
+                            &kept_body,
+                            total_size,
+                            body_error.as_ref().map(|e| e as &dyn fmt::Display),
+                        );
                         RequestStatsBuilder::default()
                             .bytes_processed(total_size)
-                            .status(status)
-                            .is_success(success && !body_error)
-                            .duration(Instant::now().duration_since(start))
-                            .fatal_error(fatal_error)
-                            .build()
-                            .expect("RequestStatsBuilder failed")
-                    }
-                    Err(e) => {
-                        error!("H3 recv_response error: {}", e);
-                        RequestStatsBuilder::default()
-                            .bytes_processed(0)
-                            .status(e.to_string())
-                            .is_success(false)
+                            .status(outcome.status)
+                            .is_success(outcome.is_success)
                             .duration(Instant::now().duration_since(start))
-                            .fatal_error(false)
+                            .operation_name(target.name.clone())
+                            .fatal_error(outcome.fatal_error)
                             .build()
                             .expect("RequestStatsBuilder failed")
                     }
+                    Err(e) => RequestStatsBuilder::default()
+                        .bytes_processed(0)
+                        .status(ErrorCategory::classify(&e).status(&e))
+                        .is_success(false)
+                        .duration(Instant::now().duration_since(start))
+                        .operation_name(target.name.clone())
+                        .fatal_error(false)
+                        .build()
+                        .expect("RequestStatsBuilder failed"),
                 }
             }
             Err(e) => {
-                error!("H3 send_request error: {}", e);
-                let status = e.to_string();
+                let status = ErrorCategory::classify(&e).status(&e);
                 RequestStatsBuilder::default()
                     .bytes_processed(0)
                     .status(status)
                     .is_success(false)
                     .duration(Instant::now().duration_since(start))
+                    .operation_name(target.name.clone())
                     .fatal_error(false)
                     .build()
                     .expect("RequestStatsBuilder failed")
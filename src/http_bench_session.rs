@@ -1,4 +1,7 @@
+use crate::assertion::Assertions;
 use crate::bench_run::BenchmarkProtocolAdapter;
+use crate::error_category::ErrorCategory;
+use crate::feeder::{Feeder, Row};
 /// Copyright 2020 Developers of the perf-gauge project.
 ///
 /// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
@@ -6,15 +9,19 @@ use crate::bench_run::BenchmarkProtocolAdapter;
 /// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 /// option. This file may not be copied, modified, or distributed
 /// except according to those terms.
+#[cfg(feature = "tls")]
+use crate::http_timing::TcpTimer;
+use crate::http_timing::{ConnectTimer, ConnectTiming, TimedResolver};
 use crate::metrics::{RequestStats, RequestStatsBuilder};
+use crate::selection::{split_weight, Selection, Selector};
+use crate::template::Template;
 use async_trait::async_trait;
 #[cfg(feature = "tls-boring")]
 use boring::ssl::{SslConnector, SslMethod};
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use core::fmt;
 use derive_builder::Builder;
 use http_body_util::{BodyExt, Full};
-use hyper::header::{HeaderName, HeaderValue};
 use hyper::{Method, Request};
 #[cfg(feature = "tls-boring")]
 use hyper_boring::HttpsConnector;
@@ -24,8 +31,10 @@ use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioExecutor;
 use log::error;
-use rand::{thread_rng, Rng};
 use serde::Deserialize;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 use std::time::Instant;
 #[cfg(feature = "tls-native")]
@@ -44,21 +53,145 @@ pub struct HttpClientConfig {
     pub stop_on_errors: Vec<u16>,
 }
 
+/// A target URL, optionally named (`name=url`) to report its metrics as a separate operation,
+/// and weighted (`url#weight`) relative to other targets.
+/// The method, headers and body of the request can be overridden for the target.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HttpTarget {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub url: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default, deserialize_with = "deserialize_optional_method")]
+    pub method: Option<Method>,
+    /// Replace the common headers with the same name.
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub body: Option<Bytes>,
+    /// Success criteria of the responses, besides a `2xx` status.
+    #[serde(skip)]
+    pub assertions: Assertions,
+    /// Parsed by [`HttpRequestBuilder::build`].
+    #[serde(skip)]
+    pub(crate) templates: TargetTemplates,
+}
+
+/// Templates of the URL, the header values and the body of a target, if they have placeholders.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct TargetTemplates {
+    url: Option<Template>,
+    headers: Vec<Option<Template>>,
+    body: Option<Template>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn deserialize_optional_method<'de, D>(deserializer: D) -> Result<Option<Method>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_method(deserializer).map(Some)
+}
+
+/// Deserialized through [`HttpRequestBuilder::build`], so the templates are parsed as well.
 #[derive(Builder, Deserialize, Clone)]
-#[builder(build_fn(validate = "Self::validate"))]
+#[builder(
+    build_fn(private, name = "build_unparsed", validate = "Self::validate"),
+    derive(Deserialize)
+)]
+#[serde(try_from = "HttpRequestBuilder")]
 pub struct HttpRequest {
-    url: Vec<String>,
+    targets: Vec<HttpTarget>,
     #[builder(default = "Method::GET")]
-    #[serde(deserialize_with = "deserialize_method", default = "default_method")]
+    #[builder_field_attr(serde(default, deserialize_with = "deserialize_optional_method"))]
     method: Method,
     #[builder(default)]
+    #[builder_field_attr(serde(default))]
     headers: Vec<(String, Vec<String>)>,
+    /// Weights of the header values, by header. Equal weights if not set.
     #[builder(default)]
+    #[builder_field_attr(serde(default))]
+    header_weights: Vec<Vec<u32>>,
+    #[builder(default)]
+    #[builder_field_attr(serde(default))]
     body: Bytes,
+    #[builder(default)]
+    #[builder_field_attr(serde(default))]
+    selection: Selection,
+    /// Selectors of the target and of each header value, shared by clones.
+    #[builder(setter(skip))]
+    #[builder_field_attr(serde(skip))]
+    selectors: Arc<OnceLock<Vec<Selector>>>,
+    /// Templates of the common header values and body, parsed by [`HttpRequestBuilder::build`].
+    #[builder(setter(skip))]
+    #[builder_field_attr(serde(skip))]
+    templates: Arc<RequestTemplates>,
+    /// The `{{counter}}` of the run, shared by clones.
+    #[builder(setter(skip))]
+    #[builder_field_attr(serde(skip))]
+    counter: Arc<AtomicU64>,
+    /// Rows with the values of the `{{column}}` placeholders.
+    #[builder(default)]
+    #[builder_field_attr(serde(skip))]
+    feeder: Option<Arc<Feeder>>,
+    /// The number of clients built, to give each one an id.
+    #[builder(setter(skip))]
+    #[builder_field_attr(serde(skip))]
+    clients: Arc<AtomicUsize>,
 }
 
-fn default_method() -> Method {
-    Method::GET
+/// Templates of the common header values (by header) and body, if they have placeholders.
+#[derive(Default)]
+struct RequestTemplates {
+    headers: Vec<Vec<Option<Template>>>,
+    body: Option<Template>,
+    /// If any part of any request has placeholders, so the `{{counter}}` is counted.
+    any: bool,
+}
+
+/// Why the next request cannot be sent.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RequestError {
+    /// Each feeder row is used, so the run stops.
+    FeederExhausted,
+    /// E.g. a URL with a feeder value, which is not valid.
+    Invalid(String),
+}
+
+impl RequestError {
+    /// Stats of the request, which is not sent. Running out of feeder rows is fatal.
+    pub(crate) fn stats(&self, operation_name: Option<String>) -> RequestStats {
+        RequestStatsBuilder::default()
+            .bytes_processed(0)
+            .status(self.to_string())
+            .is_success(false)
+            .duration(Duration::ZERO)
+            .operation_name(operation_name)
+            .fatal_error(*self == RequestError::FeederExhausted)
+            .build()
+            .expect("RequestStatsBuilder failed")
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::FeederExhausted => write!(f, "feeder exhausted"),
+            RequestError::Invalid(reason) => write!(f, "invalid request: {reason}"),
+        }
+    }
+}
+
+/// The next request, with the target and header values chosen and the placeholders rendered.
+pub struct RequestParts<'a> {
+    pub url: Cow<'a, str>,
+    pub method: Method,
+    pub headers: Vec<(&'a str, Cow<'a, str>)>,
+    pub body: Bytes,
 }
 
 fn deserialize_method<'de, D>(deserializer: D) -> Result<Method, D::Error>
@@ -76,27 +209,50 @@ pub struct HttpBenchAdapter {
 }
 
 #[cfg(feature = "tls")]
-type ProtocolConnector = HttpsConnector<HttpConnector>;
+type ProtocolConnector = ConnectTimer<HttpsConnector<TcpTimer<HttpConnector<TimedResolver>>>>;
 #[cfg(not(feature = "tls"))]
-type ProtocolConnector = HttpConnector;
+type ProtocolConnector = ConnectTimer<HttpConnector<TimedResolver>>;
+
+/// A connection pool of a client, with the id of the client (e.g. for partitioned feeders).
+pub struct HttpClient {
+    pub(crate) pool: Client<ProtocolConnector, Full<Bytes>>,
+    pub(crate) id: usize,
+}
 
 impl HttpBenchAdapter {
+    /// Builds the next request of the `client`, e.g. for adapters on top of HTTP.
+    pub(crate) fn build_request(
+        &self,
+        client: &HttpClient,
+    ) -> Result<Request<Full<Bytes>>, RequestError> {
+        self.request
+            .build_request(self.request.choose_target(), client.id)
+    }
+
     #[cfg(not(feature = "tls"))]
     fn build_connector(&self) -> ProtocolConnector {
-        self.build_http_connector()
+        ConnectTimer(self.build_http_connector())
     }
 
     #[cfg(feature = "tls-native")]
     fn build_connector(&self) -> ProtocolConnector {
-        HttpsConnector::from((self.build_http_connector(), self.build_tls_connector()))
+        ConnectTimer(HttpsConnector::from((
+            TcpTimer(self.build_http_connector()),
+            self.build_tls_connector(),
+        )))
     }
 
     #[cfg(feature = "tls-boring")]
     fn build_connector(&self) -> ProtocolConnector {
         let builder =
             SslConnector::builder(SslMethod::tls()).expect("Cannot build BoringSSL builder");
-        hyper_boring::HttpsConnector::with_connector(self.build_http_connector(), builder)
-            .expect("Cannot build Boring HttpsConnector")
+        ConnectTimer(
+            hyper_boring::HttpsConnector::with_connector(
+                TcpTimer(self.build_http_connector()),
+                builder,
+            )
+            .expect("Cannot build Boring HttpsConnector"),
+        )
     }
 
     #[cfg(feature = "tls-native")]
@@ -113,8 +269,8 @@ impl HttpBenchAdapter {
         )
     }
 
-    fn build_http_connector(&self) -> HttpConnector {
-        let mut connector = HttpConnector::new();
+    fn build_http_connector(&self) -> HttpConnector<TimedResolver> {
+        let mut connector = HttpConnector::new_with_resolver(TimedResolver::default());
         connector.set_connect_timeout(Some(Duration::from_secs(10)));
         connector.set_nodelay(true);
         #[cfg(feature = "tls")]
@@ -125,65 +281,95 @@ impl HttpBenchAdapter {
 
 #[async_trait]
 impl BenchmarkProtocolAdapter for HttpBenchAdapter {
-    type Client = Client<ProtocolConnector, Full<Bytes>>;
+    type Client = HttpClient;
 
     async fn build_client(&self) -> Result<Self::Client, String> {
-        Ok(Client::builder(TokioExecutor::new())
-            .http2_only(self.config.http2_only)
-            .pool_max_idle_per_host(if !self.config.conn_reuse {
-                0
-            } else {
-                usize::MAX
-            })
-            .build(self.build_connector()))
+        Ok(HttpClient {
+            pool: Client::builder(TokioExecutor::new())
+                .http2_only(self.config.http2_only)
+                .pool_max_idle_per_host(if !self.config.conn_reuse {
+                    0
+                } else {
+                    usize::MAX
+                })
+                .build(self.build_connector()),
+            id: self.request.register_client(),
+        })
     }
 
     async fn send_request(&self, client: &Self::Client) -> RequestStats {
         let start = Instant::now();
-        let request = self.request.build_request();
-        let response = client.request(request).await;
+        let target = self.request.choose_target();
+        let request = match self.request.build_request(target, client.id) {
+            Ok(request) => request,
+            Err(e) => return e.stats(target.name.clone()),
+        };
+        let response = client.pool.request(request).await;
 
         match response {
             Ok(r) => {
-                let status = r.status().to_string();
-                let success = r.status().is_success();
-
-                let fatal_error =
-                    !success && self.config.stop_on_errors.contains(&r.status().as_u16());
-
-                let mut body = r.into_body();
+                let headers_received = Instant::now();
+                let mut phases = r
+                    .extensions()
+                    .get::<ConnectTiming>()
+                    .and_then(ConnectTiming::take)
+                    .unwrap_or_default();
+                let connecting = phases.dns.unwrap_or_default()
+                    + phases.connect.unwrap_or_default()
+                    + phases.tls.unwrap_or_default();
+                phases.ttfb = headers_received
+                    .duration_since(start)
+                    .saturating_sub(connecting);
+                let assertions = &target.assertions;
+                let (parts, mut body) = r.into_parts();
                 let mut total_size = 0;
-                let mut body_error = false;
+                let mut body_error = None;
+                let mut kept_body = BytesMut::new();
+                let keep_body = assertions.needs_body();
                 while let Some(frame_result) = body.frame().await {
                     match frame_result {
                         Ok(frame) => {
                             if let Some(data) = frame.data_ref() {
                                 total_size += data.len();
+                                if keep_body {
+                                    kept_body.extend_from_slice(data);
+                                }
                             }
                         }
-                        Err(_) => {
-                            body_error = true;
+                        Err(e) => {
+                            body_error = Some(e);
                             break;
                         }
                     }
                 }
+                let outcome = assertions.outcome(
+                    parts.status,
+                    &self.config.stop_on_errors,
+                    &parts.headers,
+                    &kept_body,
+                    total_size,
+                    body_error.as_ref().map(|e| e as &dyn fmt::Display),
+                );
+                phases.body = headers_received.elapsed();
                 RequestStatsBuilder::default()
                     .bytes_processed(total_size)
-                    .status(status)
-                    .is_success(success && !body_error)
+                    .status(outcome.status)
+                    .is_success(outcome.is_success)
                     .duration(Instant::now().duration_since(start))
-                    .fatal_error(fatal_error)
+                    .phases(Some(phases))
+                    .operation_name(target.name.clone())
+                    .fatal_error(outcome.fatal_error)
                     .build()
                     .expect("RequestStatsBuilder failed")
             }
             Err(e) => {
-                error!("Error sending request: {}", e);
-                let status = e.to_string();
+                let status = ErrorCategory::classify(&e).status(&e);
                 RequestStatsBuilder::default()
                     .bytes_processed(0)
                     .status(status)
                     .is_success(false)
                     .duration(Instant::now().duration_since(start))
+                    .operation_name(target.name.clone())
                     .fatal_error(false)
                     .build()
                     .expect("RequestStatsBuilder failed")
@@ -192,75 +378,267 @@ impl BenchmarkProtocolAdapter for HttpBenchAdapter {
     }
 }
 
-impl HttpRequest {
-    fn build_request(&self) -> Request<Full<Bytes>> {
-        let uri = &self.url[thread_rng().gen_range(0..self.url.len())];
-        let mut request_builder = Request::builder()
-            .method(self.method.clone())
-            .uri(uri.as_str());
-
-        if !self.headers.is_empty() {
-            for (key, value) in self.headers.iter() {
-                request_builder = request_builder.header(
-                    HeaderName::from_bytes(key.as_bytes())
-                        .expect("Header name must be valid at this point"),
-                    HeaderValue::from_str(&value[thread_rng().gen_range(0..value.len())])
-                        .expect("Header value must be valid at this point"),
-                );
+impl HttpTarget {
+    /// Parses `[name=]url[#weight]`. A `=` is a part of the URL if it follows `/` or `?` (e.g. in the query).
+    pub fn parse(target: &str) -> Result<HttpTarget, String> {
+        let (target, weight) = split_weight(target)?;
+        let (name, url) = match target.split_once('=') {
+            Some((name, url)) if !name.is_empty() && !name.contains(['/', '?']) => {
+                (Some(name.to_string()), url)
             }
+            _ => (None, target),
+        };
+        Ok(HttpTarget {
+            name,
+            url: url.to_string(),
+            weight,
+            method: None,
+            headers: vec![],
+            body: None,
+            assertions: Assertions::default(),
+            templates: TargetTemplates::default(),
+        })
+    }
+}
+
+impl HttpRequest {
+    /// The target for the next request.
+    pub fn choose_target(&self) -> &HttpTarget {
+        &self.targets[self.selectors()[0].next()]
+    }
+
+    /// Header names with the values (and their templates) for the next request to `target`.
+    fn choose_headers<'a>(
+        &'a self,
+        target: &'a HttpTarget,
+    ) -> impl Iterator<Item = (&'a str, &'a str, Option<&'a Template>)> {
+        let selectors = &self.selectors()[1..];
+        self.headers
+            .iter()
+            .zip(selectors)
+            .zip(&self.templates.headers)
+            .filter(|(((name, _), _), _)| {
+                !target
+                    .headers
+                    .iter()
+                    .any(|(overridden, _)| overridden.eq_ignore_ascii_case(name))
+            })
+            .map(|(((name, values), selector), templates)| {
+                let i = selector.next();
+                (name.as_str(), values[i].as_str(), templates[i].as_ref())
+            })
+            .chain(target.headers.iter().zip(&target.templates.headers).map(
+                |((name, value), template)| (name.as_str(), value.as_str(), template.as_ref()),
+            ))
+    }
+
+    fn selectors(&self) -> &[Selector] {
+        self.selectors.get_or_init(|| {
+            let targets = self.targets.iter().map(|target| target.weight).collect();
+            let headers = self.headers.iter().enumerate().map(|(i, (_, values))| {
+                self.header_weights
+                    .get(i)
+                    .cloned()
+                    .unwrap_or_else(|| vec![1; values.len()])
+            });
+            std::iter::once(targets)
+                .chain(headers)
+                .enumerate()
+                .map(|(i, weights)| Selector::new(weights, &self.selection, i as u64))
+                .collect()
+        })
+    }
+
+    /// Parses the templates of all parts of the requests, to be rendered for each one.
+    fn parse_templates(&mut self) -> Result<(), String> {
+        let parse = |value: &str| {
+            Template::parse(value)
+                .map(|template| (!template.is_static()).then_some(template))
+                .map_err(|reason| format!("Invalid template `{value}`: {reason}"))
+        };
+        // binary (e.g. random) payloads are sent as is
+        let parse_body = |body: &Bytes| {
+            std::str::from_utf8(body)
+                .ok()
+                .and_then(|body| Template::parse(body).ok())
+                .filter(|template| !template.is_static())
+        };
+
+        for target in &mut self.targets {
+            target.templates = TargetTemplates {
+                url: parse(&target.url)?,
+                headers: target
+                    .headers
+                    .iter()
+                    .map(|(_, value)| parse(value))
+                    .collect::<Result<_, _>>()?,
+                body: target.body.as_ref().and_then(parse_body),
+            };
         }
+        let headers: Vec<Vec<Option<Template>>> = self
+            .headers
+            .iter()
+            .map(|(_, values)| values.iter().map(|value| parse(value)).collect())
+            .collect::<Result<_, _>>()?;
+        let body = parse_body(&self.body);
+
+        let any = body.is_some()
+            || headers.iter().flatten().any(Option::is_some)
+            || self.targets.iter().any(|target| {
+                let templates = &target.templates;
+                templates.url.is_some()
+                    || templates.body.is_some()
+                    || templates.headers.iter().any(Option::is_some)
+            });
+        self.templates = Arc::new(RequestTemplates { headers, body, any });
+        Ok(())
+    }
 
-        if !self.body.is_empty() {
-            request_builder
-                .body(Full::new(self.body.clone()))
-                .expect("Error building Request")
+    /// An id for a new client.
+    pub fn register_client(&self) -> usize {
+        self.clients.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The parts of the next request of the client `client_id` to `target`.
+    fn render_parts<'a>(
+        &'a self,
+        target: &'a HttpTarget,
+        client_id: usize,
+    ) -> Result<RequestParts<'a>, RequestError> {
+        let row: Option<Row> = match &self.feeder {
+            Some(feeder) => Some(
+                feeder
+                    .next_row(client_id)
+                    .ok_or(RequestError::FeederExhausted)?,
+            ),
+            None => None,
+        };
+        let counter = if self.templates.any {
+            self.counter.fetch_add(1, Ordering::Relaxed) + 1
         } else {
-            request_builder
-                .body(Full::new(Bytes::new()))
-                .map_err(|e| {
-                    error!(
-                        "Cannot create url {}, headers: {:?}. Error: {}",
-                        uri, self.headers, e
-                    );
-                })
-                .expect("Error building Request")
+            0
+        };
+        let render = |value: &'a str, template: Option<&Template>| match template {
+            Some(template) => Cow::Owned(template.render(counter, row)),
+            None => Cow::Borrowed(value),
+        };
+
+        let (body, body_template) = match &target.body {
+            Some(body) => (body, target.templates.body.as_ref()),
+            None => (&self.body, self.templates.body.as_ref()),
+        };
+        Ok(RequestParts {
+            url: render(&target.url, target.templates.url.as_ref()),
+            method: target.method.as_ref().unwrap_or(&self.method).clone(),
+            headers: self
+                .choose_headers(target)
+                .map(|(name, value, template)| (name, render(value, template)))
+                .collect(),
+            body: match body_template {
+                Some(template) => Bytes::from(template.render(counter, row)),
+                None => body.clone(),
+            },
+        })
+    }
+
+    fn build_request(
+        &self,
+        target: &HttpTarget,
+        client_id: usize,
+    ) -> Result<Request<Full<Bytes>>, RequestError> {
+        let parts = self.render_parts(target, client_id)?;
+        let mut request_builder = Request::builder()
+            .method(parts.method)
+            .uri(parts.url.as_ref());
+
+        for (key, value) in &parts.headers {
+            request_builder = request_builder.header(*key, value.as_ref());
         }
+
+        // placeholders may render invalid values, e.g. feeder ones
+        request_builder.body(Full::new(parts.body)).map_err(|e| {
+            error!(
+                "Cannot create url {}, headers: {:?}. Error: {}",
+                parts.url, parts.headers, e
+            );
+            RequestError::Invalid(e.to_string())
+        })
     }
 
     /// Get the first URL (for connection establishment in HTTP/3).
     #[cfg(feature = "http3")]
     pub fn first_url(&self) -> &str {
-        &self.url[0]
+        &self.targets[0].url
     }
 
-    /// Get request parts for building protocol-specific requests.
-    /// Returns (url, method, headers, body) with a random URL and random header values selected.
+    /// Get request parts to `target` for building protocol-specific requests,
+    /// with the header values selected and the placeholders rendered.
     #[cfg(feature = "http3")]
-    pub fn request_parts(&self) -> (&str, Method, Vec<(&str, &str)>, Bytes) {
-        use rand::{thread_rng, Rng};
-
-        let url = &self.url[thread_rng().gen_range(0..self.url.len())];
-        let method = self.method.clone();
-
-        let headers: Vec<(&str, &str)> = self
-            .headers
-            .iter()
-            .map(|(key, values)| {
-                let value = &values[thread_rng().gen_range(0..values.len())];
-                (key.as_str(), value.as_str())
-            })
-            .collect();
+    pub fn request_parts<'a>(
+        &'a self,
+        target: &'a HttpTarget,
+        client_id: usize,
+    ) -> Result<RequestParts<'a>, RequestError> {
+        self.render_parts(target, client_id)
+    }
+}
 
-        let body = self.body.clone();
+impl TryFrom<HttpRequestBuilder> for HttpRequest {
+    type Error = HttpRequestBuilderError;
 
-        (url, method, headers, body)
+    fn try_from(builder: HttpRequestBuilder) -> Result<Self, Self::Error> {
+        builder.build()
     }
 }
 
 impl HttpRequestBuilder {
+    /// Fails on invalid settings, e.g. a URL with an unclosed placeholder.
+    pub fn build(&self) -> Result<HttpRequest, HttpRequestBuilderError> {
+        let mut request = self.build_unparsed()?;
+        request
+            .parse_templates()
+            .map_err(HttpRequestBuilderError::ValidationError)?;
+        Ok(request)
+    }
+
+    /// Unnamed targets.
+    pub fn url(&mut self, url: Vec<String>) -> &mut Self {
+        self.targets(
+            url.into_iter()
+                .map(|url| HttpTarget {
+                    name: None,
+                    url,
+                    weight: 1,
+                    method: None,
+                    headers: vec![],
+                    body: None,
+                    assertions: Assertions::default(),
+                    templates: TargetTemplates::default(),
+                })
+                .collect(),
+        )
+    }
+
     /// Validate request is going to be built from the given settings
     fn validate(&self) -> Result<(), String> {
         // Method is already parsed as Method type, so validation is done at construction time
+        if self.targets.as_ref().is_some_and(Vec::is_empty) {
+            return Err("At least one target is required".to_string());
+        }
+        if let Some(targets) = &self.targets {
+            if targets.iter().all(|target| target.weight == 0) {
+                return Err("At least one target must have a positive weight".to_string());
+            }
+        }
+        if let (Some(headers), Some(weights)) = (&self.headers, &self.header_weights) {
+            let matching = weights.len() <= headers.len()
+                && headers.iter().zip(weights).all(|((_, values), weights)| {
+                    weights.len() == values.len() && weights.iter().any(|w| *w > 0)
+                });
+            if !matching {
+                return Err("Each header value must have a weight".to_string());
+            }
+        }
         Ok(())
     }
 }
@@ -269,12 +647,13 @@ impl fmt::Display for HttpRequest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(
             f,
-            "Requests={}, first request={}, method={}, headers={:?}, body size={}",
-            self.url.len(),
-            self.url[0],
+            "Requests={}, first request={}, method={}, headers={:?}, body size={}, selection={:?}",
+            self.targets.len(),
+            self.targets[0].url,
             self.method.as_str(),
             self.headers,
-            self.body.len()
+            self.body.len(),
+            self.selection
         )
     }
 }
@@ -287,13 +666,19 @@ impl fmt::Display for HttpBenchAdapter {
 
 #[cfg(test)]
 mod tests {
+    use crate::assertion::{Assertion, Assertions};
     use crate::bench_run::BenchmarkProtocolAdapter;
+    use crate::feeder::{Feeder, FeederOrder};
     use crate::http_bench_session::{
         HttpBenchAdapter, HttpBenchAdapterBuilder, HttpClientConfigBuilder, HttpRequestBuilder,
+        HttpTarget, TargetTemplates,
     };
+    use crate::selection::Selection;
     use bytes::Bytes;
+    use hyper::body::Body;
     use hyper::Method;
-    use mockito::Matcher::Exact;
+    use mockito::Matcher::{Exact, Regex};
+    use std::sync::Arc;
     use std::time::Duration;
     use tokio::time::timeout;
 
@@ -337,6 +722,52 @@ mod tests {
         assert_eq!("200 OK".to_string(), stats.status);
     }
 
+    #[tokio::test]
+    async fn test_deserialized_request() {
+        let mut server = mockito::Server::new_async().await;
+
+        let _m = server
+            .mock("POST", "/1")
+            .match_header("x-header", "value1")
+            .match_header("x-target-header", "value2")
+            .match_body(Exact("request 1".to_string()))
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let url = server.url();
+        let http_bench: HttpBenchAdapter = serde_json::from_value(serde_json::json!({
+            "config": {
+                "ignore_cert": false,
+                "conn_reuse": false,
+                "http2_only": false,
+                "stop_on_errors": [],
+            },
+            "request": {
+                "targets": [{"url": format!("{url}/1"), "headers": [["x-target-header", "value2"]]}],
+                "method": "POST",
+                "headers": [["x-header", ["value1"]]],
+                "body": "request {{counter}}",
+            },
+        }))
+        .expect("Valid request");
+
+        let client = http_bench.build_client().await.expect("Client is built");
+        let stats = http_bench.send_request(&client).await;
+        assert_eq!("200 OK".to_string(), stats.status);
+
+        let invalid = serde_json::from_value::<HttpBenchAdapter>(serde_json::json!({
+            "config": {
+                "ignore_cert": false,
+                "conn_reuse": false,
+                "http2_only": false,
+                "stop_on_errors": [],
+            },
+            "request": {"targets": [{"url": "http://localhost/{{uuid"}]},
+        }));
+        assert!(invalid.is_err());
+    }
+
     #[tokio::test]
     async fn test_success_put_request() {
         let body = "world";
@@ -509,6 +940,81 @@ mod tests {
         assert_eq!("500 Internal Server Error".to_string(), stats.status);
     }
 
+    #[tokio::test]
+    async fn test_latency_phases() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/1")
+            .with_status(200)
+            .with_body("hello")
+            .create_async()
+            .await;
+
+        let url = server.url();
+        let http_bench = HttpBenchAdapterBuilder::default()
+            .request(
+                HttpRequestBuilder::default()
+                    .url(vec![format!("{url}/1")])
+                    .build()
+                    .unwrap(),
+            )
+            .config(HttpClientConfigBuilder::default().build().unwrap())
+            .build()
+            .unwrap();
+
+        let client = http_bench.build_client().await.expect("Client is built");
+        let phases = http_bench.send_request(&client).await.phases.unwrap();
+        // mockito listens on an IP address, nothing to resolve
+        assert_eq!(None, phases.dns);
+        assert!(phases.connect.is_some());
+        assert_eq!(None, phases.tls);
+        assert!(phases.ttfb > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_failed_assertion() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/status")
+            .with_status(200)
+            .with_body(r#"{"status": "error"}"#)
+            .create_async()
+            .await;
+
+        let url = server.url();
+        let target = |value: &str| HttpTarget {
+            assertions: Assertions {
+                status: vec![200],
+                checks: vec![Assertion::Json {
+                    pointer: "/status".to_string(),
+                    value: serde_json::json!(value),
+                }],
+                fatal: true,
+            },
+            ..HttpTarget::parse(&format!("{url}/status")).unwrap()
+        };
+        for (expected, success) in [("ok", false), ("error", true)] {
+            let http_bench = HttpBenchAdapterBuilder::default()
+                .request(
+                    HttpRequestBuilder::default()
+                        .targets(vec![target(expected)])
+                        .build()
+                        .unwrap(),
+                )
+                .config(HttpClientConfigBuilder::default().build().unwrap())
+                .build()
+                .unwrap();
+
+            let client = http_bench.build_client().await.expect("Client is built");
+            let stats = http_bench.send_request(&client).await;
+            assert_eq!(success, stats.is_success, "{stats:?}");
+            assert_eq!(!success, stats.fatal_error);
+            if !success {
+                assert_eq!("assert:json_path", stats.status);
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_only_http2() {
         let body = "world";
@@ -552,4 +1058,236 @@ mod tests {
             "Expected to fail as h2 is not supported by the endpoint"
         );
     }
+
+    #[tokio::test]
+    async fn test_named_target() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/small")
+            .with_status(200)
+            .with_body("hello")
+            .create_async()
+            .await;
+
+        let url = server.url();
+        let http_bench = HttpBenchAdapterBuilder::default()
+            .request(
+                HttpRequestBuilder::default()
+                    .targets(vec![
+                        HttpTarget::parse(&format!("small={url}/small")).unwrap()
+                    ])
+                    .build()
+                    .unwrap(),
+            )
+            .config(HttpClientConfigBuilder::default().build().unwrap())
+            .build()
+            .unwrap();
+
+        let client = http_bench.build_client().await.expect("Client is built");
+        let stats = http_bench.send_request(&client).await;
+        assert!(stats.is_success);
+        assert_eq!(Some("small".to_string()), stats.operation_name);
+    }
+
+    #[tokio::test]
+    async fn test_templated_request() {
+        let mut server = mockito::Server::new_async().await;
+        let m = server
+            .mock("POST", Regex(r"^/items/[1-3]\?id=\d+$".to_string()))
+            .match_header("x-request-id", Regex("^[0-9a-f-]{36}$".to_string()))
+            .match_body(Regex(r#"^\{"name": "[a-zA-Z0-9]{8}"\}$"#.to_string()))
+            .with_status(201)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let url = server.url();
+        let http_bench = HttpBenchAdapterBuilder::default()
+            .request(
+                HttpRequestBuilder::default()
+                    .url(vec![format!(
+                        "{url}/items/{{{{counter}}}}?id={{{{timestamp}}}}"
+                    )])
+                    .method(Method::POST)
+                    .headers(vec![(
+                        "x-request-id".to_string(),
+                        vec!["{{uuid}}".to_string()],
+                    )])
+                    .body(Bytes::from_static(br#"{"name": "{{random_string 8}}"}"#))
+                    .build()
+                    .unwrap(),
+            )
+            .config(HttpClientConfigBuilder::default().build().unwrap())
+            .build()
+            .unwrap();
+
+        let client = http_bench.build_client().await.expect("Client is built");
+        for _ in 0..3 {
+            let stats = http_bench.send_request(&client).await;
+            assert!(stats.is_success, "{stats:?}");
+        }
+        m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_feeder() {
+        let mut server = mockito::Server::new_async().await;
+        let m = server
+            .mock("GET", Regex("^/users/[12]$".to_string()))
+            .match_header("x-query", Regex("^(shoes|red hat)$".to_string()))
+            .with_status(200)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let feeder = Feeder::new(
+            vec!["user".to_string(), "query".to_string()],
+            vec![
+                vec!["1".to_string(), "shoes".to_string()],
+                vec!["2".to_string(), "red hat".to_string()],
+            ],
+            FeederOrder::Sequential,
+            true,
+            1,
+        )
+        .unwrap();
+        let url = server.url();
+        let http_bench = HttpBenchAdapterBuilder::default()
+            .request(
+                HttpRequestBuilder::default()
+                    .url(vec![format!("{url}/users/{{{{user}}}}")])
+                    .headers(vec![("x-query".to_string(), vec!["{{query}}".to_string()])])
+                    .feeder(Some(Arc::new(feeder)))
+                    .build()
+                    .unwrap(),
+            )
+            .config(HttpClientConfigBuilder::default().build().unwrap())
+            .build()
+            .unwrap();
+
+        let client = http_bench.build_client().await.expect("Client is built");
+        for _ in 0..2 {
+            let stats = http_bench.send_request(&client).await;
+            assert!(stats.is_success, "{stats:?}");
+        }
+        let stats = http_bench.send_request(&client).await;
+        assert!(!stats.is_success);
+        assert!(stats.fatal_error);
+        assert_eq!("feeder exhausted", stats.status);
+        m.assert_async().await;
+    }
+
+    #[test]
+    fn test_target_overrides() {
+        let create = HttpTarget {
+            method: Some(Method::POST),
+            headers: vec![("X-Header".to_string(), "create".to_string())],
+            body: Some(Bytes::from_static(b"{}")),
+            ..HttpTarget::parse("create=http://localhost/items").unwrap()
+        };
+        let request = HttpRequestBuilder::default()
+            .targets(vec![
+                HttpTarget::parse("read=http://localhost/items/1").unwrap(),
+                create.clone(),
+            ])
+            .headers(vec![
+                ("x-header".to_string(), vec!["common".to_string()]),
+                ("x-other".to_string(), vec!["other".to_string()]),
+            ])
+            .build()
+            .unwrap();
+
+        let read = request.build_request(&request.targets[0], 0).unwrap();
+        assert_eq!(Method::GET, read.method());
+        assert_eq!("common", read.headers()["x-header"]);
+        assert_eq!(Some(0), read.body().size_hint().exact());
+
+        let create = request.build_request(&request.targets[1], 0).unwrap();
+        assert_eq!(Method::POST, create.method());
+        assert_eq!(1, create.headers().get_all("x-header").iter().count());
+        assert_eq!("create", create.headers()["x-header"]);
+        assert_eq!("other", create.headers()["x-other"]);
+        assert_eq!(Some(2), create.body().size_hint().exact());
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            Ok(HttpTarget {
+                name: Some("8kb".to_string()),
+                url: "http://localhost/8kb?a=b".to_string(),
+                weight: 20,
+                method: None,
+                headers: vec![],
+                body: None,
+                assertions: Assertions::default(),
+                templates: TargetTemplates::default(),
+            }),
+            HttpTarget::parse("8kb=http://localhost/8kb?a=b#20")
+        );
+        for url in ["http://localhost/8kb?a=b", "=http://localhost/"] {
+            assert_eq!(
+                Ok(HttpTarget {
+                    name: None,
+                    url: url.to_string(),
+                    weight: 1,
+                    method: None,
+                    headers: vec![],
+                    body: None,
+                    assertions: Assertions::default(),
+                    templates: TargetTemplates::default(),
+                }),
+                HttpTarget::parse(url)
+            );
+        }
+        assert!(HttpTarget::parse("http://localhost/#0").is_err());
+        assert!(HttpRequestBuilder::default().url(vec![]).build().is_err());
+        // templates are parsed by the builder
+        assert!(HttpRequestBuilder::default()
+            .url(vec!["http://localhost/{{uuid".to_string()])
+            .build()
+            .is_err());
+        assert!(HttpRequestBuilder::default()
+            .url(vec!["http://localhost/".to_string()])
+            .headers(vec![(
+                "x-id".to_string(),
+                vec!["{{unknown 1}}".to_string()]
+            )])
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let request = HttpRequestBuilder::default()
+            .targets(vec![
+                HttpTarget::parse("read=http://localhost/read#3").unwrap(),
+                HttpTarget::parse("write=http://localhost/write").unwrap(),
+            ])
+            .headers(vec![(
+                "x-header".to_string(),
+                vec!["a".to_string(), "b".to_string()],
+            )])
+            .header_weights(vec![vec![1, 1]])
+            .selection(Selection::RoundRobin)
+            .build()
+            .unwrap();
+
+        let mut targets = vec![];
+        let mut headers = vec![];
+        for _ in 0..4 {
+            targets.push(request.choose_target().name.clone().unwrap());
+            let (_, value, _) = request.choose_headers(&request.targets[0]).next().unwrap();
+            headers.push(value.to_string());
+        }
+        assert_eq!(vec!["read", "read", "write", "read"], targets);
+        assert_eq!(vec!["a", "b", "a", "b"], headers);
+
+        assert!(HttpRequestBuilder::default()
+            .url(vec!["http://localhost/".to_string()])
+            .headers(vec![("x-header".to_string(), vec!["a".to_string()])])
+            .header_weights(vec![vec![1, 1]])
+            .build()
+            .is_err());
+    }
 }
@@ -0,0 +1,203 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::metrics::LatencyPhases;
+use hyper::rt::{Read, ReadBufCursor, Write};
+use hyper::Uri;
+use hyper_util::client::legacy::connect::dns::{GaiAddrs, GaiResolver, Name};
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use std::future::Future;
+use std::io;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower_service::Service;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+tokio::task_local! {
+    /// The connection being opened, for the resolver and the TCP connector underneath.
+    static CONNECTING: Arc<Connecting>;
+}
+
+struct Connecting {
+    started: Instant,
+    dns: OnceLock<Duration>,
+    tcp_connected: OnceLock<Instant>,
+}
+
+/// Connection phases of a new connection, taken by the first response on it.
+/// Attached to every response of the connection, so reused connections report no phases.
+#[derive(Clone, Debug)]
+pub struct ConnectTiming(Arc<Mutex<Option<LatencyPhases>>>);
+
+impl ConnectTiming {
+    pub fn take(&self) -> Option<LatencyPhases> {
+        self.0.lock().expect("Poisoned ConnectTiming").take()
+    }
+}
+
+/// Resolves names with [`GaiResolver`], timing the resolution of the connection being opened.
+#[derive(Clone)]
+pub struct TimedResolver(GaiResolver);
+
+impl Default for TimedResolver {
+    fn default() -> Self {
+        Self(GaiResolver::new())
+    }
+}
+
+impl Service<Name> for TimedResolver {
+    type Response = GaiAddrs;
+    type Error = io::Error;
+    type Future = BoxFuture<Result<GaiAddrs, io::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let start = Instant::now();
+        let resolving = self.0.call(name);
+        Box::pin(async move {
+            let addrs = resolving.await;
+            let _ = CONNECTING.try_with(|connecting| connecting.dns.set(start.elapsed()));
+            addrs
+        })
+    }
+}
+
+/// Marks the moment the TCP connection is established, before the TLS handshake on top of it.
+#[cfg(feature = "tls")]
+#[derive(Clone)]
+pub struct TcpTimer<C>(pub C);
+
+#[cfg(feature = "tls")]
+impl<C> Service<Uri> for TcpTimer<C>
+where
+    C: Service<Uri>,
+    C::Future: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = BoxFuture<Result<C::Response, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), C::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.0.call(uri);
+        Box::pin(async move {
+            let stream = connecting.await;
+            let _ = CONNECTING.try_with(|connecting| connecting.tcp_connected.set(Instant::now()));
+            stream
+        })
+    }
+}
+
+/// Times opening connections with the inner connector, see [`ConnectTiming`].
+#[derive(Clone)]
+pub struct ConnectTimer<C>(pub C);
+
+impl<C> Service<Uri> for ConnectTimer<C>
+where
+    C: Service<Uri>,
+    C::Future: Send + 'static,
+{
+    type Response = TimedStream<C::Response>;
+    type Error = C::Error;
+    type Future = BoxFuture<Result<Self::Response, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), C::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let is_tls = uri.scheme_str() == Some("https");
+        let connecting = Arc::new(Connecting {
+            started: Instant::now(),
+            dns: OnceLock::new(),
+            tcp_connected: OnceLock::new(),
+        });
+        let stream = CONNECTING.scope(connecting.clone(), self.0.call(uri));
+        Box::pin(async move {
+            let stream = stream.await?;
+            let connected = Instant::now();
+            let tcp_connected = *connecting.tcp_connected.get_or_init(|| connected);
+            let dns = connecting.dns.get().copied();
+            let phases = LatencyPhases {
+                dns,
+                connect: Some(
+                    tcp_connected
+                        .duration_since(connecting.started)
+                        .saturating_sub(dns.unwrap_or_default()),
+                ),
+                tls: is_tls.then(|| connected.duration_since(tcp_connected)),
+                ..LatencyPhases::default()
+            };
+            Ok(TimedStream {
+                inner: stream,
+                timing: ConnectTiming(Arc::new(Mutex::new(Some(phases)))),
+            })
+        })
+    }
+}
+
+/// A connection carrying its [`ConnectTiming`].
+pub struct TimedStream<S> {
+    inner: S,
+    timing: ConnectTiming,
+}
+
+impl<S: Connection> Connection for TimedStream<S> {
+    fn connected(&self) -> Connected {
+        self.inner.connected().extra(self.timing.clone())
+    }
+}
+
+impl<S: Read + Unpin> Read for TimedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: Write + Unpin> Write for TimedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2020 Developers of the perf-gauge project.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Benchmarking library behind the `perf-gauge` tool. It can be used to run benchmarks from
+//! other programs, e.g. perf checks in `cargo test`:
+//!
+//! ```no_run
+//! use perf_gauge::{
+//!     run_benchmark, BenchmarkConfigBuilder, BenchmarkMode, HttpBenchAdapterBuilder,
+//!     HttpClientConfigBuilder, HttpRequestBuilder, RateLadderBuilder,
+//! };
+//!
+//! # async fn run() {
+//! let config = BenchmarkConfigBuilder::default()
+//!     .concurrency(4)
+//!     .rate_ladder(
+//!         RateLadderBuilder::default()
+//!             .start(100.)
+//!             .end(100.)
+//!             .rate_increment(None)
+//!             .step_duration(None)
+//!             .step_requests(Some(1000))
+//!             .build()
+//!             .unwrap(),
+//!     )
+//!     .mode(BenchmarkMode::Http(
+//!         HttpBenchAdapterBuilder::default()
+//!             .config(HttpClientConfigBuilder::default().build().unwrap())
+//!             .request(
+//!                 HttpRequestBuilder::default()
+//!                     .url(vec!["http://localhost:8080/health".to_string()])
+//!                     .build()
+//!                     .unwrap(),
+//!             )
+//!             .build()
+//!             .unwrap(),
+//!     ))
+//!     .build()
+//!     .unwrap();
+//!
+//! run_benchmark(config, |metrics| {
+//!     let combined = metrics.combined();
+//!     assert_eq!(combined.total_requests(), combined.successful_requests());
+//! })
+//! .await
+//! .expect("Batches are run");
+//! # }
+//! ```
+//!
+//! Metrics can also be sent to custom [`ExternalMetricsServiceReporter`]s (see
+//! [`BenchmarkConfigBuilder::reporters`]), and any [`BenchmarkProtocolAdapter`] can be driven
+//! by [`BenchRun::send_load`].
+
+mod assertion;
+mod bench_run;
+mod bench_session;
+mod circuit_breaker;
+mod config_error;
+mod configuration;
+mod dns_bench_session;
+mod error_category;
+mod feeder;
+mod grpc_bench_session;
+#[cfg(feature = "http3")]
+mod h3_bench_session;
+mod http_bench_session;
+mod http_timing;
+mod load_profile;
+mod metrics;
+#[cfg(feature = "report-to-prometheus")]
+mod prometheus_reporter;
+mod protocol_registry;
+mod rate_limiter;
+mod redis_bench_session;
+mod runner;
+mod scenario_bench_session;
+mod selection;
+mod socket_session;
+mod tcp_bench_session;
+mod template;
+mod throughput_search;
+mod udp_bench_session;
+mod ws_bench_session;
+
+pub use crate::bench_run::{BatchState, BenchRun, BenchmarkProtocolAdapter, DynProtocolAdapter};
+pub use crate::bench_session::{BenchBatch, BenchSession, RateLadder, RateLadderBuilder};
+pub use crate::circuit_breaker::{Abort, CircuitBreaker, CircuitBreakerBuilder};
+pub use crate::config_error::{ConfigError, ConfigErrors};
+pub use crate::configuration::{BenchmarkConfig, BenchmarkConfigBuilder, BenchmarkMode};
+pub use crate::error_category::ErrorCategory;
+pub use crate::http_bench_session::{
+    HttpBenchAdapter, HttpBenchAdapterBuilder, HttpClientConfig, HttpClientConfigBuilder,
+    HttpRequest, HttpRequestBuilder,
+};
+pub use crate::load_profile::LoadProfile;
+pub use crate::metrics::{
+    BenchRunMetrics, BenchRunMetricsItem, ExternalMetricsServiceReporter, LatencyPhases,
+    RequestStats, RequestStatsBuilder,
+};
+pub use crate::protocol_registry::ProtocolRegistry;
+pub use crate::rate_limiter::{ArrivalProcess, RateLimiter};
+pub use crate::runner::run_benchmark;
+pub use crate::throughput_search::{
+    SloCriteria, SloCriteriaBuilder, ThroughputSearch, ThroughputSearchBuilder,
+};
@@ -0,0 +1,336 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use serde::Deserialize;
+use std::f64::consts::PI;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A load shape described as a sequence of stages, e.g.
+/// ```yaml
+/// step: 30s
+/// stages:
+///   - type: ramp
+///     from: 100
+///     to: 1000
+///     duration: 10m
+///   - type: hold
+///     rate: 1000
+///     duration: 1h
+/// ```
+/// Each stage is split into steps of `step` duration, and each step becomes a separate batch.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoadProfile {
+    #[serde(deserialize_with = "deserialize_duration", default = "default_step")]
+    step: Duration,
+    stages: Vec<LoadStage>,
+    #[serde(skip)]
+    current_stage: usize,
+    #[serde(skip)]
+    stage_elapsed: Duration,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LoadStage {
+    /// Linear change of the rate from `from` to `to`.
+    Ramp {
+        from: f64,
+        to: f64,
+        #[serde(deserialize_with = "deserialize_duration")]
+        duration: Duration,
+    },
+    /// Constant rate.
+    Hold {
+        rate: f64,
+        #[serde(deserialize_with = "deserialize_duration")]
+        duration: Duration,
+    },
+    /// A short period of a constant (usually high) rate.
+    Spike {
+        rate: f64,
+        #[serde(deserialize_with = "deserialize_duration")]
+        duration: Duration,
+    },
+    /// Linear decrease of the rate from `from` to `to`.
+    RampDown {
+        from: f64,
+        to: f64,
+        #[serde(deserialize_with = "deserialize_duration")]
+        duration: Duration,
+    },
+    /// The rate oscillates around `base` by `amplitude` with the given `period`.
+    Sine {
+        base: f64,
+        amplitude: f64,
+        #[serde(deserialize_with = "deserialize_duration")]
+        period: Duration,
+        #[serde(deserialize_with = "deserialize_duration")]
+        duration: Duration,
+    },
+}
+
+/// A single step of the load profile: requests are sent at `rate` for `duration`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadStep {
+    pub rate: f64,
+    pub duration: Duration,
+}
+
+fn default_step() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    humantime::Duration::from_str(s.as_str())
+        .map(Into::into)
+        .map_err(serde::de::Error::custom)
+}
+
+impl LoadProfile {
+    /// Reads a profile from a YAML or JSON file (by the `.json` extension).
+    pub fn from_file(filename: &str) -> Result<LoadProfile, String> {
+        let content = fs::read_to_string(filename)
+            .map_err(|e| format!("Cannot read load profile {filename}: {e}"))?;
+        if filename.ends_with(".json") {
+            LoadProfile::from_json(&content)
+        } else {
+            LoadProfile::from_yaml(&content)
+        }
+    }
+
+    pub fn from_yaml(content: &str) -> Result<LoadProfile, String> {
+        let profile: LoadProfile =
+            serde_yaml::from_str(content).map_err(|e| format!("Invalid load profile: {e}"))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    pub fn from_json(content: &str) -> Result<LoadProfile, String> {
+        let profile: LoadProfile =
+            serde_json::from_str(content).map_err(|e| format!("Invalid load profile: {e}"))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.step.is_zero() {
+            return Err("Load profile step must be positive".to_string());
+        }
+        if self.stages.is_empty() {
+            return Err("Load profile must have at least one stage".to_string());
+        }
+        // zero rate means "unlimited", so it's not allowed within a profile
+        for stage in self.stages.iter() {
+            let valid = match stage {
+                LoadStage::Ramp { from, to, .. } | LoadStage::RampDown { from, to, .. } => {
+                    *from >= 0. && *to >= 0. && from.max(*to) > 0.
+                }
+                LoadStage::Hold { rate, .. } | LoadStage::Spike { rate, .. } => *rate > 0.,
+                LoadStage::Sine {
+                    base,
+                    amplitude,
+                    period,
+                    ..
+                } => *amplitude >= 0. && base > amplitude && !period.is_zero(),
+            };
+            if !valid {
+                return Err(format!("Rates must be positive in stage {stage:?}"));
+            }
+            if stage.duration().is_zero() {
+                return Err(format!("Stage duration must be positive: {stage:?}"));
+            }
+            if let LoadStage::RampDown { from, to, .. } = stage {
+                if from < to {
+                    return Err(format!("Ramp-down must decrease the rate: {stage:?}"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The next step of the profile, or `None` if all stages are complete.
+    /// The rate of a step is the rate at its midpoint, which is the average rate of the step for ramps.
+    pub fn next_step(&mut self) -> Option<LoadStep> {
+        let stage = self.stages.get(self.current_stage)?;
+        let stage_duration = stage.duration();
+        let step_duration = self.step.min(stage_duration - self.stage_elapsed);
+        let midpoint = self.stage_elapsed + step_duration / 2;
+        let rate = stage.rate_at(midpoint);
+
+        self.stage_elapsed += step_duration;
+        if self.stage_elapsed >= stage_duration {
+            self.current_stage += 1;
+            self.stage_elapsed = Duration::ZERO;
+        }
+
+        Some(LoadStep {
+            rate,
+            duration: step_duration,
+        })
+    }
+}
+
+impl LoadStage {
+    fn duration(&self) -> Duration {
+        match self {
+            LoadStage::Ramp { duration, .. }
+            | LoadStage::Hold { duration, .. }
+            | LoadStage::Spike { duration, .. }
+            | LoadStage::RampDown { duration, .. }
+            | LoadStage::Sine { duration, .. } => *duration,
+        }
+    }
+
+    fn rate_at(&self, elapsed: Duration) -> f64 {
+        match self {
+            LoadStage::Ramp { from, to, duration } | LoadStage::RampDown { from, to, duration } => {
+                from + (to - from) * elapsed.as_secs_f64() / duration.as_secs_f64()
+            }
+            LoadStage::Hold { rate, .. } | LoadStage::Spike { rate, .. } => *rate,
+            LoadStage::Sine {
+                base,
+                amplitude,
+                period,
+                ..
+            } => base + amplitude * (2. * PI * elapsed.as_secs_f64() / period.as_secs_f64()).sin(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::load_profile::{LoadProfile, LoadStage, LoadStep};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_yaml() {
+        let profile = LoadProfile::from_yaml(
+            r#"
+step: 1m
+stages:
+  - type: ramp
+    from: 0
+    to: 100
+    duration: 2m
+  - type: spike
+    rate: 500
+    duration: 30s
+  - type: ramp_down
+    from: 100
+    to: 10
+    duration: 1m
+  - type: sine
+    base: 100
+    amplitude: 50
+    period: 4m
+    duration: 4m
+"#,
+        )
+        .expect("Valid profile");
+
+        assert_eq!(Duration::from_secs(60), profile.step);
+        assert_eq!(4, profile.stages.len());
+        assert_eq!(
+            LoadStage::Spike {
+                rate: 500.,
+                duration: Duration::from_secs(30)
+            },
+            profile.stages[1]
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let profile = LoadProfile::from_json(
+            r#"{"stages": [{"type": "hold", "rate": 10, "duration": "1h"}]}"#,
+        )
+        .expect("Valid profile");
+        assert_eq!(Duration::from_secs(10), profile.step);
+        assert_eq!(
+            LoadStage::Hold {
+                rate: 10.,
+                duration: Duration::from_secs(3600)
+            },
+            profile.stages[0]
+        );
+    }
+
+    #[test]
+    fn test_invalid_profiles() {
+        assert!(LoadProfile::from_yaml("stages: []").is_err());
+        assert!(LoadProfile::from_yaml("stages: [{type: hold, rate: 0, duration: 1m}]").is_err());
+        assert!(LoadProfile::from_yaml(
+            "stages: [{type: ramp_down, from: 10, to: 100, duration: 1m}]"
+        )
+        .is_err());
+        assert!(LoadProfile::from_yaml(
+            "stages: [{type: sine, base: 10, amplitude: 20, period: 1m, duration: 1m}]"
+        )
+        .is_err());
+        assert!(
+            LoadProfile::from_yaml("stages: [{type: hold, rate: 10, duration: forever}]").is_err()
+        );
+        assert!(LoadProfile::from_file("not_existing_file.yaml").is_err());
+    }
+
+    #[test]
+    fn test_steps() {
+        let mut profile = LoadProfile::from_yaml(
+            r#"
+step: 1m
+stages:
+  - type: ramp
+    from: 0
+    to: 100
+    duration: 2m
+  - type: hold
+    rate: 100
+    duration: 90s
+  - type: sine
+    base: 100
+    amplitude: 50
+    period: 4m
+    duration: 4m
+"#,
+        )
+        .expect("Valid profile");
+
+        let minute = Duration::from_secs(60);
+        let mut steps = vec![];
+        while let Some(step) = profile.next_step() {
+            steps.push(step);
+        }
+        assert_eq!(None, profile.next_step());
+
+        let expected = [
+            (25., minute),
+            (75., minute),
+            (100., minute),
+            (100., Duration::from_secs(30)),
+            (100. + 50. * (std::f64::consts::PI / 4.).sin(), minute),
+            (100. + 50. * (3. * std::f64::consts::PI / 4.).sin(), minute),
+            (100. + 50. * (5. * std::f64::consts::PI / 4.).sin(), minute),
+            (100. + 50. * (7. * std::f64::consts::PI / 4.).sin(), minute),
+        ];
+        assert_eq!(expected.len(), steps.len());
+        for (LoadStep { rate, duration }, (expected_rate, expected_duration)) in
+            steps.iter().zip(expected.iter())
+        {
+            assert!(
+                (rate - expected_rate).abs() < 1e-9,
+                "{rate} {expected_rate}"
+            );
+            assert_eq!(expected_duration, duration);
+        }
+    }
+}
@@ -5,28 +5,12 @@
 /// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
 /// option. This file may not be copied, modified, or distributed
 /// except according to those terms.
-mod bench_run;
-mod bench_session;
-mod configuration;
-#[cfg(feature = "http3")]
-mod h3_bench_session;
-mod http_bench_session;
-mod metrics;
-#[cfg(feature = "report-to-prometheus")]
-mod prometheus_reporter;
-mod rate_limiter;
-
-use crate::configuration::BenchmarkConfig;
-use crate::metrics::{BenchRunMetrics, ExternalMetricsServiceReporter};
-use log::error;
 use log::{info, LevelFilter};
 use log4rs::append::console::ConsoleAppender;
 use log4rs::config::{Appender, Root};
 use log4rs::Config;
-use std::sync::mpsc::Sender;
-use std::sync::Arc;
-use std::thread::JoinHandle;
-use std::{panic, process, thread};
+use perf_gauge::{run_benchmark, BenchmarkConfig};
+use std::{panic, process};
 use tokio::io;
 
 #[tokio::main]
@@ -37,72 +21,37 @@ async fn main() -> io::Result<()> {
         process::exit(0x1);
     }));
 
-    let mut benchmark_config = BenchmarkConfig::from_command_line().inspect_err(|_| {
-        println!("Failed to process parameters. Exiting.");
-    })?;
+    let benchmark_config = match BenchmarkConfig::from_command_line() {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("{errors}");
+            process::exit(0x2);
+        }
+    };
 
     init_logger();
 
     info!("Starting with configuration {}", benchmark_config);
 
-    let (reporter_task, batch_metric_sender) =
-        create_async_metrics_channel(&benchmark_config.reporters, benchmark_config.continuous);
-    let bench_session = benchmark_config.new_bench_session();
-
-    for batch in bench_session {
-        info!("Running next batch {}", batch);
-        let metrics = BenchRunMetrics::new();
-        let batch_run_result = batch.run(metrics).await;
-        match batch_run_result {
-            Ok(stats) => {
-                batch_metric_sender.send(stats).unwrap_or_default();
-            }
-            Err(e) => {
-                error!("Unexpected error during batch run: {}", e);
-            }
+    let throughput_search = match run_benchmark(benchmark_config, |_| {}).await {
+        Ok(throughput_search) => throughput_search,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(0x1);
         }
-    }
+    };
 
-    shutdown(reporter_task, batch_metric_sender);
-
-    Ok(())
-}
-
-fn shutdown(reporter_task: JoinHandle<()>, batch_metric_sender: Sender<BenchRunMetrics>) {
-    // we need to drop it explicitly, to signal completion to the `mpsc` thread.
-    drop(batch_metric_sender);
-    reporter_task.join().unwrap_or_default();
     println!("Done gauging performance. Exiting.");
-}
 
-fn create_async_metrics_channel(
-    metric_reporters: &[Arc<dyn ExternalMetricsServiceReporter + Send + Sync + 'static>],
-    continuous: bool,
-) -> (JoinHandle<()>, Sender<BenchRunMetrics>) {
-    // We need to report metrics in a separate threads,
-    // as at the moment of writing this code not all major metric client libraries
-    // had `async` APIs.
-    // We can replace it with `tokio::sync::mpsc` and `tokio::spawn` at any time
-    let (sender, receiver) = std::sync::mpsc::channel();
-    let metric_reporters = metric_reporters.to_owned();
-    let reporter_task = thread::spawn(move || {
-        while let Ok(stats) = receiver.recv() {
-            // broadcast to all metrics reporters
-            for reporter in &metric_reporters {
-                if let Err(e) = reporter.report(&stats) {
-                    error!("Error sending metrics: {}", e);
-                }
-            }
-        }
-        // for continuous runs we don't want to reset metrics
-        // to avoid saw-like graphs
-        if !continuous {
-            for reporter in metric_reporters {
-                reporter.reset_metrics();
-            }
+    // printed last, after all reports, so scripts can easily pick it up
+    if let Some(throughput_search) = throughput_search {
+        match throughput_search.result() {
+            Some(rate) => println!("Max sustainable rate meeting the SLO: {rate:.3} per second"),
+            None => println!("No probed rate met the SLO"),
         }
-    });
-    (reporter_task, sender)
+    }
+
+    Ok(())
 }
 
 fn init_logger() {
@@ -1,3 +1,4 @@
+use crate::circuit_breaker::Abort;
 use bytesize::ByteSize;
 use core::fmt;
 use derive_builder::Builder;
@@ -130,6 +131,8 @@ pub struct DefaultConsoleReporter {
 pub struct BenchRunMetrics {
     pub(crate) combined: BenchRunMetricsItem,
     pub(crate) by_operation: HashMap<String, BenchRunMetricsItem>,
+    /// Set if the batch was stopped by the [`CircuitBreaker`](crate::circuit_breaker::CircuitBreaker).
+    pub(crate) aborted: Option<Abort>,
 }
 
 #[derive(Clone)]
@@ -142,12 +145,17 @@ pub struct BenchRunMetricsItem {
     pub(crate) throughput: Histogram,
     pub(crate) success_latency: Histogram,
     pub(crate) error_latency: Histogram,
+    /// Latency measured from the intended send time (open-model runs only).
+    pub(crate) response_time: Histogram,
+    /// By phase, in the order of [`LatencyPhases::NAMES`].
+    pub(crate) phase_latency: [Histogram; 5],
 }
 
 #[derive(Serialize)]
 struct BenchRunReport {
     combined: BenchRunReportItem,
     by_operation: HashMap<String, BenchRunReportItem>,
+    aborted: Option<String>,
 }
 
 /// Default reporter that prints stats to console.
@@ -162,6 +170,8 @@ struct BenchRunReportItem {
     bitrate_mbps: f64,
     response_code_summary: Vec<(String, i32)>,
     latency_summary: Vec<(String, u64)>,
+    response_time_summary: Vec<(String, u64)>,
+    latency_phases: Vec<(String, Vec<(String, u64)>)>,
 }
 
 #[derive(Builder, Debug)]
@@ -173,6 +183,43 @@ pub struct RequestStats {
     #[builder(default = "None")]
     pub operation_name: Option<String>,
     pub fatal_error: bool,
+    /// Time elapsed since the request was supposed to be sent.
+    /// Set only in the open-model mode, where requests are scheduled independently of completions.
+    #[builder(default = "None")]
+    pub response_time: Option<Duration>,
+    /// Set by protocols that can tell the phases of the request apart (e.g. HTTP).
+    #[builder(default = "None")]
+    pub phases: Option<LatencyPhases>,
+}
+
+/// Where the time of a request goes. The connection phases are set only if a new connection was opened.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyPhases {
+    /// Resolving the host name, unless it is an IP address.
+    pub dns: Option<Duration>,
+    /// Establishing the TCP connection.
+    pub connect: Option<Duration>,
+    /// The TLS handshake.
+    pub tls: Option<Duration>,
+    /// From sending the request over the connection to receiving the response headers.
+    pub ttfb: Duration,
+    /// Receiving the response body.
+    pub body: Duration,
+}
+
+impl LatencyPhases {
+    pub const NAMES: [&'static str; 5] = ["dns", "connect", "tls", "ttfb", "body"];
+
+    /// In the order of [`LatencyPhases::NAMES`].
+    pub fn durations(&self) -> [Option<Duration>; 5] {
+        [
+            self.dns,
+            self.connect,
+            self.tls,
+            Some(self.ttfb),
+            Some(self.body),
+        ]
+    }
 }
 
 impl BenchRunMetrics {
@@ -180,20 +227,48 @@ impl BenchRunMetrics {
         Self {
             combined: BenchRunMetricsItem::new(),
             by_operation: HashMap::new(),
+            aborted: None,
         }
     }
 
+    /// Metrics across all requests of the batch.
+    pub fn combined(&self) -> &BenchRunMetricsItem {
+        &self.combined
+    }
+
+    /// Metrics of requests with an `operation_name`, by the name.
+    pub fn by_operation(&self) -> &HashMap<String, BenchRunMetricsItem> {
+        &self.by_operation
+    }
+
+    /// Why the batch was aborted, if it was.
+    pub fn aborted(&self) -> Option<&Abort> {
+        self.aborted.as_ref()
+    }
+
     pub fn report_request(&mut self, stats: RequestStats) {
         self.combined.report_request(&stats);
         if let Some(operation_name) = stats.operation_name.as_ref() {
             self.by_operation
                 .entry(operation_name.to_owned())
-                .or_insert_with(BenchRunMetricsItem::new)
+                .or_default()
                 .report_request(&stats);
         }
     }
 }
 
+impl Default for BenchRunMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for BenchRunMetricsItem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BenchRunMetricsItem {
     pub fn new() -> Self {
         Self {
@@ -206,6 +281,10 @@ impl BenchRunMetricsItem {
             success_latency: Histogram::new(10, 64)
                 .expect("Cannot build success latency histogram"),
             error_latency: Histogram::new(10, 64).expect("Cannot build error latency histogram"),
+            response_time: Histogram::new(10, 64).expect("Cannot build response time histogram"),
+            phase_latency: std::array::from_fn(|_| {
+                Histogram::new(10, 64).expect("Cannot build phase latency histogram")
+            }),
         }
     }
 
@@ -224,6 +303,20 @@ impl BenchRunMetricsItem {
                 .increment(stats.duration.as_micros() as u64)
                 .unwrap_or_default();
         }
+        if let Some(response_time) = stats.response_time {
+            self.response_time
+                .increment(response_time.as_micros() as u64)
+                .unwrap_or_default();
+        }
+        if let Some(phases) = stats.phases.as_ref() {
+            for (histogram, duration) in self.phase_latency.iter_mut().zip(phases.durations()) {
+                if let Some(duration) = duration {
+                    histogram
+                        .increment(duration.as_micros() as u64)
+                        .unwrap_or_default();
+                }
+            }
+        }
         self.total_bytes += stats.bytes_processed;
         self.summary
             .entry(stats.status.to_owned())
@@ -231,6 +324,49 @@ impl BenchRunMetricsItem {
             .add_assign(1);
     }
 
+    pub fn total_requests(&self) -> usize {
+        self.total_requests
+    }
+
+    pub fn successful_requests(&self) -> usize {
+        self.successful_requests
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    /// Number of requests by status, e.g. `200 OK`.
+    pub fn response_codes(&self) -> &HashMap<String, i32> {
+        &self.summary
+    }
+
+    /// In microseconds.
+    pub fn success_latency(&self) -> &Histogram {
+        &self.success_latency
+    }
+
+    /// In microseconds.
+    pub fn error_latency(&self) -> &Histogram {
+        &self.error_latency
+    }
+
+    /// In microseconds. Empty unless the load is open-model.
+    pub fn response_time(&self) -> &Histogram {
+        &self.response_time
+    }
+
+    /// In microseconds, by phase in the order of [`LatencyPhases::NAMES`].
+    /// Empty unless the protocol reports [`RequestStats::phases`].
+    pub fn phase_latency(&self) -> &[Histogram; 5] {
+        &self.phase_latency
+    }
+
+    /// In bytes per second.
+    pub fn throughput(&self) -> &Histogram {
+        &self.throughput
+    }
+
     pub fn truncated_mean(histogram: &Histogram, threshold: f64) -> u64 {
         let lowest = histogram.get_percentile(threshold).unwrap_or_default() as i64;
         let highest = histogram
@@ -285,6 +421,39 @@ impl BenchRunReportItem {
         let mut latency = metrics.success_latency.clone();
         latency.merge(&metrics.error_latency);
 
+        BenchRunReportItem::histogram_summary(&latency)
+    }
+
+    fn response_time_summary(metrics: &BenchRunMetricsItem) -> Vec<(String, u64)> {
+        if metrics.response_time.minimum().is_none() {
+            // closed-model run, nothing to correct
+            return vec![];
+        }
+        BenchRunReportItem::histogram_summary(&metrics.response_time)
+    }
+
+    fn latency_phases(metrics: &BenchRunMetricsItem) -> Vec<(String, Vec<(String, u64)>)> {
+        LatencyPhases::NAMES
+            .iter()
+            .zip(metrics.phase_latency.iter())
+            .filter(|(_, latency)| latency.minimum().is_some())
+            .map(|(name, latency)| {
+                let summary = [("p50", 50.0), ("p90", 90.0), ("p99", 99.0)]
+                    .into_iter()
+                    .map(|(label, p)| {
+                        (
+                            label.to_string(),
+                            latency.get_percentile(p).unwrap_or_default(),
+                        )
+                    })
+                    .chain([("Max".to_string(), latency.maximum().unwrap_or_default())])
+                    .collect();
+                (name.to_string(), summary)
+            })
+            .collect()
+    }
+
+    fn histogram_summary(latency: &Histogram) -> Vec<(String, u64)> {
         vec![
             ("Min".to_string(), latency.minimum().unwrap_or_default()),
             (
@@ -316,15 +485,15 @@ impl BenchRunReportItem {
             ("StdDev".to_string(), latency.stddev().unwrap_or_default()),
             (
                 "tm95".to_string(),
-                BenchRunMetricsItem::truncated_mean(&latency, 5.0),
+                BenchRunMetricsItem::truncated_mean(latency, 5.0),
             ),
             (
                 "tm99".to_string(),
-                BenchRunMetricsItem::truncated_mean(&latency, 1.0),
+                BenchRunMetricsItem::truncated_mean(latency, 1.0),
             ),
             (
                 "tm99.9".to_string(),
-                BenchRunMetricsItem::truncated_mean(&latency, 0.1),
+                BenchRunMetricsItem::truncated_mean(latency, 0.1),
             ),
         ]
     }
@@ -332,6 +501,9 @@ impl BenchRunReportItem {
 
 impl fmt::Display for BenchRunReport {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(reason) = self.aborted.as_ref() {
+            writeln!(f, "Aborted: {reason}")?;
+        }
         writeln!(f, "{}", self.combined)
     }
 }
@@ -370,37 +542,85 @@ impl fmt::Display for BenchRunReportItem {
 
         if !self.latency_summary.is_empty() {
             writeln!(f)?;
-            writeln!(f, "Latency:")?;
-            let mut max_label_len = 0;
-            let mut max_value_len = 0;
-            let mut min_value = 1_000_000_000;
-            for (label, value) in self.latency_summary.iter() {
-                max_label_len = max_label_len.max(label.len());
-                max_value_len = max_value_len.max(value.to_string().len());
-                min_value = min_value.min(*value);
-            }
-            let use_ms = min_value >= 1_000;
-
-            for (label, value) in self.latency_summary.iter() {
-                let label_spacing = " ".repeat(max_label_len - label.len() + 1);
-                let value_spacing = " ".repeat(max_value_len - value.to_string().len() + 1);
-                if use_ms {
-                    writeln!(
-                        f,
-                        "{label}{label_spacing}:{value_spacing}{value:.2}ms",
-                        value = *value as f64 / 1000.0,
-                    )?;
-                } else {
-                    writeln!(f, "{label}{label_spacing}:{value_spacing}{value}µs")?;
-                }
+            if self.response_time_summary.is_empty() {
+                writeln!(f, "Latency:")?;
+            } else {
+                writeln!(f, "Latency (service time):")?;
             }
-            Ok(())
-        } else {
+            BenchRunReportItem::write_latency(f, &self.latency_summary)?;
+        }
+
+        if !self.response_time_summary.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Response time (corrected):")?;
+            BenchRunReportItem::write_latency(f, &self.response_time_summary)?;
+        }
+
+        if !self.latency_phases.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Latency phases (p50 / p90 / p99 / Max):")?;
+            BenchRunReportItem::write_phases(f, &self.latency_phases)?;
+        }
+
+        if self.latency_summary.is_empty() {
             writeln!(f)
+        } else {
+            Ok(())
         }
     }
 }
 
+impl BenchRunReportItem {
+    fn write_phases(
+        f: &mut fmt::Formatter<'_>,
+        phases: &[(String, Vec<(String, u64)>)],
+    ) -> fmt::Result {
+        let max_label_len = phases.iter().map(|(name, _)| name.len()).max();
+        for (name, summary) in phases {
+            let label_spacing = " ".repeat(max_label_len.unwrap_or_default() - name.len() + 1);
+            let values: Vec<String> = summary
+                .iter()
+                .map(|(_, value)| {
+                    if *value >= 1_000 {
+                        format!("{:.2}ms", *value as f64 / 1000.0)
+                    } else {
+                        format!("{value}µs")
+                    }
+                })
+                .collect();
+            writeln!(f, "{name}{label_spacing}: {}", values.join(" / "))?;
+        }
+        Ok(())
+    }
+
+    fn write_latency(f: &mut fmt::Formatter<'_>, summary: &[(String, u64)]) -> fmt::Result {
+        let mut max_label_len = 0;
+        let mut max_value_len = 0;
+        let mut min_value = 1_000_000_000;
+        for (label, value) in summary.iter() {
+            max_label_len = max_label_len.max(label.len());
+            max_value_len = max_value_len.max(value.to_string().len());
+            min_value = min_value.min(*value);
+        }
+        let use_ms = min_value >= 1_000;
+
+        for (label, value) in summary.iter() {
+            let label_spacing = " ".repeat(max_label_len - label.len() + 1);
+            let value_spacing = " ".repeat(max_value_len - value.to_string().len() + 1);
+            if use_ms {
+                writeln!(
+                    f,
+                    "{label}{label_spacing}:{value_spacing}{value:.2}ms",
+                    value = *value as f64 / 1000.0,
+                )?;
+            } else {
+                writeln!(f, "{label}{label_spacing}:{value_spacing}{value}µs")?;
+            }
+        }
+        Ok(())
+    }
+}
+
 // cov:begin-ignore-line
 impl ExternalMetricsServiceReporter for DefaultConsoleReporter {
     fn report(&self, metrics: &BenchRunMetrics) -> io::Result<()> {
@@ -447,6 +667,7 @@ impl DefaultConsoleReporter {
         BenchRunReport {
             combined: self.build_item_report(&metrics.combined),
             by_operation,
+            aborted: metrics.aborted.as_ref().map(|abort| abort.reason.clone()),
         }
     }
 
@@ -473,6 +694,8 @@ impl DefaultConsoleReporter {
             bitrate_mbps: total_bytes as f64 / duration.as_secs_f64() * 8. / 1_000_000.,
             response_code_summary: BenchRunReportItem::summary_ordered(metrics),
             latency_summary: BenchRunReportItem::latency_summary(metrics),
+            response_time_summary: BenchRunReportItem::response_time_summary(metrics),
+            latency_phases: BenchRunReportItem::latency_phases(metrics),
         }
     }
 }
@@ -481,8 +704,8 @@ impl DefaultConsoleReporter {
 #[cfg(test)]
 mod tests {
     use crate::bench_run::BenchRun;
-    use crate::metrics::{BenchRunMetrics, DefaultConsoleReporter, RequestStats};
-    use crate::rate_limiter::RateLimiter;
+    use crate::metrics::{BenchRunMetrics, DefaultConsoleReporter, LatencyPhases, RequestStats};
+    use crate::rate_limiter::{ArrivalProcess, RateLimiter};
     use std::thread::sleep;
     use std::time::Duration;
 
@@ -506,6 +729,8 @@ mod tests {
                 duration: Default::default(),
                 operation_name: None,
                 fatal_error: false,
+                response_time: None,
+                phases: None,
             });
         }
 
@@ -536,6 +761,8 @@ mod tests {
                 duration: Duration::from_micros(i),
                 operation_name: None,
                 fatal_error: false,
+                response_time: None,
+                phases: None,
             });
         }
 
@@ -569,6 +796,8 @@ mod tests {
                     Some("OperationB".to_string())
                 },
                 fatal_error: false,
+                response_time: None,
+                phases: None,
             });
         }
 
@@ -625,11 +854,59 @@ mod tests {
         assert_eq!(Some(("StdDev".to_string(), 289)), items.next());
     }
 
+    #[test]
+    fn test_latency_phases() {
+        let mut metrics = BenchRunMetrics::new();
+        for i in 0..1000 {
+            metrics.report_request(RequestStats {
+                is_success: i % 2 == 0,
+                bytes_processed: 0,
+                status: "200 OK".to_string(),
+                duration: Duration::from_micros(3 * i),
+                operation_name: None,
+                fatal_error: false,
+                response_time: None,
+                phases: Some(LatencyPhases {
+                    // a new connection for every 10th request
+                    connect: (i % 10 == 0).then(|| Duration::from_micros(i)),
+                    ttfb: Duration::from_micros(i),
+                    body: Duration::from_micros(2_000),
+                    ..LatencyPhases::default()
+                }),
+            });
+        }
+
+        let report = DefaultConsoleReporter::new(None).build_report(&metrics);
+        let phases = &report.combined.latency_phases;
+        let names: Vec<&str> = phases.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(vec!["connect", "ttfb", "body"], names);
+        assert_eq!(
+            vec![
+                ("p50".to_string(), 500),
+                ("p90".to_string(), 900),
+                ("p99".to_string(), 990),
+                ("Max".to_string(), 999),
+            ],
+            phases[1].1
+        );
+
+        let as_str = report.to_string();
+        assert!(as_str.contains("Latency phases (p50 / p90 / p99 / Max):"));
+        assert!(as_str.contains("ttfb    : 500µs / 900µs / 990µs / 999µs"));
+        assert!(as_str.contains("body    : 2.00ms / 2.00ms / 2.00ms / 2.00ms"));
+    }
+
     #[test]
     fn test_has_more_work_request_limit() {
         let requests = 10;
-        let mut metrics =
-            BenchRun::from_request_limit(0, requests, RateLimiter::build_rate_limiter(0.), None);
+        let mut metrics = BenchRun::from_request_limit(
+            0,
+            requests,
+            RateLimiter::build_rate_limiter(0., ArrivalProcess::Constant),
+            None,
+            false,
+            None,
+        );
         for _ in 0..requests {
             assert!(metrics.has_more_work());
         }
@@ -639,8 +916,14 @@ mod tests {
     #[test]
     fn test_has_more_work_time_limit() {
         let duration = Duration::from_secs(1);
-        let mut metrics =
-            BenchRun::from_duration_limit(0, duration, RateLimiter::build_rate_limiter(0.), None);
+        let mut metrics = BenchRun::from_duration_limit(
+            0,
+            duration,
+            RateLimiter::build_rate_limiter(0., ArrivalProcess::Constant),
+            None,
+            false,
+            None,
+        );
         for _ in 0..1000 {
             assert!(metrics.has_more_work());
         }
@@ -659,6 +942,8 @@ mod tests {
                 duration: Duration::from_micros(i),
                 operation_name: None,
                 fatal_error: false,
+                response_time: None,
+                phases: None,
             });
         }
 
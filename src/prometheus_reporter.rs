@@ -1,5 +1,6 @@
 use crate::metrics::{
     BenchRunMetrics, BenchRunMetricsItem, ExternalMetricsServiceReporter, HistogramStatsExt,
+    LatencyPhases,
 };
 use histogram::Histogram;
 use log::info;
@@ -142,6 +143,32 @@ impl PrometheusReporter {
             latency,
         );
 
+        if bench_run_metrics.response_time.minimum().is_some() {
+            PrometheusReporter::register_histogram(
+                &registry,
+                PrometheusReporter::build_metric_name(&operation_name, "response_time"),
+                "Latency measured from the intended send time (corrected for coordinated omission)",
+                bench_run_metrics.response_time.clone(),
+            );
+        }
+
+        for (phase, latency) in LatencyPhases::NAMES
+            .iter()
+            .zip(bench_run_metrics.phase_latency.iter())
+        {
+            if latency.minimum().is_some() {
+                PrometheusReporter::register_histogram(
+                    &registry,
+                    PrometheusReporter::build_metric_name(
+                        &operation_name,
+                        &format!("{phase}_latency"),
+                    ),
+                    "Latency of a phase of requests (dns, connect, tls, ttfb or body)",
+                    latency.clone(),
+                );
+            }
+        }
+
         registry
     }
 
@@ -613,6 +640,8 @@ mod test {
                 duration: Duration::from_micros(i),
                 operation_name: None,
                 fatal_error: false,
+                response_time: None,
+                phases: None,
             });
         }
 
@@ -0,0 +1,244 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::config_error::{ConfigError, ConfigErrors};
+use crate::configuration::BenchmarkMode;
+use crate::dns_bench_session::{DnsBenchAdapter, DnsOptions};
+use crate::grpc_bench_session::{GrpcBenchAdapter, GrpcOptions};
+use crate::redis_bench_session::{RedisBenchAdapter, RedisOptions};
+use crate::scenario_bench_session::{ScenarioBenchAdapter, ScenarioOptions};
+use crate::tcp_bench_session::{TcpBenchAdapter, TcpOptions};
+use crate::udp_bench_session::{UdpBenchAdapter, UdpOptions};
+use crate::ws_bench_session::{WsBenchAdapter, WsOptions};
+use clap::{Args, Command, FromArgMatches};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Protocols available in addition to the built-in `http` mode. Each protocol has its own
+/// subcommand (e.g. `perf-gauge my-protocol --option value`) and config file section
+/// (e.g. `my_protocol: {option: value}`), both parsed into the options type of the protocol.
+pub struct ProtocolRegistry {
+    protocols: BTreeMap<&'static str, Box<dyn ProtocolFactory>>,
+}
+
+/// Builds a [`BenchmarkMode`] from the options of a subcommand or a config file section.
+trait ProtocolFactory: Send + Sync {
+    fn command(&self) -> Command;
+    fn build_from_args(&self, args: &[String]) -> Result<BenchmarkMode, ConfigErrors>;
+    fn build_from_section(&self, section: serde_json::Value)
+        -> Result<BenchmarkMode, ConfigErrors>;
+}
+
+struct TypedProtocolFactory<O, F> {
+    name: &'static str,
+    build: F,
+    options: PhantomData<fn() -> O>,
+}
+
+impl<O, F> ProtocolFactory for TypedProtocolFactory<O, F>
+where
+    O: Args + FromArgMatches + DeserializeOwned,
+    F: Fn(O) -> Result<BenchmarkMode, ConfigErrors> + Send + Sync,
+{
+    fn command(&self) -> Command {
+        O::augment_args(Command::new(self.name).bin_name(format!("perf-gauge {}", self.name)))
+    }
+
+    fn build_from_args(&self, args: &[String]) -> Result<BenchmarkMode, ConfigErrors> {
+        let invalid_options = |reason: String| ConfigError::InvalidValue {
+            flag: self.name,
+            value: args[1..].join(" "),
+            reason,
+        };
+        let matches = match self.command().try_get_matches_from(args) {
+            Ok(matches) => matches,
+            // --help
+            Err(e) if !e.use_stderr() => e.exit(),
+            Err(e) => return Err(invalid_options(e.render().to_string()).into()),
+        };
+        let options = O::from_arg_matches(&matches).map_err(|e| invalid_options(e.to_string()))?;
+        (self.build)(options)
+    }
+
+    fn build_from_section(
+        &self,
+        section: serde_json::Value,
+    ) -> Result<BenchmarkMode, ConfigErrors> {
+        let options =
+            serde_json::from_value(section.clone()).map_err(|e| ConfigError::InvalidValue {
+                flag: self.name,
+                value: section.to_string(),
+                reason: e.to_string(),
+            })?;
+        (self.build)(options)
+    }
+}
+
+/// Built-in protocols.
+impl Default for ProtocolRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register("dns", |options: DnsOptions| {
+            Ok(BenchmarkMode::Custom(Arc::new(
+                DnsBenchAdapter::from_options(options)?,
+            )))
+        });
+        registry.register("grpc", |options: GrpcOptions| {
+            Ok(BenchmarkMode::Custom(Arc::new(
+                GrpcBenchAdapter::from_options(options)?,
+            )))
+        });
+        registry.register("redis", |options: RedisOptions| {
+            Ok(BenchmarkMode::Custom(Arc::new(
+                RedisBenchAdapter::from_options(options)?,
+            )))
+        });
+        registry.register("scenario", |options: ScenarioOptions| {
+            Ok(BenchmarkMode::Custom(Arc::new(
+                ScenarioBenchAdapter::from_options(options)?,
+            )))
+        });
+        registry.register("tcp", |options: TcpOptions| {
+            Ok(BenchmarkMode::Custom(Arc::new(
+                TcpBenchAdapter::from_options(options)?,
+            )))
+        });
+        registry.register("udp", |options: UdpOptions| {
+            Ok(BenchmarkMode::Custom(Arc::new(
+                UdpBenchAdapter::from_options(options)?,
+            )))
+        });
+        registry.register("ws", |options: WsOptions| {
+            Ok(BenchmarkMode::Custom(Arc::new(
+                WsBenchAdapter::from_options(options)?,
+            )))
+        });
+        registry
+    }
+}
+
+impl ProtocolRegistry {
+    /// A registry without any protocols apart from `http`, e.g. to register only custom ones.
+    pub fn empty() -> Self {
+        Self {
+            protocols: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a protocol by its subcommand name. `build` creates the adapter from the options,
+    /// usually as [`BenchmarkMode::Custom`].
+    pub fn register<O, F>(&mut self, name: &'static str, build: F) -> &mut Self
+    where
+        O: Args + FromArgMatches + DeserializeOwned + 'static,
+        F: Fn(O) -> Result<BenchmarkMode, ConfigErrors> + Send + Sync + 'static,
+    {
+        self.protocols.insert(
+            name,
+            Box::new(TypedProtocolFactory {
+                name,
+                build,
+                options: PhantomData,
+            }),
+        );
+        self
+    }
+
+    /// Lists the registered protocols for the help message.
+    pub(crate) fn help(&self) -> Option<String> {
+        if self.protocols.is_empty() {
+            return None;
+        }
+        let mut help = "Registered protocols (see `perf-gauge <PROTOCOL> --help`):".to_string();
+        for command in self.protocols.values().map(|factory| factory.command()) {
+            help.push_str(&format!(
+                "\n  {:<12}{}",
+                command.get_name(),
+                command
+                    .get_about()
+                    .map(|s| s.to_string())
+                    .unwrap_or_default()
+            ));
+        }
+        Some(help)
+    }
+
+    /// Builds the mode from the subcommand name followed by its arguments.
+    pub(crate) fn build_from_args(&self, args: &[String]) -> Result<BenchmarkMode, ConfigErrors> {
+        let name = args.first().map_or("", |s| s.as_str());
+        self.find(name)?.build_from_args(args)
+    }
+
+    /// Builds the mode from config file sections not recognized as general options.
+    /// Exactly one of them must be a registered protocol.
+    pub(crate) fn build_from_sections(
+        &self,
+        sections: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<BenchmarkMode, ConfigErrors> {
+        let mut protocols = sections
+            .iter()
+            .filter(|(name, _)| self.protocols.contains_key(name.as_str()));
+        match (protocols.next(), protocols.next()) {
+            (Some((name, section)), None) => self.find(name)?.build_from_section(section.clone()),
+            (None, _) => Err(ConfigError::UnknownProtocol {
+                name: sections.keys().cloned().collect::<Vec<_>>().join(", "),
+                known: self.names(),
+            }
+            .into()),
+            (Some((name, _)), Some((other, _))) => Err(ConfigError::InvalidValue {
+                flag: "config",
+                value: format!("{name}, {other}"),
+                reason: "only one protocol section is allowed".to_string(),
+            }
+            .into()),
+        }
+    }
+
+    fn find(&self, name: &str) -> Result<&dyn ProtocolFactory, ConfigError> {
+        self.protocols
+            .get(name)
+            .map(|factory| factory.as_ref())
+            .ok_or_else(|| ConfigError::UnknownProtocol {
+                name: name.to_string(),
+                known: self.names(),
+            })
+    }
+
+    pub(crate) fn names(&self) -> Vec<String> {
+        std::iter::once("http")
+            .chain(self.protocols.keys().copied())
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config_error::ConfigError;
+    use crate::protocol_registry::ProtocolRegistry;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_unknown_protocol() {
+        let registry = ProtocolRegistry::empty();
+        assert_eq!(
+            Err(ConfigError::UnknownProtocol {
+                name: "smtp".to_string(),
+                known: vec!["http".to_string()]
+            }
+            .into()),
+            registry.build_from_args(&["smtp".to_string()]).map(|_| ())
+        );
+        assert!(registry
+            .build_from_sections(&BTreeMap::from([(
+                "smtp".to_string(),
+                serde_json::Value::Null
+            )]))
+            .is_err());
+    }
+}
@@ -7,20 +7,56 @@
 /// except according to those terms.
 use leaky_bucket::RateLimiter as InnerRateLimiter;
 use log::debug;
+use rand::{thread_rng, Rng};
 use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+/// How many arrivals a client may fall behind its schedule before the missed ones are dropped,
+/// same as the burst capacity of the constant rate limiter.
+const MAX_BACKLOG: f64 = 100.;
+
+/// Distribution of gaps between two consecutive requests.
+/// All of them preserve the average rate.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ArrivalProcess {
+    /// Evenly spaced requests.
+    #[default]
+    Constant,
+    /// Exponentially distributed gaps, i.e. requests arrive as a Poisson process.
+    Poisson,
+    /// Gaps are uniformly distributed within `mean * (1 ± jitter)`.
+    Uniform { jitter: f64 },
+    /// Requests are sent only during `on` periods, followed by `off` periods of silence.
+    Bursty { on: Duration, off: Duration },
+}
 
 #[derive(Clone)]
 pub struct RateLimiter {
     leaky_bucket: Option<Arc<InnerRateLimiter>>,
+    rate_per_second: f64,
+    arrival_process: ArrivalProcess,
+    next_arrival: Option<Instant>,
+    /// Position on the schedule since the first request, for bursty arrivals.
+    schedule_offset: Duration,
 }
 
 impl RateLimiter {
-    pub fn build_rate_limiter(rate_per_second: f64) -> RateLimiter {
-        if rate_per_second == 0. {
-            // unlimited
-            return RateLimiter { leaky_bucket: None };
+    pub fn build_rate_limiter(
+        rate_per_second: f64,
+        arrival_process: ArrivalProcess,
+    ) -> RateLimiter {
+        if rate_per_second == 0. || arrival_process != ArrivalProcess::Constant {
+            // either unlimited, or paced by sampled inter-arrival gaps
+            return RateLimiter {
+                leaky_bucket: None,
+                rate_per_second,
+                arrival_process,
+                next_arrival: None,
+                schedule_offset: Duration::ZERO,
+            };
         }
 
         let (amount, interval) = RateLimiter::rate_to_refill_amount_and_duration(rate_per_second);
@@ -39,13 +75,75 @@ impl RateLimiter {
                     .max(amount as usize * 100)
                     .build(),
             )),
+            rate_per_second,
+            arrival_process: ArrivalProcess::Constant,
+            next_arrival: None,
+            schedule_offset: Duration::ZERO,
         }
     }
 
-    pub async fn acquire_one(&self) {
+    pub async fn acquire_one(&mut self) {
         if let Some(leaky_bucket) = self.leaky_bucket.as_ref() {
             leaky_bucket.acquire_one().await;
+        } else if let Some(next_arrival) = self.schedule_next_arrival(Instant::now()) {
+            sleep_until(next_arrival).await;
+        }
+    }
+
+    /// Moves the timeline to the next arrival. If the client stalled, it catches up with
+    /// at most `MAX_BACKLOG` missed arrivals instead of firing all of them at once.
+    fn schedule_next_arrival(&mut self, now: Instant) -> Option<Instant> {
+        let gap = self.next_inter_arrival()?;
+        let max_lag = Duration::from_secs_f64(MAX_BACKLOG / self.rate_per_second);
+        let previous = match (self.next_arrival, now.checked_sub(max_lag)) {
+            (Some(previous), Some(earliest)) => previous.max(earliest),
+            (Some(previous), None) => previous,
+            (None, _) => now,
+        };
+        let next_arrival = previous + gap;
+        self.next_arrival = Some(next_arrival);
+        Some(next_arrival)
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.rate_per_second == 0.
+    }
+
+    /// The gap until the next request on the timeline, sampled from the arrival process.
+    /// `None` if the rate is unlimited.
+    pub fn next_inter_arrival(&mut self) -> Option<Duration> {
+        if self.is_unlimited() {
+            return None;
         }
+
+        let mean_gap = 1. / self.rate_per_second;
+        let gap = match &self.arrival_process {
+            ArrivalProcess::Constant => mean_gap,
+            ArrivalProcess::Poisson => {
+                // inverse transform sampling, `1 - U` to avoid ln(0)
+                -(1. - thread_rng().gen::<f64>()).ln() * mean_gap
+            }
+            ArrivalProcess::Uniform { jitter } => {
+                mean_gap * (1. + jitter * thread_rng().gen_range(-1.0..=1.0))
+            }
+            ArrivalProcess::Bursty { on, off } => {
+                let cycle = *on + *off;
+                // send faster during `on` periods to preserve the average rate
+                let mut next = self.schedule_offset
+                    + Duration::from_secs_f64(mean_gap)
+                        .mul_f64(on.as_secs_f64() / cycle.as_secs_f64());
+                let position = Duration::from_nanos((next.as_nanos() % cycle.as_nanos()) as u64);
+                if position >= *on {
+                    // skip the `off` period
+                    next += cycle - position;
+                }
+                let gap = next - self.schedule_offset;
+                self.schedule_offset = next;
+                return Some(gap);
+            }
+        };
+
+        Some(Duration::from_secs_f64(gap))
     }
 
     fn gcd(mut a: usize, mut b: usize) -> usize {
@@ -80,18 +178,70 @@ impl RateLimiter {
 
 impl fmt::Debug for RateLimiter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("RateLimiter").finish()
+        f.debug_struct("RateLimiter")
+            .field("arrival_process", &self.arrival_process)
+            .finish()
+    }
+}
+
+impl FromStr for ArrivalProcess {
+    type Err = String;
+
+    /// Parses `constant`, `poisson`, `uniform[:JITTER]` or `bursty:ON:OFF`,
+    /// e.g. `uniform:0.5` or `bursty:2s:1s`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let parse_duration = |value: Option<&str>| -> Result<Duration, String> {
+            let value = value
+                .ok_or_else(|| format!("Bursty arrivals require `bursty:ON:OFF`, got `{s}`"))?;
+            humantime::Duration::from_str(value)
+                .map(Into::into)
+                .map_err(|e| format!("Illegal duration `{value}`: {e}"))
+        };
+
+        let arrival_process = match parts.next().unwrap_or_default() {
+            "constant" => ArrivalProcess::Constant,
+            "poisson" => ArrivalProcess::Poisson,
+            "uniform" => {
+                let jitter = match parts.next() {
+                    None => 0.5,
+                    Some(value) => value
+                        .parse::<f64>()
+                        .map_err(|e| format!("Illegal jitter `{value}`: {e}"))?,
+                };
+                if !(0.0..=1.0).contains(&jitter) {
+                    return Err(format!("Jitter must be within [0, 1], got {jitter}"));
+                }
+                ArrivalProcess::Uniform { jitter }
+            }
+            "bursty" => {
+                let on = parse_duration(parts.next())?;
+                let off = parse_duration(parts.next())?;
+                if on.is_zero() {
+                    return Err("Bursty `on` period must be positive".to_string());
+                }
+                ArrivalProcess::Bursty { on, off }
+            }
+            other => return Err(format!("Unsupported arrival process `{other}`")),
+        };
+
+        if parts.next().is_some() {
+            return Err(format!("Unexpected arrival process format `{s}`"));
+        }
+
+        Ok(arrival_process)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::rate_limiter::RateLimiter;
+    use crate::rate_limiter::{ArrivalProcess, RateLimiter};
+    use std::str::FromStr;
     use std::time::{Duration, Instant};
 
     #[tokio::test]
     async fn test_limited_frequent() {
-        let rate_limiter = RateLimiter::build_rate_limiter(100.);
+        let mut rate_limiter = RateLimiter::build_rate_limiter(100., ArrivalProcess::Constant);
         let begin = Instant::now();
         for _ in 0..100 {
             rate_limiter.acquire_one().await;
@@ -103,7 +253,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_limited_seldom() {
-        let rate_limiter = RateLimiter::build_rate_limiter(0.5);
+        let mut rate_limiter = RateLimiter::build_rate_limiter(0.5, ArrivalProcess::Constant);
         let begin = Instant::now();
         for _ in 0..2 {
             rate_limiter.acquire_one().await;
@@ -116,7 +266,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_unlimited() {
-        let rate_limiter = RateLimiter::build_rate_limiter(0.);
+        let mut rate_limiter = RateLimiter::build_rate_limiter(0., ArrivalProcess::Constant);
         let begin = Instant::now();
         for _ in 0..1_000_000 {
             rate_limiter.acquire_one().await;
@@ -126,6 +276,131 @@ mod tests {
         assert!(elapsed.as_secs_f64() < 1.);
     }
 
+    #[tokio::test]
+    async fn test_limited_poisson() {
+        let mut rate_limiter = RateLimiter::build_rate_limiter(100., ArrivalProcess::Poisson);
+        let begin = Instant::now();
+        for _ in 0..100 {
+            rate_limiter.acquire_one().await;
+        }
+        let elapsed = Instant::now().duration_since(begin);
+        println!("Elapsed: {elapsed:?}");
+        assert!((elapsed.as_secs_f64() - 1.).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_inter_arrival() {
+        assert_eq!(
+            None,
+            RateLimiter::build_rate_limiter(0., ArrivalProcess::Constant).next_inter_arrival()
+        );
+        assert_eq!(
+            Some(Duration::from_millis(10)),
+            RateLimiter::build_rate_limiter(100., ArrivalProcess::Constant).next_inter_arrival()
+        );
+        assert_eq!(
+            Some(Duration::from_secs(2)),
+            RateLimiter::build_rate_limiter(0.5, ArrivalProcess::Constant).next_inter_arrival()
+        );
+    }
+
+    #[test]
+    fn test_backlog_is_capped() {
+        let mut rate_limiter =
+            RateLimiter::build_rate_limiter(100., ArrivalProcess::Uniform { jitter: 0. });
+        let start = tokio::time::Instant::now();
+        assert_eq!(
+            Some(start + Duration::from_millis(10)),
+            rate_limiter.schedule_next_arrival(start)
+        );
+        assert_eq!(
+            Some(start + Duration::from_millis(20)),
+            rate_limiter.schedule_next_arrival(start)
+        );
+
+        // after a 10s stall only the last 100 arrivals (1s) are caught up
+        let now = start + Duration::from_secs(10);
+        assert_eq!(
+            Some(now - Duration::from_millis(990)),
+            rate_limiter.schedule_next_arrival(now)
+        );
+        let caught_up = (0..200)
+            .take_while(|_| rate_limiter.schedule_next_arrival(now).unwrap() <= now)
+            .count();
+        assert_eq!(99, caught_up);
+    }
+
+    #[test]
+    fn test_poisson_inter_arrival() {
+        let mut rate_limiter = RateLimiter::build_rate_limiter(100., ArrivalProcess::Poisson);
+        let samples = 100_000;
+        let total: f64 = (0..samples)
+            .map(|_| rate_limiter.next_inter_arrival().unwrap().as_secs_f64())
+            .sum();
+        let mean = total / samples as f64;
+        assert!((mean - 0.01).abs() < 0.001, "Mean gap: {mean}");
+    }
+
+    #[test]
+    fn test_uniform_inter_arrival() {
+        let mut rate_limiter =
+            RateLimiter::build_rate_limiter(100., ArrivalProcess::Uniform { jitter: 0.2 });
+        for _ in 0..10_000 {
+            let gap = rate_limiter.next_inter_arrival().unwrap().as_secs_f64();
+            assert!((0.008 - 1e-9..=0.012 + 1e-9).contains(&gap), "Gap: {gap}");
+        }
+    }
+
+    #[test]
+    fn test_bursty_inter_arrival() {
+        let mut rate_limiter = RateLimiter::build_rate_limiter(
+            10.,
+            ArrivalProcess::Bursty {
+                on: Duration::from_secs(1),
+                off: Duration::from_secs(1),
+            },
+        );
+        // 20 requests per second during `on`, nothing during `off`
+        let gaps: Vec<f64> = (0..40)
+            .map(|_| rate_limiter.next_inter_arrival().unwrap().as_secs_f64())
+            .collect();
+        let long_gaps = gaps.iter().filter(|gap| **gap > 0.5).count();
+        assert_eq!(2, long_gaps);
+        let total: f64 = gaps.iter().sum();
+        assert!((total - 4.).abs() < 0.1, "Total: {total}");
+    }
+
+    #[test]
+    fn test_arrival_process_from_str() {
+        assert_eq!(
+            Ok(ArrivalProcess::Constant),
+            ArrivalProcess::from_str("constant")
+        );
+        assert_eq!(
+            Ok(ArrivalProcess::Poisson),
+            ArrivalProcess::from_str("poisson")
+        );
+        assert_eq!(
+            Ok(ArrivalProcess::Uniform { jitter: 0.5 }),
+            ArrivalProcess::from_str("uniform")
+        );
+        assert_eq!(
+            Ok(ArrivalProcess::Uniform { jitter: 0.1 }),
+            ArrivalProcess::from_str("uniform:0.1")
+        );
+        assert_eq!(
+            Ok(ArrivalProcess::Bursty {
+                on: Duration::from_secs(2),
+                off: Duration::from_millis(500),
+            }),
+            ArrivalProcess::from_str("bursty:2s:500ms")
+        );
+        assert!(ArrivalProcess::from_str("uniform:2").is_err());
+        assert!(ArrivalProcess::from_str("bursty:2s").is_err());
+        assert!(ArrivalProcess::from_str("gaussian").is_err());
+        assert!(ArrivalProcess::from_str("poisson:1").is_err());
+    }
+
     #[test]
     fn test_rate_to_refill_amount_and_duration() {
         let test = vec![
@@ -0,0 +1,459 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::bench_run::BenchmarkProtocolAdapter;
+use crate::config_error::{ConfigError, ConfigErrors};
+use crate::configuration::BenchmarkConfig;
+use crate::metrics::RequestStats;
+use crate::socket_session::{request_stats, validate_targets, Connection, Outcome, SocketError};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use clap::{Args, ValueEnum};
+use core::fmt;
+use derive_builder::Builder;
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+use std::io;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+#[derive(Args, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+#[command(about = "Run in Redis mode (RESP)", long_about = None)]
+pub struct RedisOptions {
+    /// Target in `host:port` form, e.g. my-redis.com:6379 Can be multiple ones (with random choice balancing).
+    #[arg(required = true)]
+    target: Vec<String>,
+    /// Commands to send. Can be multiple ones, e.g. `--command get --command set`. In this case a random one is chosen for each request.
+    #[arg(long, value_enum, default_value = "get")]
+    #[serde(default = "RedisOptions::default_command")]
+    command: Vec<RedisCommand>,
+    /// Number of commands sent in a single round-trip.
+    #[arg(long, default_value_t = 1)]
+    #[serde(default = "RedisOptions::default_pipeline")]
+    pipeline: usize,
+    /// Number of distinct keys. Each command uses a random one.
+    #[arg(long, default_value_t = 10000)]
+    #[serde(default = "RedisOptions::default_keyspace")]
+    keyspace: u64,
+    /// Prefix of the keys, followed by the key number.
+    #[arg(long = "key_prefix", default_value = "key:")]
+    #[serde(default = "RedisOptions::default_key_prefix")]
+    key_prefix: String,
+    /// Value for `set`. Could be either `random://[0-9]+`, `file://$filename` or `base64://${valid_base64}`.
+    #[arg(short = 'B', long, default_value = "random://64")]
+    #[serde(default = "RedisOptions::default_value")]
+    value: String,
+    /// Password to `AUTH` with after connecting.
+    #[arg(long)]
+    password: Option<String>,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisCommand {
+    Get,
+    Set,
+    Incr,
+}
+
+impl RedisOptions {
+    fn default_command() -> Vec<RedisCommand> {
+        vec![RedisCommand::Get]
+    }
+
+    fn default_pipeline() -> usize {
+        1
+    }
+
+    fn default_keyspace() -> u64 {
+        10000
+    }
+
+    fn default_key_prefix() -> String {
+        "key:".to_string()
+    }
+
+    fn default_value() -> String {
+        "random://64".to_string()
+    }
+}
+
+impl RedisCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            RedisCommand::Get => "GET",
+            RedisCommand::Set => "SET",
+            RedisCommand::Incr => "INCR",
+        }
+    }
+}
+
+/// Sends Redis commands (optionally pipelined) on a persistent connection per client.
+/// Each request is reported with the command name as the `operation_name`.
+#[derive(Builder, Clone)]
+pub struct RedisBenchAdapter {
+    targets: Vec<String>,
+    commands: Vec<RedisCommand>,
+    #[builder(default = "1")]
+    pipeline: usize,
+    keyspace: u64,
+    #[builder(default)]
+    key_prefix: String,
+    #[builder(default)]
+    value: Bytes,
+    #[builder(default)]
+    password: Option<String>,
+}
+
+/// Connection of a single client. Re-opened after an error.
+pub struct RedisClient {
+    connection: Connection<BufReader<TcpStream>>,
+}
+
+/// A parsed reply: its size in bytes, the error kind (e.g. `ERR`), if any, and if it's a nil.
+#[derive(Debug, PartialEq, Eq)]
+struct Reply {
+    size: usize,
+    error: Option<String>,
+    nil: bool,
+}
+
+impl RedisBenchAdapter {
+    pub fn from_options(options: RedisOptions) -> Result<RedisBenchAdapter, ConfigErrors> {
+        let mut errors = vec![];
+
+        validate_targets(&options.target, &mut errors);
+        if options.command.is_empty() {
+            errors.push(ConfigError::MissingValue {
+                flag: "command",
+                reason: "at least one command is required".to_string(),
+            });
+        }
+        if options.pipeline == 0 {
+            errors.push(ConfigError::InvalidValue {
+                flag: "pipeline",
+                value: "0".to_string(),
+                reason: "must be positive".to_string(),
+            });
+        }
+        if options.keyspace == 0 {
+            errors.push(ConfigError::InvalidValue {
+                flag: "keyspace",
+                value: "0".to_string(),
+                reason: "must be positive".to_string(),
+            });
+        }
+
+        let value =
+            BenchmarkConfig::generate_body("value", Some(&options.value)).unwrap_or_else(|e| {
+                errors.push(e);
+                Bytes::new()
+            });
+
+        if !errors.is_empty() {
+            return Err(ConfigErrors(errors));
+        }
+
+        Ok(RedisBenchAdapterBuilder::default()
+            .targets(options.target)
+            .commands(options.command)
+            .pipeline(options.pipeline)
+            .keyspace(options.keyspace)
+            .key_prefix(options.key_prefix)
+            .value(value)
+            .password(options.password)
+            .build()
+            .expect("RedisBenchAdapterBuilder failed"))
+    }
+
+    async fn connect(&self) -> Result<BufReader<TcpStream>, SocketError> {
+        let target = self
+            .targets
+            .choose(&mut thread_rng())
+            .expect("At least one target is required");
+        let stream = TcpStream::connect(target)
+            .await
+            .map_err(SocketError::connect)?;
+        stream.set_nodelay(true).map_err(SocketError::connect)?;
+        let mut connection = BufReader::new(stream);
+
+        if let Some(password) = &self.password {
+            let mut auth = BytesMut::new();
+            RedisBenchAdapter::encode(&mut auth, &[b"AUTH", password.as_bytes()]);
+            connection
+                .write_all(&auth)
+                .await
+                .map_err(SocketError::connect)?;
+            let reply = RedisBenchAdapter::read_reply(&mut connection)
+                .await
+                .map_err(SocketError::connect)?;
+            if let Some(e) = reply.error {
+                return Err(SocketError::connect(format!("AUTH failed: {e}")));
+            }
+        }
+        Ok(connection)
+    }
+
+    /// Encodes a command as a RESP array of bulk strings.
+    fn encode(buf: &mut BytesMut, args: &[&[u8]]) {
+        buf.put_slice(format!("*{}\r\n", args.len()).as_bytes());
+        for arg in args {
+            buf.put_slice(format!("${}\r\n", arg.len()).as_bytes());
+            buf.put_slice(arg);
+            buf.put_slice(b"\r\n");
+        }
+    }
+
+    fn build_commands(&self, command: RedisCommand) -> Bytes {
+        let mut rng = thread_rng();
+        let mut buf = BytesMut::new();
+        for _ in 0..self.pipeline {
+            let key = format!("{}{}", self.key_prefix, rng.gen_range(0..self.keyspace));
+            let name = command.name().as_bytes();
+            match command {
+                RedisCommand::Set => {
+                    RedisBenchAdapter::encode(&mut buf, &[name, key.as_bytes(), &self.value])
+                }
+                RedisCommand::Get | RedisCommand::Incr => {
+                    RedisBenchAdapter::encode(&mut buf, &[name, key.as_bytes()])
+                }
+            }
+        }
+        buf.freeze()
+    }
+
+    /// Reads a single reply, including nested ones for arrays.
+    async fn read_reply(connection: &mut BufReader<TcpStream>) -> io::Result<Reply> {
+        let mut reply = Reply {
+            size: 0,
+            error: None,
+            nil: false,
+        };
+        // replies left to read, as arrays contain nested ones
+        let mut remaining = 1;
+        while remaining > 0 {
+            remaining -= 1;
+            let mut line = String::new();
+            let n = connection.read_line(&mut line).await?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed",
+                ));
+            }
+            reply.size += n;
+            let line = line.trim_end();
+            let (kind, content) = line.split_at(line.len().min(1));
+            let invalid =
+                || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid reply: {line}"));
+            let length = || content.parse::<i64>().map_err(|_| invalid());
+            match kind {
+                "+" | ":" => {}
+                "-" => {
+                    let kind = content.split(' ').next().unwrap_or_default();
+                    reply.error.get_or_insert_with(|| kind.to_string());
+                }
+                "$" => {
+                    let length = length()?;
+                    if length < 0 {
+                        reply.nil = true;
+                    } else {
+                        // the content and CRLF
+                        let mut data = vec![0; length as usize + 2];
+                        connection.read_exact(&mut data).await?;
+                        reply.size += data.len();
+                    }
+                }
+                "*" => remaining += length()?.max(0),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(reply)
+    }
+
+    async fn round_trip(
+        &self,
+        connection: &mut BufReader<TcpStream>,
+        command: RedisCommand,
+    ) -> io::Result<Reply> {
+        connection.write_all(&self.build_commands(command)).await?;
+
+        let mut result = RedisBenchAdapter::read_reply(connection).await?;
+        for _ in 1..self.pipeline {
+            let reply = RedisBenchAdapter::read_reply(connection).await?;
+            result.size += reply.size;
+            result.nil |= reply.nil;
+            if result.error.is_none() {
+                result.error = reply.error;
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl BenchmarkProtocolAdapter for RedisBenchAdapter {
+    type Client = RedisClient;
+
+    async fn build_client(&self) -> Result<Self::Client, String> {
+        let connected = self.connect().await.map_err(|e| e.to_string())?;
+        Ok(RedisClient {
+            connection: Connection::new(Some(connected)),
+        })
+    }
+
+    /// Requests share the connection of a client, one at a time.
+    fn supports_open_model(&self) -> bool {
+        false
+    }
+
+    async fn send_request(&self, client: &Self::Client) -> RequestStats {
+        let command = *self
+            .commands
+            .choose(&mut thread_rng())
+            .expect("At least one command is required");
+        let mut connection = client.connection.lock().await;
+        let start = Instant::now();
+
+        let (result, connect) = match connection.open(self.connect()).await {
+            Ok((connected, connect)) => (
+                self.round_trip(connected, command)
+                    .await
+                    .map_err(SocketError::request),
+                connect,
+            ),
+            Err(e) => (Err(e), None),
+        };
+        if result.is_err() {
+            // re-connect on the next request
+            connection.close();
+        }
+
+        let error;
+        let result = match result {
+            Ok(Reply {
+                size,
+                error: Some(e),
+                ..
+            }) => {
+                error = e;
+                Ok(Outcome {
+                    size,
+                    status: &error,
+                    is_success: false,
+                })
+            }
+            Ok(Reply {
+                size, nil: true, ..
+            }) => Ok(Outcome::success(size, "nil")),
+            Ok(Reply { size, .. }) => Ok(Outcome::success(size, "OK")),
+            Err(e) => Err(e),
+        };
+        request_stats(Some(command.name()), result, start, connect)
+    }
+}
+
+impl fmt::Display for RedisBenchAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Redis {:?}, commands: {:?}, pipeline: {}, keyspace: {}, value size: {} bytes",
+            self.targets,
+            self.commands,
+            self.pipeline,
+            self.keyspace,
+            self.value.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_run::BenchmarkProtocolAdapter;
+    use crate::redis_bench_session::{RedisBenchAdapter, RedisOptions};
+    use crate::socket_session::tests::{parse_options, start_tcp_server};
+    use bytes::BytesMut;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    /// Replies with canned responses by the command name.
+    async fn start_server() -> SocketAddr {
+        start_tcp_server(|stream| async move {
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            while stream.read_line(&mut line).await.unwrap_or_default() > 0 {
+                let args: usize = line.trim()[1..].parse().unwrap();
+                let mut command = vec![];
+                for _ in 0..args {
+                    line.clear();
+                    stream.read_line(&mut line).await.unwrap();
+                    let mut arg = vec![0; line.trim()[1..].parse::<usize>().unwrap() + 2];
+                    stream.read_exact(&mut arg).await.unwrap();
+                    command.push(String::from_utf8_lossy(&arg).trim().to_string());
+                }
+                let reply: &[u8] = match command[0].as_str() {
+                    "GET" => b"$5\r\nhello\r\n",
+                    "SET" => b"+OK\r\n",
+                    "INCR" => b"-WRONGTYPE Operation against a key\r\n",
+                    _ => b"-ERR unknown command\r\n",
+                };
+                stream.write_all(reply).await.unwrap();
+                line.clear();
+            }
+        })
+        .await
+    }
+
+    fn build_adapter(args: &[&str]) -> RedisBenchAdapter {
+        let options: RedisOptions = parse_options(["redis"].iter().chain(args));
+        let adapter = RedisBenchAdapter::from_options(options).unwrap();
+        assert!(adapter.to_string().starts_with("Redis"));
+        adapter
+    }
+
+    #[tokio::test]
+    async fn test_redis_commands() {
+        let addr = start_server().await.to_string();
+
+        let adapter = build_adapter(&[&addr, "--pipeline", "3"]);
+        let client = adapter.build_client().await.unwrap();
+        let stats = adapter.send_request(&client).await;
+        assert!(stats.is_success);
+        assert_eq!(Some("GET".to_string()), stats.operation_name);
+        assert_eq!(3 * 11, stats.bytes_processed);
+
+        let adapter = build_adapter(&[&addr, "--command", "set", "-B", "random://10"]);
+        let stats = adapter.send_request(&client).await;
+        assert!(stats.is_success);
+        assert_eq!("OK", stats.status);
+        assert_eq!(Some("SET".to_string()), stats.operation_name);
+
+        let adapter = build_adapter(&[&addr, "--command", "incr"]);
+        let stats = adapter.send_request(&client).await;
+        assert!(!stats.is_success);
+        assert_eq!("WRONGTYPE", stats.status);
+        assert_eq!(Some("INCR".to_string()), stats.operation_name);
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut buf = BytesMut::new();
+        RedisBenchAdapter::encode(&mut buf, &[b"GET", b"key:1"]);
+        assert_eq!(&b"*2\r\n$3\r\nGET\r\n$5\r\nkey:1\r\n"[..], &buf[..]);
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let options: RedisOptions =
+            parse_options(["redis", "localhost", "--pipeline", "0", "--keyspace", "0"]);
+        let errors = RedisBenchAdapter::from_options(options).err().unwrap();
+        assert_eq!(3, errors.0.len());
+    }
+}
@@ -0,0 +1,405 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::bench_session::BenchSession;
+use crate::configuration::BenchmarkConfig;
+use crate::metrics::{BenchRunMetrics, ExternalMetricsServiceReporter};
+use crate::throughput_search::ThroughputSearch;
+use log::{error, info};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+
+/// Runs the benchmark on the current tokio runtime.
+/// Metrics of each batch are passed to `on_batch` and then to the reporters of the config.
+/// Returns the completed throughput search, if the config has one, or the error of the first batch
+/// that failed to run (e.g. its clients could not be built). Batches after it are not run.
+pub async fn run_benchmark(
+    mut benchmark_config: BenchmarkConfig,
+    mut on_batch: impl FnMut(&BenchRunMetrics),
+) -> Result<Option<ThroughputSearch>, String> {
+    let (reporter_task, batch_metric_sender) =
+        create_async_metrics_channel(&benchmark_config.reporters, benchmark_config.continuous);
+    let mut bench_session = benchmark_config.new_bench_session();
+
+    let result = if let Some(throughput_search) = benchmark_config.throughput_search.take() {
+        search_throughput(
+            throughput_search,
+            &mut bench_session,
+            &mut on_batch,
+            &batch_metric_sender,
+        )
+        .await
+        .map(Some)
+    } else {
+        run_batches(bench_session, &mut on_batch, &batch_metric_sender)
+            .await
+            .map(|_| None)
+    };
+
+    shutdown(reporter_task, batch_metric_sender).await;
+
+    result
+}
+
+async fn run_batches(
+    bench_session: BenchSession,
+    on_batch: &mut impl FnMut(&BenchRunMetrics),
+    batch_metric_sender: &Sender<BenchRunMetrics>,
+) -> Result<(), String> {
+    for batch in bench_session {
+        info!("Running next batch {}", batch);
+        let stats = batch.run(BenchRunMetrics::new()).await.map_err(|e| {
+            error!("Unexpected error during batch run: {}", e);
+            e
+        })?;
+        let stop_session = stop_session(&stats);
+        on_batch(&stats);
+        batch_metric_sender.send(stats).unwrap_or_default();
+        if stop_session {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn search_throughput(
+    mut throughput_search: ThroughputSearch,
+    bench_session: &mut BenchSession,
+    on_batch: &mut impl FnMut(&BenchRunMetrics),
+    batch_metric_sender: &Sender<BenchRunMetrics>,
+) -> Result<ThroughputSearch, String> {
+    while let Some(rate) = throughput_search.next_rate() {
+        let batch = bench_session.batch_at(rate);
+        info!("Probing rate {:.3} with batch {}", rate, batch);
+        let stats = batch.run(BenchRunMetrics::new()).await.map_err(|e| {
+            error!("Unexpected error during batch run: {}", e);
+            e
+        })?;
+        let stop_session = stop_session(&stats);
+        throughput_search.record(rate, &stats);
+        on_batch(&stats);
+        batch_metric_sender.send(stats).unwrap_or_default();
+        if stop_session {
+            break;
+        }
+    }
+
+    Ok(throughput_search)
+}
+
+/// Whether the circuit breaker stopped the whole session.
+fn stop_session(stats: &BenchRunMetrics) -> bool {
+    match stats.aborted() {
+        Some(abort) if abort.stop_session => {
+            error!("Stopping the session: {}", abort.reason);
+            true
+        }
+        _ => false,
+    }
+}
+
+async fn shutdown(reporter_task: JoinHandle<()>, batch_metric_sender: Sender<BenchRunMetrics>) {
+    // we need to drop it explicitly, to signal completion to the `mpsc` thread.
+    drop(batch_metric_sender);
+    // the reporters may still be sending metrics, which must not block the runtime of the caller
+    tokio::task::spawn_blocking(move || reporter_task.join())
+        .await
+        .ok();
+}
+
+fn create_async_metrics_channel(
+    metric_reporters: &[Arc<dyn ExternalMetricsServiceReporter + Send + Sync + 'static>],
+    continuous: bool,
+) -> (JoinHandle<()>, Sender<BenchRunMetrics>) {
+    // We need to report metrics in a separate threads,
+    // as at the moment of writing this code not all major metric client libraries
+    // had `async` APIs.
+    // We can replace it with `tokio::sync::mpsc` and `tokio::spawn` at any time
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let metric_reporters = metric_reporters.to_owned();
+    let reporter_task = thread::spawn(move || {
+        while let Ok(stats) = receiver.recv() {
+            // broadcast to all metrics reporters
+            for reporter in &metric_reporters {
+                if let Err(e) = reporter.report(&stats) {
+                    error!("Error sending metrics: {}", e);
+                }
+            }
+        }
+        // for continuous runs we don't want to reset metrics
+        // to avoid saw-like graphs
+        if !continuous {
+            for reporter in metric_reporters {
+                reporter.reset_metrics();
+            }
+        }
+    });
+    (reporter_task, sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_run::BenchmarkProtocolAdapter;
+    use crate::bench_session::RateLadderBuilder;
+    use crate::circuit_breaker::CircuitBreakerBuilder;
+    use crate::configuration::{BenchmarkConfigBuilder, BenchmarkMode};
+    use crate::http_bench_session::{
+        HttpBenchAdapterBuilder, HttpClientConfigBuilder, HttpRequestBuilder,
+    };
+    use crate::metrics::{BenchRunMetrics, ExternalMetricsServiceReporter, RequestStats};
+    use crate::runner::run_benchmark;
+    use crate::throughput_search::{SloCriteriaBuilder, ThroughputSearchBuilder};
+    use async_trait::async_trait;
+    use core::fmt;
+    use mockito::Server;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[derive(Default)]
+    struct CountingReporter {
+        requests: AtomicUsize,
+    }
+
+    impl ExternalMetricsServiceReporter for CountingReporter {
+        fn report(&self, metrics: &BenchRunMetrics) -> io::Result<()> {
+            self.requests
+                .fetch_add(metrics.combined().total_requests(), Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn reset_metrics(&self) {}
+    }
+
+    #[tokio::test]
+    async fn test_run_benchmark() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/1")
+            .with_status(200)
+            .with_body("hello")
+            .create_async()
+            .await;
+
+        let reporter = Arc::new(CountingReporter::default());
+        let config = BenchmarkConfigBuilder::default()
+            .concurrency(2)
+            .rate_ladder(
+                RateLadderBuilder::default()
+                    .start(100.)
+                    .end(200.)
+                    .rate_increment(Some(100.))
+                    .step_duration(None)
+                    .step_requests(Some(5))
+                    .build()
+                    .unwrap(),
+            )
+            .mode(BenchmarkMode::Http(
+                HttpBenchAdapterBuilder::default()
+                    .config(HttpClientConfigBuilder::default().build().unwrap())
+                    .request(
+                        HttpRequestBuilder::default()
+                            .url(vec![format!("http://{}/1", server.host_with_port())])
+                            .build()
+                            .unwrap(),
+                    )
+                    .build()
+                    .unwrap(),
+            ))
+            .reporters(vec![reporter.clone()])
+            .build()
+            .unwrap();
+
+        let mut batches = vec![];
+        let throughput_search = run_benchmark(config, |metrics| {
+            batches.push(metrics.combined().successful_requests())
+        })
+        .await
+        .expect("Batches are run");
+
+        assert!(throughput_search.is_none());
+        // 100, 200 and one more iteration at the max rate
+        assert_eq!(vec![10, 10, 10], batches);
+        assert_eq!(30, reporter.requests.load(Ordering::SeqCst));
+    }
+
+    /// Clients can't be built, e.g. the target doesn't resolve.
+    struct FailingAdapter;
+
+    #[async_trait]
+    impl BenchmarkProtocolAdapter for FailingAdapter {
+        type Client = ();
+
+        async fn build_client(&self) -> Result<Self::Client, String> {
+            Err("Cannot build a client".to_string())
+        }
+
+        async fn send_request(&self, _: &Self::Client) -> RequestStats {
+            unreachable!("No clients")
+        }
+    }
+
+    impl fmt::Display for FailingAdapter {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("failing")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_benchmark_error() {
+        let reporter = Arc::new(CountingReporter::default());
+        let config = BenchmarkConfigBuilder::default()
+            .rate_ladder(
+                RateLadderBuilder::default()
+                    .start(100.)
+                    .end(200.)
+                    .rate_increment(Some(100.))
+                    .step_duration(None)
+                    .step_requests(Some(5))
+                    .build()
+                    .unwrap(),
+            )
+            .mode(BenchmarkMode::Custom(Arc::new(FailingAdapter)))
+            .reporters(vec![reporter.clone()])
+            .build()
+            .unwrap();
+
+        let mut batches = 0;
+        let result = run_benchmark(config, |_| batches += 1).await;
+
+        assert_eq!(Err("Cannot build a client".to_string()), result.map(|_| ()));
+        // the next batches are not run
+        assert_eq!(0, batches);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_stops_session() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/1")
+            .with_status(500)
+            .create_async()
+            .await;
+
+        let config = BenchmarkConfigBuilder::default()
+            .rate_ladder(
+                RateLadderBuilder::default()
+                    .start(50.)
+                    .end(100.)
+                    .rate_increment(Some(50.))
+                    .step_duration(Some(Duration::from_secs(5)))
+                    .step_requests(None)
+                    .build()
+                    .unwrap(),
+            )
+            .mode(BenchmarkMode::Http(
+                HttpBenchAdapterBuilder::default()
+                    .config(HttpClientConfigBuilder::default().build().unwrap())
+                    .request(
+                        HttpRequestBuilder::default()
+                            .url(vec![format!("http://{}/1", server.host_with_port())])
+                            .build()
+                            .unwrap(),
+                    )
+                    .build()
+                    .unwrap(),
+            ))
+            .circuit_breaker(Some(
+                CircuitBreakerBuilder::default()
+                    .window(Duration::from_millis(500))
+                    .max_error_rate(Some(10.))
+                    .stop_session(true)
+                    .build()
+                    .unwrap(),
+            ))
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        let mut aborted = vec![];
+        run_benchmark(config, |metrics| {
+            aborted.push(metrics.aborted().map(|abort| abort.reason.clone()))
+        })
+        .await
+        .expect("Batches are run");
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(1, aborted.len());
+        // checked on the elapsed part of the window, once it holds enough requests
+        assert!(aborted[0]
+            .as_ref()
+            .unwrap()
+            .starts_with("Error rate 100.000% is above 10.000% over the last"));
+    }
+
+    #[tokio::test]
+    async fn test_search_slow_target() {
+        let mut server = Server::new_async().await;
+        // a single client can't send more than 20 requests per second
+        let _m = server
+            .mock("GET", "/1")
+            .with_status(200)
+            .with_chunked_body(|w| {
+                std::thread::sleep(Duration::from_millis(50));
+                w.write_all(b"hello")
+            })
+            .create_async()
+            .await;
+
+        let config = BenchmarkConfigBuilder::default()
+            .rate_ladder(
+                RateLadderBuilder::default()
+                    .start(10.)
+                    .end(10.)
+                    .rate_increment(None)
+                    .step_duration(None)
+                    .step_requests(Some(10))
+                    .build()
+                    .unwrap(),
+            )
+            .mode(BenchmarkMode::Http(
+                HttpBenchAdapterBuilder::default()
+                    .config(HttpClientConfigBuilder::default().build().unwrap())
+                    .request(
+                        HttpRequestBuilder::default()
+                            .url(vec![format!("http://{}/1", server.host_with_port())])
+                            .build()
+                            .unwrap(),
+                    )
+                    .build()
+                    .unwrap(),
+            ))
+            .throughput_search(Some(
+                ThroughputSearchBuilder::default()
+                    .slo(
+                        SloCriteriaBuilder::default()
+                            .max_error_rate(Some(0.))
+                            .build()
+                            .unwrap(),
+                    )
+                    .start_rate(10.)
+                    .max_rate(Some(80.))
+                    .precision(30.)
+                    .build()
+                    .unwrap(),
+            ))
+            .build()
+            .unwrap();
+
+        let throughput_search = run_benchmark(config, |_| {})
+            .await
+            .expect("Batches are run")
+            .expect("The search must be run");
+
+        // all requests succeed, but rates above 20 are never reached
+        let result = throughput_search.result().expect("10 rps must be met");
+        assert!((10. ..40.).contains(&result), "Result: {result}");
+    }
+}
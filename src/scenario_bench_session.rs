@@ -0,0 +1,666 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::bench_run::BenchmarkProtocolAdapter;
+use crate::config_error::{ConfigError, ConfigErrors};
+use crate::configuration::BenchmarkConfig;
+use crate::error_category::ErrorCategory;
+use crate::feeder::Row;
+use crate::http_bench_session::{
+    HttpBenchAdapter, HttpBenchAdapterBuilder, HttpClient, HttpClientConfigBuilder,
+    HttpRequestBuilder,
+};
+use crate::metrics::{RequestStats, RequestStatsBuilder};
+use crate::template::Template;
+use async_trait::async_trait;
+use bytes::Bytes;
+use clap::Args;
+use core::fmt;
+use http_body_util::{BodyExt, Full};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{HeaderMap, Method, Request, Uri};
+use regex::Regex;
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[derive(Args, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+#[command(about = "Run multi-step HTTP scenarios", long_about = None)]
+pub struct ScenarioOptions {
+    /// YAML file with the steps, e.g. `[{name: home, url: "http://localhost/"}]`.
+    /// In config files the steps can be given in the `step` list instead.
+    #[arg()]
+    file: Option<String>,
+    #[arg(skip)]
+    #[serde(default)]
+    step: Vec<StepOptions>,
+    /// Allow self signed certificates.
+    #[arg(long = "ignore_cert")]
+    #[serde(default)]
+    ignore_cert: bool,
+    /// If connections should be re-used.
+    #[arg(long = "conn_reuse")]
+    #[serde(default)]
+    conn_reuse: bool,
+}
+
+/// A request of the scenario. Values extracted from the responses of the previous steps
+/// are available as `{{name}}` placeholders.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct StepOptions {
+    /// Reported as the operation name.
+    name: String,
+    url: String,
+    method: Option<String>,
+    /// Headers in "Name:Value" form.
+    #[serde(default)]
+    header: Vec<String>,
+    /// Could be either `random://[0-9]+`, `file://$filename` or `base64://${valid_base64}`.
+    body: Option<String>,
+    #[serde(default)]
+    extract: Vec<ExtractOptions>,
+}
+
+/// Where a value comes from: exactly one of `json`, `regex` or `header`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct ExtractOptions {
+    name: String,
+    /// JSON pointer in the response body, e.g. `/data/token`.
+    json: Option<String>,
+    /// Regular expression matched against the response body. The first capture group if any, e.g. `id=(\d+)`.
+    regex: Option<String>,
+    /// Response header.
+    header: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum Extractor {
+    Json(String),
+    Regex(Regex),
+    Header(HeaderName),
+}
+
+#[derive(Clone, Debug)]
+struct Extraction {
+    /// Index of the scenario variable.
+    variable: usize,
+    extractor: Extractor,
+}
+
+#[derive(Clone, Debug)]
+struct Step {
+    name: String,
+    url: Template,
+    method: Method,
+    headers: Vec<(HeaderName, Template)>,
+    body: Bytes,
+    /// If the body is text with placeholders.
+    body_template: Option<Template>,
+    extract: Vec<Extraction>,
+}
+
+/// Runs the steps in order, one per request, and starts over after the last one or a failed one.
+/// Each client runs its own scenario iterations.
+#[derive(Clone)]
+pub struct ScenarioBenchAdapter {
+    /// Builds the clients.
+    http: HttpBenchAdapter,
+    steps: Vec<Step>,
+    /// Names of the extracted values.
+    variables: Vec<String>,
+    /// The `{{counter}}`, by scenario iteration.
+    iterations: Arc<AtomicU64>,
+}
+
+pub struct ScenarioClient {
+    http: HttpClient,
+    state: Mutex<ScenarioState>,
+}
+
+#[derive(Default)]
+struct ScenarioState {
+    step: usize,
+    iteration: u64,
+    values: Vec<String>,
+}
+
+impl ScenarioBenchAdapter {
+    pub fn from_options(options: ScenarioOptions) -> Result<ScenarioBenchAdapter, ConfigErrors> {
+        let mut errors = vec![];
+
+        let step_options = match (&options.file, options.step.is_empty()) {
+            (Some(_), false) => {
+                return Err(ConfigError::Conflict {
+                    flag: "file",
+                    other: "step",
+                }
+                .into())
+            }
+            (Some(filename), true) => ScenarioBenchAdapter::read_steps(filename)?,
+            (None, false) => options.step,
+            (None, true) => {
+                return Err(ConfigError::MissingValue {
+                    flag: "step",
+                    reason: "at least one step is required".to_string(),
+                }
+                .into())
+            }
+        };
+
+        let mut variables: Vec<String> = vec![];
+        let mut steps = vec![];
+        for step in &step_options {
+            if let Some(step) = ScenarioBenchAdapter::parse_step(step, &mut variables, &mut errors)
+            {
+                steps.push(step);
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(ConfigErrors(errors));
+        }
+
+        let http = HttpBenchAdapterBuilder::default()
+            .config(
+                HttpClientConfigBuilder::default()
+                    .ignore_cert(options.ignore_cert)
+                    .conn_reuse(options.conn_reuse)
+                    .build()
+                    .expect("HttpClientConfigBuilder failed"),
+            )
+            .request(
+                HttpRequestBuilder::default()
+                    .url(vec![step_options[0].url.clone()])
+                    .build()
+                    .expect("HttpRequestBuilder failed"),
+            )
+            .build()
+            .expect("HttpBenchAdapterBuilder failed");
+
+        Ok(ScenarioBenchAdapter {
+            http,
+            steps,
+            variables,
+            iterations: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    fn read_steps(filename: &str) -> Result<Vec<StepOptions>, ConfigError> {
+        let invalid_file = |reason: String| ConfigError::InvalidValue {
+            flag: "file",
+            value: filename.to_string(),
+            reason,
+        };
+        let content = std::fs::read_to_string(filename).map_err(|e| invalid_file(e.to_string()))?;
+        serde_yaml::from_str(&content).map_err(|e| invalid_file(e.to_string()))
+    }
+
+    /// Placeholders can use the `variables` extracted by the previous steps.
+    fn parse_step(
+        step: &StepOptions,
+        variables: &mut Vec<String>,
+        errors: &mut Vec<ConfigError>,
+    ) -> Option<Step> {
+        let errors_before = errors.len();
+        let template = |flag: &'static str, value: &str, errors: &mut Vec<ConfigError>| {
+            ScenarioBenchAdapter::parse_template(value, variables)
+                .map_err(|reason| {
+                    errors.push(ConfigError::InvalidValue {
+                        flag,
+                        value: value.to_string(),
+                        reason,
+                    })
+                })
+                .ok()
+        };
+
+        let url = template("url", &step.url, errors);
+        let mut headers = vec![];
+        for header in &step.header {
+            match header.split_once(':') {
+                Some((name, value)) if HeaderName::from_bytes(name.as_bytes()).is_ok() => {
+                    if let Some(value) = template("header", value, errors) {
+                        headers.push((
+                            HeaderName::from_bytes(name.as_bytes()).expect("Checked above"),
+                            value,
+                        ));
+                    }
+                }
+                _ => errors.push(ConfigError::InvalidValue {
+                    flag: "header",
+                    value: header.clone(),
+                    reason: "expected a valid `Name:Value`".to_string(),
+                }),
+            }
+        }
+        let body = BenchmarkConfig::generate_body("body", step.body.as_deref())
+            .map_err(|e| errors.push(e))
+            .unwrap_or_default();
+        // random bodies are not templates
+        let body_template = match std::str::from_utf8(&body) {
+            Ok(text)
+                if !step
+                    .body
+                    .as_deref()
+                    .unwrap_or_default()
+                    .starts_with("random://") =>
+            {
+                template("body", text, errors).filter(|template| !template.is_static())
+            }
+            _ => None,
+        };
+        let method = Method::from_bytes(step.method.as_deref().unwrap_or("GET").as_bytes())
+            .map_err(|e| {
+                errors.push(ConfigError::InvalidValue {
+                    flag: "method",
+                    value: step.method.clone().unwrap_or_default(),
+                    reason: e.to_string(),
+                })
+            })
+            .ok();
+
+        // available to the next steps
+        let mut extract = vec![];
+        for options in &step.extract {
+            if let Some(extractor) = ScenarioBenchAdapter::parse_extractor(options, errors) {
+                let variable = match variables.iter().position(|v| *v == options.name) {
+                    Some(variable) => variable,
+                    None => {
+                        variables.push(options.name.clone());
+                        variables.len() - 1
+                    }
+                };
+                extract.push(Extraction {
+                    variable,
+                    extractor,
+                });
+            }
+        }
+
+        if let Some(url) = &url {
+            let sample = url.render(1, None);
+            if let Err(e) = Uri::from_str(&sample) {
+                errors.push(ConfigError::InvalidValue {
+                    flag: "url",
+                    value: step.url.clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+
+        if errors.len() != errors_before {
+            return None;
+        }
+        Some(Step {
+            name: step.name.clone(),
+            url: url?,
+            method: method?,
+            headers,
+            body,
+            body_template,
+            extract,
+        })
+    }
+
+    /// Only the `variables` can be used in the placeholders.
+    fn parse_template(value: &str, variables: &[String]) -> Result<Template, String> {
+        let template = Template::parse(value)?;
+        if let Some(variable) = template
+            .variables()
+            .find(|variable| !variables.iter().any(|v| v == variable))
+        {
+            return Err(format!(
+                "`{{{{{variable}}}}}` is not extracted by the previous steps"
+            ));
+        }
+        Ok(template)
+    }
+
+    fn parse_extractor(
+        options: &ExtractOptions,
+        errors: &mut Vec<ConfigError>,
+    ) -> Option<Extractor> {
+        let invalid_extract = |reason: String| ConfigError::InvalidValue {
+            flag: "extract",
+            value: options.name.clone(),
+            reason,
+        };
+        match (&options.json, &options.regex, &options.header) {
+            (Some(pointer), None, None) if pointer.is_empty() || pointer.starts_with('/') => {
+                Some(Extractor::Json(pointer.clone()))
+            }
+            (Some(pointer), None, None) => {
+                errors.push(invalid_extract(format!(
+                    "JSON pointer `{pointer}` must start with `/`"
+                )));
+                None
+            }
+            (None, Some(regex), None) => Regex::new(regex)
+                .map(Extractor::Regex)
+                .map_err(|e| errors.push(invalid_extract(e.to_string())))
+                .ok(),
+            (None, None, Some(header)) => HeaderName::from_bytes(header.as_bytes())
+                .map(Extractor::Header)
+                .map_err(|e| errors.push(invalid_extract(e.to_string())))
+                .ok(),
+            _ => {
+                errors.push(invalid_extract(
+                    "expected exactly one of `json`, `regex` or `header`".to_string(),
+                ));
+                None
+            }
+        }
+    }
+
+    fn build_request(
+        &self,
+        step: &Step,
+        counter: u64,
+        values: Row,
+    ) -> Result<Request<Full<Bytes>>, String> {
+        let mut request_builder = Request::builder()
+            .method(step.method.clone())
+            .uri(step.url.render(counter, Some(values)));
+        for (name, value) in &step.headers {
+            let value = HeaderValue::from_str(&value.render(counter, Some(values)))
+                .map_err(|e| e.to_string())?;
+            request_builder = request_builder.header(name, value);
+        }
+        let body = match &step.body_template {
+            Some(template) => Bytes::from(template.render(counter, Some(values))),
+            None => step.body.clone(),
+        };
+        request_builder
+            .body(Full::new(body))
+            .map_err(|e| e.to_string())
+    }
+
+    /// The status, if the step succeeded and the size of the response.
+    /// The extracted values are stored in `values`.
+    async fn send_step(
+        &self,
+        client: &ScenarioClient,
+        step: &Step,
+        request: Request<Full<Bytes>>,
+        values: &mut [String],
+    ) -> (String, bool, usize) {
+        let (parts, body) = match client.http.pool.request(request).await {
+            Ok(response) => response.into_parts(),
+            Err(e) => return (ErrorCategory::classify(&e).status(&e), false, 0),
+        };
+        let body = match body.collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => return (ErrorCategory::BodyError.status(&e), false, 0),
+        };
+        if !parts.status.is_success() {
+            return (parts.status.to_string(), false, body.len());
+        }
+        for extraction in &step.extract {
+            match ScenarioBenchAdapter::extract(&extraction.extractor, &parts.headers, &body) {
+                Some(value) => values[extraction.variable] = value,
+                None => {
+                    let variable = &self.variables[extraction.variable];
+                    return (format!("`{variable}` is not extracted"), false, body.len());
+                }
+            }
+        }
+        (parts.status.to_string(), true, body.len())
+    }
+
+    /// The value, if the response has it.
+    fn extract(extractor: &Extractor, headers: &HeaderMap, body: &[u8]) -> Option<String> {
+        match extractor {
+            Extractor::Json(pointer) => {
+                let json: serde_json::Value = serde_json::from_slice(body).ok()?;
+                match json.pointer(pointer)? {
+                    serde_json::Value::String(value) => Some(value.clone()),
+                    value => Some(value.to_string()),
+                }
+            }
+            Extractor::Regex(regex) => {
+                let captures = regex.captures(std::str::from_utf8(body).ok()?)?;
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .map(|value| value.as_str().to_string())
+            }
+            Extractor::Header(name) => headers.get(name)?.to_str().ok().map(String::from),
+        }
+    }
+
+    /// Moves on to the next step, or starts over if the `step` failed.
+    fn complete_step(&self, client: &ScenarioClient, step: usize, values: Option<Vec<String>>) {
+        let mut state = client.state.lock().expect("Scenario lock is poisoned");
+        match values {
+            Some(values) => {
+                state.values = values;
+                state.step = (step + 1) % self.steps.len();
+            }
+            None => state.step = 0,
+        }
+    }
+}
+
+#[async_trait]
+impl BenchmarkProtocolAdapter for ScenarioBenchAdapter {
+    type Client = ScenarioClient;
+
+    async fn build_client(&self) -> Result<Self::Client, String> {
+        Ok(ScenarioClient {
+            http: self.http.build_client().await?,
+            state: Mutex::new(ScenarioState::default()),
+        })
+    }
+
+    /// Steps of a client run in order, one at a time.
+    fn supports_open_model(&self) -> bool {
+        false
+    }
+
+    async fn send_request(&self, client: &Self::Client) -> RequestStats {
+        let start = Instant::now();
+        let (index, iteration, mut values) = {
+            let mut state = client.state.lock().expect("Scenario lock is poisoned");
+            if state.step == 0 {
+                state.iteration = self.iterations.fetch_add(1, Ordering::Relaxed) + 1;
+                state.values = vec![String::new(); self.variables.len()];
+            }
+            (state.step, state.iteration, state.values.clone())
+        };
+        let step = &self.steps[index];
+
+        let (status, success, bytes_processed) =
+            match self.build_request(step, iteration, Row::new(&self.variables, &values)) {
+                Ok(request) => self.send_step(client, step, request, &mut values).await,
+                Err(e) => (format!("invalid request: {e}"), false, 0),
+            };
+
+        self.complete_step(client, index, success.then_some(values));
+        RequestStatsBuilder::default()
+            .bytes_processed(bytes_processed)
+            .status(status)
+            .is_success(success)
+            .duration(Instant::now().duration_since(start))
+            .operation_name(Some(step.name.clone()))
+            .fatal_error(false)
+            .build()
+            .expect("RequestStatsBuilder failed")
+    }
+}
+
+impl fmt::Display for ScenarioBenchAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps: Vec<&str> = self.steps.iter().map(|step| step.name.as_str()).collect();
+        write!(
+            f,
+            "Scenario steps={:?}, extracted={:?}",
+            steps, self.variables
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_run::BenchmarkProtocolAdapter;
+    use crate::config_error::ConfigError;
+    use crate::scenario_bench_session::{ScenarioBenchAdapter, ScenarioOptions};
+    use mockito::Matcher::Exact;
+
+    fn options(yaml: &str) -> ScenarioOptions {
+        serde_yaml::from_str(yaml).expect("Valid options")
+    }
+
+    #[tokio::test]
+    async fn test_scenario() {
+        let mut server = mockito::Server::new_async().await;
+        let login = server
+            .mock("POST", "/login")
+            .with_status(200)
+            .with_header("x-session", "s1")
+            .with_body(r#"{"data": {"token": "abc", "user": 42}}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let profile = server
+            .mock("GET", "/users/42")
+            .match_header("authorization", Exact("Bearer abc".to_string()))
+            .match_header("x-session", Exact("s1".to_string()))
+            .with_status(200)
+            .with_body("<a href=\"/orders?id=7\">")
+            .expect(1)
+            .create_async()
+            .await;
+        let orders = server
+            .mock("DELETE", "/orders/7")
+            .with_status(404)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let url = server.url();
+        let adapter = ScenarioBenchAdapter::from_options(options(&format!(
+            r#"
+step:
+  - name: login
+    url: {url}/login
+    method: POST
+    extract:
+      - {{name: token, json: /data/token}}
+      - {{name: user, json: /data/user}}
+      - {{name: session, header: x-session}}
+  - name: profile
+    url: "{url}/users/{{{{user}}}}"
+    header: ["authorization:Bearer {{{{token}}}}", "x-session:{{{{session}}}}"]
+    extract:
+      - {{name: order, regex: 'id=(\d+)'}}
+  - name: cancel
+    url: "{url}/orders/{{{{order}}}}"
+    method: DELETE
+"#
+        )))
+        .expect("Valid scenario");
+        assert_eq!(
+            r#"Scenario steps=["login", "profile", "cancel"], extracted=["token", "user", "session", "order"]"#,
+            adapter.to_string()
+        );
+
+        let client = adapter.build_client().await.expect("Client is built");
+        let mut operations = vec![];
+        for _ in 0..4 {
+            let stats = adapter.send_request(&client).await;
+            operations.push((stats.operation_name.unwrap(), stats.is_success));
+        }
+        assert_eq!(
+            vec![
+                ("login".to_string(), true),
+                ("profile".to_string(), true),
+                ("cancel".to_string(), false),
+                ("login".to_string(), true),
+            ],
+            operations
+        );
+        login.assert_async().await;
+        profile.assert_async().await;
+        orders.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_failed_extraction() {
+        let mut server = mockito::Server::new_async().await;
+        let login = server
+            .mock("POST", "/login")
+            .with_status(200)
+            .with_body("not json")
+            .expect(2)
+            .create_async()
+            .await;
+
+        let url = server.url();
+        let adapter = ScenarioBenchAdapter::from_options(options(&format!(
+            r#"
+step:
+  - {{name: login, url: "{url}/login", method: POST, extract: [{{name: token, json: /token}}]}}
+  - {{name: profile, url: "{url}/profile", header: ["authorization:Bearer {{{{token}}}}"]}}
+"#
+        )))
+        .expect("Valid scenario");
+
+        let client = adapter.build_client().await.expect("Client is built");
+        for _ in 0..2 {
+            let stats = adapter.send_request(&client).await;
+            assert_eq!(Some("login".to_string()), stats.operation_name);
+            assert!(!stats.is_success);
+            assert_eq!("`token` is not extracted", stats.status);
+        }
+        login.assert_async().await;
+    }
+
+    #[test]
+    fn test_invalid_scenario() {
+        let errors = ScenarioBenchAdapter::from_options(options(
+            r#"
+step:
+  - name: first
+    url: "http://localhost/{{token}}"
+    method: "GE T"
+    extract:
+      - {name: token, json: /token}
+      - {name: both, json: /a, header: x-a}
+      - {name: regex, regex: "("}
+      - {name: pointer, json: a}
+  - {name: second, url: "http://localhost/{{token}}", header: [no-value]}
+"#,
+        ))
+        .err()
+        .unwrap();
+        let flags: Vec<&str> = errors
+            .0
+            .iter()
+            .map(|e| match e {
+                ConfigError::InvalidValue { flag, .. } => *flag,
+                e => panic!("Unexpected error {e}"),
+            })
+            .collect();
+        assert_eq!(
+            vec!["url", "method", "extract", "extract", "extract", "header"],
+            flags
+        );
+
+        assert!(matches!(
+            ScenarioBenchAdapter::from_options(options("{}"))
+                .err()
+                .unwrap()
+                .0[0],
+            ConfigError::MissingValue { flag: "step", .. }
+        ));
+    }
+}
@@ -0,0 +1,159 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use serde::Deserialize;
+use std::sync::Mutex;
+
+/// How alternatives (e.g. target URLs or header values) are chosen for each request.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Selection {
+    /// Random choice, proportional to the weights.
+    #[default]
+    Random,
+    /// Random choice with a fixed seed, so runs are reproducible.
+    Seeded(u64),
+    /// Smooth weighted round-robin, e.g. `a a b a a` for weights 4 and 1.
+    RoundRobin,
+}
+
+/// Chooses the index of the next alternative by the weights.
+/// Shared by all clients, so the distribution holds for the whole run.
+pub struct Selector {
+    weights: Vec<u32>,
+    total: u64,
+    state: SelectorState,
+}
+
+enum SelectorState {
+    Random,
+    Seeded(Box<Mutex<StdRng>>),
+    /// Current weights of the smooth weighted round-robin.
+    RoundRobin(Mutex<Vec<i64>>),
+}
+
+impl Selector {
+    /// `stream` distinguishes selectors of the same run, so they use different seeds.
+    pub fn new(weights: Vec<u32>, selection: &Selection, stream: u64) -> Selector {
+        let state = match selection {
+            Selection::Random => SelectorState::Random,
+            Selection::Seeded(seed) => SelectorState::Seeded(Box::new(Mutex::new(
+                StdRng::seed_from_u64(seed.wrapping_add(stream)),
+            ))),
+            Selection::RoundRobin => SelectorState::RoundRobin(Mutex::new(vec![0; weights.len()])),
+        };
+        Selector {
+            total: weights.iter().map(|w| *w as u64).sum(),
+            weights,
+            state,
+        }
+    }
+
+    pub fn next(&self) -> usize {
+        if self.weights.len() <= 1 {
+            return 0;
+        }
+        match &self.state {
+            SelectorState::Random => self.pick(thread_rng().gen_range(0..self.total)),
+            SelectorState::Seeded(rng) => {
+                let point = rng
+                    .lock()
+                    .expect("Selector lock is poisoned")
+                    .gen_range(0..self.total);
+                self.pick(point)
+            }
+            SelectorState::RoundRobin(current) => {
+                let mut current = current.lock().expect("Selector lock is poisoned");
+                let mut chosen = 0;
+                for (i, weight) in self.weights.iter().enumerate() {
+                    current[i] += *weight as i64;
+                    if current[i] > current[chosen] {
+                        chosen = i;
+                    }
+                }
+                current[chosen] -= self.total as i64;
+                chosen
+            }
+        }
+    }
+
+    /// The index of the weight range containing `point`.
+    fn pick(&self, mut point: u64) -> usize {
+        for (i, weight) in self.weights.iter().enumerate() {
+            if point < *weight as u64 {
+                return i;
+            }
+            point -= *weight as u64;
+        }
+        self.weights.len() - 1
+    }
+}
+
+/// Splits the `#WEIGHT` suffix off `value`, e.g. `http://localhost/read#80`. The weight is `1` without it.
+pub fn split_weight(value: &str) -> Result<(&str, u32), String> {
+    match value.rsplit_once('#') {
+        Some((value, weight))
+            if !weight.is_empty() && weight.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            match weight.parse::<u32>() {
+                Ok(weight) if weight > 0 => Ok((value, weight)),
+                _ => Err(format!("weight must be between 1 and {}", u32::MAX)),
+            }
+        }
+        _ => Ok((value, 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::selection::{split_weight, Selection, Selector};
+
+    fn counts(selector: &Selector, n: usize, len: usize) -> Vec<usize> {
+        let mut counts = vec![0; len];
+        for _ in 0..n {
+            counts[selector.next()] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let selector = Selector::new(vec![4, 1], &Selection::RoundRobin, 0);
+        let sequence: Vec<usize> = (0..10).map(|_| selector.next()).collect();
+        assert_eq!(vec![0, 0, 1, 0, 0, 0, 0, 1, 0, 0], sequence);
+        assert_eq!(vec![800, 200], counts(&selector, 1000, 2));
+    }
+
+    #[test]
+    fn test_seeded() {
+        let sequence = |stream| {
+            let selector = Selector::new(vec![1, 1, 1], &Selection::Seeded(42), stream);
+            (0..20).map(|_| selector.next()).collect::<Vec<usize>>()
+        };
+        assert_eq!(sequence(0), sequence(0));
+        assert_ne!(sequence(0), sequence(1));
+    }
+
+    #[test]
+    fn test_random_weights() {
+        let selector = Selector::new(vec![80, 20, 0], &Selection::Random, 0);
+        let counts = counts(&selector, 10000, 3);
+        assert!(counts[0] > 7000, "{counts:?}");
+        assert!(counts[1] > 1000, "{counts:?}");
+        assert_eq!(0, counts[2]);
+    }
+
+    #[test]
+    fn test_split_weight() {
+        assert_eq!(Ok(("read", 80)), split_weight("read#80"));
+        assert_eq!(Ok(("a#b", 1)), split_weight("a#b"));
+        assert_eq!(Ok(("plain", 1)), split_weight("plain"));
+        assert!(split_weight("zero#0").is_err());
+    }
+}
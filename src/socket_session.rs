@@ -0,0 +1,272 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::config_error::ConfigError;
+use crate::error_category::ErrorCategory;
+use crate::metrics::{LatencyPhases, RequestStats, RequestStatsBuilder};
+use core::fmt;
+use std::error::Error;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, MutexGuard};
+
+/// A request failed while opening the connection or during the round-trip over it.
+#[derive(Debug)]
+pub(crate) struct SocketError {
+    connecting: bool,
+    error: Box<dyn Error + Send + Sync>,
+}
+
+/// A completed round-trip: the size of the response, its status and if it's a success.
+pub(crate) struct Outcome<'a> {
+    pub size: usize,
+    pub status: &'a str,
+    pub is_success: bool,
+}
+
+/// Connection of a single client. Opened by the first request, and re-opened by the next one after
+/// the connection is closed (e.g. after an error).
+pub(crate) struct Connection<C> {
+    slot: Mutex<Option<C>>,
+}
+
+pub(crate) struct ConnectionGuard<'a, C> {
+    slot: MutexGuard<'a, Option<C>>,
+}
+
+impl SocketError {
+    pub(crate) fn connect(error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        Self {
+            connecting: true,
+            error: error.into(),
+        }
+    }
+
+    pub(crate) fn request(error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        Self {
+            connecting: false,
+            error: error.into(),
+        }
+    }
+
+    fn category(&self) -> ErrorCategory {
+        if self.connecting {
+            ErrorCategory::classify_connect(self.error.as_ref())
+        } else {
+            ErrorCategory::classify(self.error.as_ref())
+        }
+    }
+}
+
+impl fmt::Display for SocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<'a> Outcome<'a> {
+    pub(crate) fn success(size: usize, status: &'a str) -> Self {
+        Self {
+            size,
+            status,
+            is_success: true,
+        }
+    }
+
+    pub(crate) fn failure(status: &'a str) -> Self {
+        Self {
+            size: 0,
+            status,
+            is_success: false,
+        }
+    }
+}
+
+impl<C> Connection<C> {
+    pub(crate) fn new(connected: Option<C>) -> Self {
+        Self {
+            slot: Mutex::new(connected),
+        }
+    }
+
+    /// Requests of a client are sequential, so a client holds the lock for the whole request.
+    pub(crate) async fn lock(&self) -> ConnectionGuard<'_, C> {
+        ConnectionGuard {
+            slot: self.slot.lock().await,
+        }
+    }
+}
+
+impl<C> ConnectionGuard<'_, C> {
+    /// The open connection, or a new one along with the time it took to open it.
+    pub(crate) async fn open<F>(
+        &mut self,
+        connect: F,
+    ) -> Result<(&mut C, Option<Duration>), SocketError>
+    where
+        F: Future<Output = Result<C, SocketError>>,
+    {
+        if self.slot.is_some() {
+            return Ok((self.slot.as_mut().expect("Checked above"), None));
+        }
+        let start = Instant::now();
+        let connected = self.slot.insert(connect.await?);
+        Ok((connected, Some(start.elapsed())))
+    }
+
+    /// The next request opens a new connection.
+    pub(crate) fn close(&mut self) {
+        *self.slot = None;
+    }
+}
+
+/// Checks that the targets are in the `host:port` form.
+pub(crate) fn validate_targets(targets: &[String], errors: &mut Vec<ConfigError>) {
+    for target in targets {
+        if !is_host_port(target) {
+            errors.push(ConfigError::InvalidValue {
+                flag: "target",
+                value: target.clone(),
+                reason: "expected `host:port`".to_string(),
+            });
+        }
+    }
+}
+
+pub(crate) fn is_host_port(target: &str) -> bool {
+    target
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+}
+
+/// Failures are reported by [`ErrorCategory`]. Opening a connection is a part of the request,
+/// reported as the `connect` phase, and the rest of it as `ttfb`.
+pub(crate) fn request_stats(
+    operation_name: Option<&str>,
+    result: Result<Outcome, SocketError>,
+    start: Instant,
+    connect: Option<Duration>,
+) -> RequestStats {
+    let (bytes_processed, status, is_success) = match result {
+        Ok(outcome) => (outcome.size, outcome.status.to_string(), outcome.is_success),
+        Err(e) => (0, e.category().status(&e.error), false),
+    };
+    let duration = start.elapsed();
+    RequestStatsBuilder::default()
+        .bytes_processed(bytes_processed)
+        .status(status)
+        .is_success(is_success)
+        .duration(duration)
+        .operation_name(operation_name.map(String::from))
+        .fatal_error(false)
+        .phases(Some(LatencyPhases {
+            connect,
+            ttfb: duration.saturating_sub(connect.unwrap_or_default()),
+            ..LatencyPhases::default()
+        }))
+        .build()
+        .expect("RequestStatsBuilder failed")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::config_error::ConfigError;
+    use crate::socket_session::{
+        is_host_port, request_stats, validate_targets, Connection, Outcome, SocketError,
+    };
+    use clap::{Args, Parser};
+    use std::ffi::OsString;
+    use std::future::Future;
+    use std::io;
+    use std::net::SocketAddr;
+    use std::time::Instant;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[derive(Parser)]
+    struct TestCli<T: Args> {
+        #[command(flatten)]
+        options: T,
+    }
+
+    /// Parses the options of a subcommand, e.g. `["tcp", "localhost:8080"]`.
+    pub(crate) fn parse_options<T: Args>(
+        args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+    ) -> T {
+        TestCli::<T>::try_parse_from(args).unwrap().options
+    }
+
+    /// Serves each accepted connection in a separate task.
+    pub(crate) async fn start_tcp_server<F, R>(serve: F) -> SocketAddr
+    where
+        F: Fn(TcpStream) -> R + Send + 'static,
+        R: Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream));
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_validate_targets() {
+        assert!(is_host_port("localhost:8080"));
+        assert!(is_host_port("[::1]:8080"));
+        assert!(!is_host_port(":8080"));
+        assert!(!is_host_port("localhost"));
+        assert!(!is_host_port("localhost:http"));
+
+        let mut errors = vec![];
+        validate_targets(
+            &["localhost:80".to_string(), "localhost".to_string()],
+            &mut errors,
+        );
+        assert_eq!(
+            vec![ConfigError::InvalidValue {
+                flag: "target",
+                value: "localhost".to_string(),
+                reason: "expected `host:port`".to_string(),
+            }],
+            errors
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection() {
+        let connection = Connection::new(None);
+        let mut guard = connection.lock().await;
+        let (connected, connect) = guard.open(async { Ok(1) }).await.unwrap();
+        assert_eq!(1, *connected);
+        assert!(connect.is_some());
+        // the open connection is reused
+        let (connected, connect) = guard.open(async { Ok(2) }).await.unwrap();
+        assert_eq!(1, *connected);
+        assert!(connect.is_none());
+
+        guard.close();
+        let error = guard
+            .open(async {
+                Err::<i32, _>(SocketError::connect(io::Error::from(
+                    io::ErrorKind::ConnectionRefused,
+                )))
+            })
+            .await
+            .unwrap_err();
+        let stats = request_stats(Some("test"), Err(error), Instant::now(), None);
+        assert!(!stats.is_success);
+        assert_eq!("connect_refused", stats.status);
+
+        let (connected, _) = guard.open(async { Ok(3) }).await.unwrap();
+        assert_eq!(3, *connected);
+        let stats = request_stats(None, Ok(Outcome::success(10, "OK")), Instant::now(), None);
+        assert!(stats.is_success);
+        assert_eq!(10, stats.bytes_processed);
+    }
+}
@@ -0,0 +1,420 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::bench_run::BenchmarkProtocolAdapter;
+use crate::config_error::{ConfigError, ConfigErrors};
+use crate::configuration::BenchmarkConfig;
+use crate::metrics::RequestStats;
+use crate::socket_session::{request_stats, validate_targets, Connection, Outcome, SocketError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use clap::Args;
+use core::fmt;
+use derive_builder::Builder;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use serde::Deserialize;
+use std::io;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[derive(Args, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+#[command(about = "Run in raw TCP mode (request-response)", long_about = None)]
+pub struct TcpOptions {
+    /// Target in `host:port` form, e.g. my-proxy.com:8080 Can be multiple ones (with random choice balancing).
+    #[arg(required = true)]
+    target: Vec<String>,
+    /// Payload to send. Could be either `random://[0-9]+`, `file://$filename` or `base64://${valid_base64}`.
+    #[arg(short = 'B', long)]
+    message: Option<String>,
+    /// Read the response until the delimiter, e.g. `\n` or `\r\n\r\n`.
+    /// By default the response is expected to be an echo of the payload.
+    #[arg(long)]
+    delimiter: Option<String>,
+    /// Read the response of exactly this many bytes.
+    #[arg(long = "response_size")]
+    response_size: Option<usize>,
+    /// If connections should be re-used.
+    #[arg(long = "conn_reuse")]
+    #[serde(default)]
+    conn_reuse: bool,
+}
+
+/// How to find the end of a response.
+#[derive(Clone, Debug)]
+pub enum ResponseEnd {
+    Delimiter(Vec<u8>),
+    Length(usize),
+    /// The same bytes as the payload.
+    Echo,
+}
+
+/// Writes the payload to a TCP connection and reads the response.
+/// A request opening a connection reports it as the `connect` phase.
+/// Without `conn_reuse` each round-trip opens a new connection.
+#[derive(Builder, Clone)]
+pub struct TcpBenchAdapter {
+    targets: Vec<String>,
+    #[builder(default)]
+    message: Bytes,
+    response_end: ResponseEnd,
+    #[builder(default)]
+    conn_reuse: bool,
+}
+
+const STATUS_OK: &str = "OK";
+const STATUS_MISMATCH: &str = "mismatch";
+
+/// Connection of a single client. Re-opened after an error, or after each round-trip without `conn_reuse`.
+pub struct TcpClient {
+    stream: Connection<TcpStream>,
+}
+
+impl TcpBenchAdapter {
+    pub fn from_options(options: TcpOptions) -> Result<TcpBenchAdapter, ConfigErrors> {
+        let mut errors = vec![];
+
+        validate_targets(&options.target, &mut errors);
+
+        let message = BenchmarkConfig::generate_body("message", options.message.as_deref())
+            .unwrap_or_else(|e| {
+                errors.push(e);
+                Bytes::new()
+            });
+
+        let response_end = match (options.delimiter, options.response_size) {
+            (Some(_), Some(_)) => {
+                errors.push(ConfigError::Conflict {
+                    flag: "delimiter",
+                    other: "response_size",
+                });
+                None
+            }
+            (Some(delimiter), None) => {
+                let delimiter = TcpBenchAdapter::unescape(&delimiter);
+                if delimiter.is_empty() {
+                    errors.push(ConfigError::InvalidValue {
+                        flag: "delimiter",
+                        value: String::new(),
+                        reason: "must not be empty".to_string(),
+                    });
+                }
+                Some(ResponseEnd::Delimiter(delimiter))
+            }
+            (None, Some(size)) => Some(ResponseEnd::Length(size)),
+            (None, None) if message.is_empty() => {
+                errors.push(ConfigError::MissingValue {
+                    flag: "message",
+                    reason: "an echo response requires a non-empty payload, or use --delimiter or --response_size".to_string(),
+                });
+                None
+            }
+            (None, None) => Some(ResponseEnd::Echo),
+        };
+
+        match response_end {
+            Some(response_end) if errors.is_empty() => Ok(TcpBenchAdapterBuilder::default()
+                .targets(options.target)
+                .message(message)
+                .response_end(response_end)
+                .conn_reuse(options.conn_reuse)
+                .build()
+                .expect("TcpBenchAdapterBuilder failed")),
+            _ => Err(ConfigErrors(errors)),
+        }
+    }
+
+    /// Supports `\n`, `\r`, `\t`, `\0` and `\\` escapes, as delimiters are hard to pass otherwise.
+    fn unescape(value: &str) -> Vec<u8> {
+        let mut result = vec![];
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            let c = match c {
+                '\\' => match chars.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some(other) if other != '\\' => {
+                        result.push(b'\\');
+                        other
+                    }
+                    _ => '\\',
+                },
+                c => c,
+            };
+            let mut buf = [0; 4];
+            result.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+        result
+    }
+
+    async fn connect(&self) -> Result<TcpStream, SocketError> {
+        let target = self
+            .targets
+            .choose(&mut thread_rng())
+            .expect("At least one target is required");
+        let stream = TcpStream::connect(target)
+            .await
+            .map_err(SocketError::connect)?;
+        stream.set_nodelay(true).map_err(SocketError::connect)?;
+        Ok(stream)
+    }
+
+    /// Writes the payload and reads the response. An echo differing from the payload is a `mismatch`.
+    async fn round_trip(&self, stream: &mut TcpStream) -> Result<Outcome<'static>, SocketError> {
+        stream
+            .write_all(&self.message)
+            .await
+            .map_err(SocketError::request)?;
+
+        let mut response = Vec::new();
+        let mut buf = [0; 8192];
+        // where the delimiter can start in the bytes not searched yet
+        let mut searched = 0;
+        loop {
+            match &self.response_end {
+                ResponseEnd::Length(size) if response.len() >= *size => {
+                    return Ok(Outcome::success(response.len(), STATUS_OK))
+                }
+                ResponseEnd::Echo if response.len() >= self.message.len() => {
+                    return Ok(if response == self.message {
+                        Outcome::success(response.len(), STATUS_OK)
+                    } else {
+                        Outcome::failure(STATUS_MISMATCH)
+                    });
+                }
+                ResponseEnd::Delimiter(delimiter) => {
+                    if response[searched..]
+                        .windows(delimiter.len())
+                        .any(|w| w == delimiter)
+                    {
+                        return Ok(Outcome::success(response.len(), STATUS_OK));
+                    }
+                    searched = response.len().saturating_sub(delimiter.len() - 1);
+                }
+                _ => {}
+            }
+            let n = stream.read(&mut buf).await.map_err(SocketError::request)?;
+            if n == 0 {
+                return Err(SocketError::request(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed",
+                )));
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+
+#[async_trait]
+impl BenchmarkProtocolAdapter for TcpBenchAdapter {
+    type Client = TcpClient;
+
+    async fn build_client(&self) -> Result<Self::Client, String> {
+        Ok(TcpClient {
+            stream: Connection::new(None),
+        })
+    }
+
+    /// Requests share the connection of a client, one at a time.
+    fn supports_open_model(&self) -> bool {
+        false
+    }
+
+    async fn send_request(&self, client: &Self::Client) -> RequestStats {
+        let mut stream = client.stream.lock().await;
+        let start = Instant::now();
+
+        let (result, connect) = match stream.open(self.connect()).await {
+            Ok((connected, connect)) => (self.round_trip(connected).await, connect),
+            Err(e) => (Err(e), None),
+        };
+        // the rest of a mismatched echo would be read by the next request
+        if !result.as_ref().is_ok_and(|outcome| outcome.is_success) || !self.conn_reuse {
+            stream.close();
+        }
+        request_stats(None, result, start, connect)
+    }
+}
+
+impl fmt::Display for TcpBenchAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TCP {:?}, payload size: {} bytes, response: {:?}, conn_reuse: {}",
+            self.targets,
+            self.message.len(),
+            self.response_end,
+            self.conn_reuse
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_run::BenchmarkProtocolAdapter;
+    use crate::config_error::ConfigError;
+    use crate::socket_session::tests::{parse_options, start_tcp_server};
+    use crate::tcp_bench_session::{TcpBenchAdapter, TcpOptions};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Echoes everything back.
+    async fn start_server() -> SocketAddr {
+        start_tcp_server(|mut stream| async move {
+            let (mut read, mut write) = stream.split();
+            tokio::io::copy(&mut read, &mut write)
+                .await
+                .unwrap_or_default();
+            write.shutdown().await.unwrap_or_default();
+        })
+        .await
+    }
+
+    fn build_adapter(args: &[&str]) -> TcpBenchAdapter {
+        let options: TcpOptions = parse_options(["tcp"].iter().chain(args));
+        let adapter = TcpBenchAdapter::from_options(options).unwrap();
+        assert!(adapter.to_string().starts_with("TCP"));
+        adapter
+    }
+
+    #[tokio::test]
+    async fn test_tcp_echo() {
+        let addr = start_server().await.to_string();
+        let adapter = build_adapter(&[&addr, "-B", "random://100", "--conn_reuse"]);
+
+        let client = adapter.build_client().await.unwrap();
+        for i in 0..3 {
+            let stats = adapter.send_request(&client).await;
+            assert!(stats.is_success);
+            assert_eq!(100, stats.bytes_processed);
+            // only the first request opens the connection
+            assert_eq!(i == 0, stats.phases.unwrap().connect.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_delimiter_without_reuse() {
+        let addr = start_server().await.to_string();
+        // "hello\n"
+        let adapter = build_adapter(&[&addr, "-B", "base64://aGVsbG8K", "--delimiter", "\\n"]);
+
+        let client = adapter.build_client().await.unwrap();
+        for _ in 0..2 {
+            let stats = adapter.send_request(&client).await;
+            assert!(stats.is_success);
+            assert_eq!(6, stats.bytes_processed);
+            assert!(stats.phases.unwrap().connect.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_connection_closed() {
+        let addr = start_tcp_server(|mut stream| async move {
+            let mut buf = [0; 3];
+            stream.read_exact(&mut buf).await.unwrap_or_default();
+            // a shorter response than expected
+            stream.write_all(&buf[..1]).await.unwrap_or_default();
+        })
+        .await
+        .to_string();
+        let adapter = build_adapter(&[&addr, "-B", "random://3", "--conn_reuse"]);
+
+        let client = adapter.build_client().await.unwrap();
+        let stats = adapter.send_request(&client).await;
+        assert!(!stats.is_success);
+        assert_eq!("reset_by_peer", stats.status);
+        // re-connects after an error
+        let stats = adapter.send_request(&client).await;
+        assert!(stats.phases.unwrap().connect.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_echo_mismatch() {
+        let addr = start_tcp_server(|mut stream| async move {
+            let mut buf = [0; 3];
+            while stream.read_exact(&mut buf).await.is_ok() {
+                buf[0] ^= 0xff;
+                stream.write_all(&buf).await.unwrap_or_default();
+            }
+        })
+        .await
+        .to_string();
+        let adapter = build_adapter(&[&addr, "-B", "random://3", "--conn_reuse"]);
+
+        let client = adapter.build_client().await.unwrap();
+        let stats = adapter.send_request(&client).await;
+        assert!(!stats.is_success);
+        assert_eq!("mismatch", stats.status);
+        // re-connects after a mismatch
+        let stats = adapter.send_request(&client).await;
+        assert!(stats.phases.unwrap().connect.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_delimiter_split() {
+        let addr = start_tcp_server(|mut stream| async move {
+            let mut buf = [0; 1];
+            stream.read_exact(&mut buf).await.unwrap_or_default();
+            // the delimiter spans two reads
+            stream.write_all(b"hello\r").await.unwrap_or_default();
+            stream.flush().await.unwrap_or_default();
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            stream.write_all(b"\nrest").await.unwrap_or_default();
+        })
+        .await
+        .to_string();
+        let adapter = build_adapter(&[&addr, "-B", "random://1", "--delimiter", "\\r\\n"]);
+
+        let client = adapter.build_client().await.unwrap();
+        let stats = adapter.send_request(&client).await;
+        assert!(stats.is_success);
+        assert_eq!(11, stats.bytes_processed);
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let options: TcpOptions = parse_options([
+            "tcp",
+            "localhost",
+            "--delimiter",
+            "\\n",
+            "--response_size",
+            "10",
+        ]);
+        let errors = TcpBenchAdapter::from_options(options).err().unwrap();
+        assert_eq!(
+            vec![
+                ConfigError::InvalidValue {
+                    flag: "target",
+                    value: "localhost".to_string(),
+                    reason: "expected `host:port`".to_string(),
+                },
+                ConfigError::Conflict {
+                    flag: "delimiter",
+                    other: "response_size",
+                },
+            ],
+            errors.0
+        );
+
+        let options: TcpOptions = parse_options(["tcp", "localhost:80"]);
+        assert!(TcpBenchAdapter::from_options(options).is_err());
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(
+            b"\r\n\r\n".to_vec(),
+            TcpBenchAdapter::unescape("\\r\\n\\r\\n")
+        );
+        assert_eq!(b"a\\b\\".to_vec(), TcpBenchAdapter::unescape("a\\b\\\\"));
+    }
+}
@@ -0,0 +1,226 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::feeder::Row;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+
+/// A value with `{{placeholder}}`s rendered anew for each request, e.g. `/items/{{random_int 1 1000}}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    /// `{{uuid}}` - a random UUID (v4).
+    Uuid,
+    /// `{{random_int MIN MAX}}` - a random integer, both bounds included.
+    RandomInt(i64, i64),
+    /// `{{timestamp}}` - Unix time in milliseconds.
+    Timestamp,
+    /// `{{counter}}` - the sequence number of the request in the run.
+    Counter,
+    /// `{{random_string LEN}}` - random alphanumeric characters.
+    RandomString(usize),
+    /// `{{column}}` - the value of the column of the feeder row.
+    Variable(String),
+}
+
+impl Template {
+    pub fn parse(value: &str) -> Result<Template, String> {
+        let mut segments = vec![];
+        let mut rest = value;
+        while let Some(start) = rest.find(OPEN) {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let after_open = &rest[start + OPEN.len()..];
+            let end = after_open
+                .find(CLOSE)
+                .ok_or_else(|| format!("`{OPEN}` is not closed with `{CLOSE}`"))?;
+            segments.push(Segment::parse(&after_open[..end])?);
+            rest = &after_open[end + CLOSE.len()..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        Ok(Template { segments })
+    }
+
+    /// Whether the value is the same for each request.
+    pub fn is_static(&self) -> bool {
+        self.segments
+            .iter()
+            .all(|segment| matches!(segment, Segment::Literal(_)))
+    }
+
+    /// Names of the `{{column}}` placeholders.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Variable(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Renders the value for the request number `counter`, with the values of the feeder `row`.
+    pub fn render(&self, counter: u64, row: Option<Row>) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Uuid => write_uuid(&mut rendered),
+                Segment::RandomInt(min, max) => {
+                    let _ = write!(rendered, "{}", thread_rng().gen_range(*min..=*max));
+                }
+                Segment::Timestamp => {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default();
+                    let _ = write!(rendered, "{}", now.as_millis());
+                }
+                Segment::Counter => {
+                    let _ = write!(rendered, "{counter}");
+                }
+                Segment::RandomString(len) => rendered.extend(
+                    thread_rng()
+                        .sample_iter(&Alphanumeric)
+                        .take(*len)
+                        .map(char::from),
+                ),
+                Segment::Variable(name) => {
+                    rendered.push_str(row.and_then(|row| row.get(name)).unwrap_or_default())
+                }
+            }
+        }
+        rendered
+    }
+}
+
+impl Segment {
+    fn parse(placeholder: &str) -> Result<Segment, String> {
+        let invalid = |reason: &str| format!("invalid `{OPEN}{placeholder}{CLOSE}`: {reason}");
+        let parse_arg = |arg: &str| {
+            arg.parse::<i64>()
+                .map_err(|_| invalid(&format!("`{arg}` is not an integer")))
+        };
+        let words: Vec<&str> = placeholder.split_whitespace().collect();
+        match words.as_slice() {
+            ["uuid"] => Ok(Segment::Uuid),
+            ["timestamp"] => Ok(Segment::Timestamp),
+            ["counter"] => Ok(Segment::Counter),
+            ["random_int", min, max] => {
+                let (min, max) = (parse_arg(min)?, parse_arg(max)?);
+                if min > max {
+                    return Err(invalid("MIN must not be greater than MAX"));
+                }
+                Ok(Segment::RandomInt(min, max))
+            }
+            ["random_string", len] => {
+                let len = parse_arg(len)?;
+                usize::try_from(len)
+                    .map(Segment::RandomString)
+                    .map_err(|_| invalid("LEN must not be negative"))
+            }
+            [name]
+                if name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.')) =>
+            {
+                Ok(Segment::Variable(name.to_string()))
+            }
+            _ => Err(invalid(
+                "expected one of `uuid`, `random_int MIN MAX`, `timestamp`, `counter`, `random_string LEN` or a feeder column",
+            )),
+        }
+    }
+}
+
+fn write_uuid(rendered: &mut String) {
+    let mut bytes: [u8; 16] = thread_rng().gen();
+    // version 4, variant RFC 4122
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    for (i, byte) in bytes.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            rendered.push('-');
+        }
+        let _ = write!(rendered, "{byte:02x}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::feeder::{Feeder, FeederOrder};
+    use crate::template::Template;
+
+    #[test]
+    fn test_render() {
+        let template =
+            Template::parse("/items/{{counter}}?id={{ random_int 5 5 }}&s={{random_string 8}}")
+                .unwrap();
+        assert!(!template.is_static());
+        let rendered = template.render(42, None);
+        assert!(rendered.starts_with("/items/42?id=5&s="), "{rendered}");
+        assert_eq!("/items/42?id=5&s=".len() + 8, rendered.len());
+        assert_ne!(template.render(42, None), template.render(42, None));
+
+        let uuid = Template::parse("{{uuid}}").unwrap().render(0, None);
+        assert_eq!(36, uuid.len());
+        assert_eq!(Some('4'), uuid.chars().nth(14));
+
+        let timestamp = Template::parse("{{timestamp}}").unwrap().render(0, None);
+        assert!(timestamp.parse::<u128>().unwrap() > 1_600_000_000_000);
+
+        let plain = Template::parse("{\"a\": {}}").unwrap();
+        assert!(plain.is_static());
+        assert_eq!("{\"a\": {}}", plain.render(0, None));
+    }
+
+    #[test]
+    fn test_variables() {
+        let feeder = Feeder::new(
+            vec!["user".to_string(), "query".to_string()],
+            vec![vec!["42".to_string(), "shoes".to_string()]],
+            FeederOrder::Sequential,
+            false,
+            1,
+        )
+        .unwrap();
+        let template =
+            Template::parse("/users/{{user}}/search?q={{ query }}&x={{missing}}").unwrap();
+        assert_eq!(
+            vec!["user", "query", "missing"],
+            template.variables().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "/users/42/search?q=shoes&x=",
+            template.render(1, feeder.next_row(0))
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        for value in [
+            "{{counter",
+            "{{unknown argument}}",
+            "{{a/b}}",
+            "{{random_int 1}}",
+            "{{random_int 10 1}}",
+            "{{random_int a 1}}",
+            "{{random_string -1}}",
+        ] {
+            assert!(Template::parse(value).is_err(), "{value}");
+        }
+    }
+}
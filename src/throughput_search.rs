@@ -0,0 +1,261 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::metrics::{BenchRunMetrics, HistogramStatsExt};
+use derive_builder::Builder;
+use log::info;
+use std::time::{Duration, Instant};
+
+/// Service level objective each probed rate must meet.
+#[derive(Builder, Clone, Debug)]
+pub struct SloCriteria {
+    #[builder(default)]
+    max_p99: Option<Duration>,
+    /// In percents, e.g. `1.0` for 1%.
+    #[builder(default)]
+    max_error_rate: Option<f64>,
+    /// How far (in percents) the achieved rate may fall below the probed one.
+    /// In the closed model a saturated target slows clients down, so the probed rate may be never reached.
+    #[builder(default = "10.0")]
+    max_rate_shortfall: f64,
+}
+
+/// Finds the highest rate meeting the SLO: the rate is doubled until the SLO is violated
+/// (exponential probe), then the interval between the last good and the first bad rate is bisected
+/// until it's narrower than `precision` (in percents of the upper bound).
+#[derive(Builder, Clone, Debug)]
+pub struct ThroughputSearch {
+    slo: SloCriteria,
+    start_rate: f64,
+    #[builder(default)]
+    max_rate: Option<f64>,
+    #[builder(default = "5.0")]
+    precision: f64,
+    /// The highest rate that met the SLO so far.
+    #[builder(setter(skip))]
+    highest_good: Option<f64>,
+    /// The lowest rate that violated the SLO so far.
+    #[builder(setter(skip))]
+    lowest_bad: Option<f64>,
+}
+
+impl SloCriteria {
+    /// Returns the reason if the SLO is violated at the probed `rate`.
+    pub fn check(&self, rate: f64, metrics: &BenchRunMetrics) -> Result<(), String> {
+        let combined = &metrics.combined;
+        if combined.total_requests == 0 {
+            return Err("No requests were sent".to_string());
+        }
+
+        let duration = Instant::now().duration_since(combined.bench_begin);
+        let achieved = combined.total_requests as f64 / duration.as_secs_f64();
+        if achieved * 100. < rate * (100. - self.max_rate_shortfall) {
+            return Err(format!(
+                "Achieved rate {achieved:.3} is below the probed {rate:.3}"
+            ));
+        }
+
+        if let Some(max_error_rate) = self.max_error_rate {
+            let failed = combined.total_requests - combined.successful_requests;
+            let error_rate = failed as f64 * 100. / combined.total_requests as f64;
+            if error_rate > max_error_rate {
+                return Err(format!(
+                    "Error rate {error_rate:.3}% is above {max_error_rate:.3}%"
+                ));
+            }
+        }
+
+        if let Some(max_p99) = self.max_p99 {
+            let mut latency = combined.success_latency.clone();
+            latency.merge(&combined.error_latency);
+            let p99 = Duration::from_micros(latency.get_percentile(99.0).unwrap_or_default());
+            if p99 > max_p99 {
+                return Err(format!("p99 latency {p99:?} is above {max_p99:?}"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ThroughputSearch {
+    /// The next rate to probe, or `None` if the search is complete.
+    pub fn next_rate(&self) -> Option<f64> {
+        match (self.highest_good, self.lowest_bad) {
+            (None, None) => Some(self.start_rate),
+            (Some(good), None) => match self.max_rate {
+                Some(max_rate) if good >= max_rate => None,
+                Some(max_rate) => Some((good * 2.).min(max_rate)),
+                None => Some(good * 2.),
+            },
+            (None, Some(bad)) => {
+                // nothing met the SLO yet, keep halving down to `precision` of the start rate
+                if bad * 100. <= self.precision * self.start_rate {
+                    None
+                } else {
+                    Some(bad / 2.)
+                }
+            }
+            (Some(good), Some(bad)) => {
+                if (bad - good) * 100. <= self.precision * bad {
+                    None
+                } else {
+                    Some((good + bad) / 2.)
+                }
+            }
+        }
+    }
+
+    /// Records the outcome of a probe.
+    pub fn record(&mut self, rate: f64, metrics: &BenchRunMetrics) {
+        match self.slo.check(rate, metrics) {
+            Ok(()) => {
+                info!("Rate {rate:.3} meets the SLO");
+                self.highest_good = Some(self.highest_good.map_or(rate, |r| r.max(rate)));
+            }
+            Err(reason) => {
+                info!("Rate {rate:.3} violates the SLO: {reason}");
+                self.lowest_bad = Some(self.lowest_bad.map_or(rate, |r| r.min(rate)));
+            }
+        }
+    }
+
+    /// The highest rate that met the SLO, if any.
+    pub fn result(&self) -> Option<f64> {
+        self.highest_good
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::{BenchRunMetrics, RequestStatsBuilder};
+    use crate::throughput_search::{SloCriteriaBuilder, ThroughputSearchBuilder};
+    use std::time::{Duration, Instant};
+
+    fn build_metrics(latency: Duration, errors: usize) -> BenchRunMetrics {
+        let mut metrics = BenchRunMetrics::new();
+        for i in 0..100 {
+            metrics.report_request(
+                RequestStatsBuilder::default()
+                    .bytes_processed(0)
+                    .status("200 OK".to_string())
+                    .is_success(i >= errors)
+                    .duration(latency)
+                    .fatal_error(false)
+                    .build()
+                    .expect("RequestStatsBuilder failed"),
+            );
+        }
+        metrics
+    }
+
+    #[test]
+    fn test_slo_check() {
+        let slo = SloCriteriaBuilder::default()
+            .max_p99(Some(Duration::from_millis(10)))
+            .max_error_rate(Some(1.))
+            .build()
+            .unwrap();
+
+        assert!(slo.check(100., &BenchRunMetrics::new()).is_err());
+        assert!(slo
+            .check(100., &build_metrics(Duration::from_millis(5), 1))
+            .is_ok());
+        assert!(slo
+            .check(100., &build_metrics(Duration::from_millis(5), 2))
+            .is_err());
+        assert!(slo
+            .check(100., &build_metrics(Duration::from_millis(20), 0))
+            .is_err());
+
+        // 100 requests in 10 seconds
+        let mut metrics = build_metrics(Duration::from_millis(5), 0);
+        metrics.combined.bench_begin = Instant::now() - Duration::from_secs(10);
+        assert!(slo.check(10., &metrics).is_ok());
+        assert!(slo
+            .check(100., &metrics)
+            .unwrap_err()
+            .starts_with("Achieved rate"));
+    }
+
+    #[test]
+    fn test_search() {
+        let mut search = ThroughputSearchBuilder::default()
+            .slo(
+                SloCriteriaBuilder::default()
+                    .max_p99(Some(Duration::from_millis(10)))
+                    .build()
+                    .unwrap(),
+            )
+            .start_rate(100.)
+            .precision(1.)
+            .build()
+            .unwrap();
+
+        // the service degrades above 1234 rps
+        let knee = 1234.;
+        let mut probes = vec![];
+        while let Some(rate) = search.next_rate() {
+            probes.push(rate);
+            let latency = if rate <= knee {
+                Duration::from_millis(1)
+            } else {
+                Duration::from_millis(100)
+            };
+            search.record(rate, &build_metrics(latency, 0));
+        }
+
+        assert_eq!(&probes[..5], &[100., 200., 400., 800., 1600.]);
+        let result = search.result().expect("Must find a rate");
+        assert!(result <= knee);
+        assert!(knee - result <= 0.01 * 1600., "Result: {result}");
+    }
+
+    #[test]
+    fn test_search_max_rate() {
+        let mut search = ThroughputSearchBuilder::default()
+            .slo(
+                SloCriteriaBuilder::default()
+                    .max_error_rate(Some(0.))
+                    .build()
+                    .unwrap(),
+            )
+            .start_rate(100.)
+            .max_rate(Some(300.))
+            .build()
+            .unwrap();
+
+        let mut probes = vec![];
+        while let Some(rate) = search.next_rate() {
+            probes.push(rate);
+            search.record(rate, &build_metrics(Duration::from_millis(1), 0));
+        }
+
+        assert_eq!(vec![100., 200., 300.], probes);
+        assert_eq!(Some(300.), search.result());
+    }
+
+    #[test]
+    fn test_search_nothing_meets_slo() {
+        let mut search = ThroughputSearchBuilder::default()
+            .slo(
+                SloCriteriaBuilder::default()
+                    .max_error_rate(Some(0.))
+                    .build()
+                    .unwrap(),
+            )
+            .start_rate(100.)
+            .build()
+            .unwrap();
+
+        while let Some(rate) = search.next_rate() {
+            search.record(rate, &build_metrics(Duration::from_millis(1), 10));
+        }
+
+        assert_eq!(None, search.result());
+    }
+}
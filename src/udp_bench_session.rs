@@ -0,0 +1,331 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::bench_run::BenchmarkProtocolAdapter;
+use crate::config_error::ConfigErrors;
+use crate::configuration::{parse_duration, BenchmarkConfig};
+use crate::metrics::RequestStats;
+use crate::socket_session::{request_stats, validate_targets, Outcome, SocketError};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use clap::Args;
+use core::fmt;
+use derive_builder::Builder;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+#[derive(Args, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+#[command(about = "Run in UDP mode (request-response)", long_about = None)]
+pub struct UdpOptions {
+    /// Target in `host:port` form, e.g. my-collector.com:8125 Can be multiple ones (with random choice balancing).
+    #[arg(required = true)]
+    target: Vec<String>,
+    /// Payload to send after the sequence number. Could be either `random://[0-9]+`, `file://$filename` or `base64://${valid_base64}`.
+    #[arg(short = 'B', long)]
+    message: Option<String>,
+    /// How long to wait for a reply before the datagram is considered lost. E.g. 200ms
+    #[arg(long, default_value = "1s")]
+    #[serde(default = "UdpOptions::default_timeout")]
+    timeout: String,
+}
+
+impl UdpOptions {
+    fn default_timeout() -> String {
+        "1s".to_string()
+    }
+}
+
+/// Sends datagrams prefixed with a sequence number (u64 big-endian) and waits for the reply
+/// starting with the same sequence number, e.g. from an echo server.
+/// A reply received after a reply to a later datagram is `reordered`, and no reply within the timeout makes it `lost`.
+#[derive(Builder, Clone)]
+pub struct UdpBenchAdapter {
+    targets: Vec<String>,
+    #[builder(default)]
+    message: Bytes,
+    timeout: Duration,
+}
+
+/// A socket connected to one of the targets. Replies are received by a separate task,
+/// so datagrams of a client can be in flight at the same time (e.g. with `--open_model`).
+pub struct UdpClient {
+    socket: Arc<UdpSocket>,
+    sequence: AtomicU64,
+    replies: Arc<Mutex<Replies>>,
+    receiver: JoinHandle<()>,
+}
+
+/// Datagrams waiting for a reply, by sequence number.
+#[derive(Default)]
+struct Replies {
+    outstanding: HashMap<u64, oneshot::Sender<io::Result<Reply>>>,
+    /// The highest sequence number replied to.
+    last_received: u64,
+}
+
+struct Reply {
+    size: usize,
+    reordered: bool,
+}
+
+const SEQUENCE_SIZE: usize = 8;
+const MAX_DATAGRAM_SIZE: usize = 65536;
+const STATUS_OK: &str = "OK";
+const STATUS_REORDERED: &str = "reordered";
+const STATUS_LOST: &str = "lost";
+
+impl UdpBenchAdapter {
+    pub fn from_options(options: UdpOptions) -> Result<UdpBenchAdapter, ConfigErrors> {
+        let mut errors = vec![];
+
+        validate_targets(&options.target, &mut errors);
+
+        let message = BenchmarkConfig::generate_body("message", options.message.as_deref())
+            .unwrap_or_else(|e| {
+                errors.push(e);
+                Bytes::new()
+            });
+        let timeout = parse_duration("timeout", &Some(options.timeout), &mut errors);
+
+        match timeout {
+            Some(timeout) if errors.is_empty() => Ok(UdpBenchAdapterBuilder::default()
+                .targets(options.target)
+                .message(message)
+                .timeout(timeout)
+                .build()
+                .expect("UdpBenchAdapterBuilder failed")),
+            _ => Err(ConfigErrors(errors)),
+        }
+    }
+
+    fn datagram(&self, sequence: u64) -> Bytes {
+        let mut datagram = BytesMut::with_capacity(SEQUENCE_SIZE + self.message.len());
+        datagram.put_u64(sequence);
+        datagram.put_slice(&self.message);
+        datagram.freeze()
+    }
+}
+
+impl UdpClient {
+    /// Passes replies to the outstanding datagrams. Late replies to datagrams reported as `lost` are dropped.
+    /// A receive error (e.g. an ICMP port unreachable) fails all outstanding datagrams.
+    async fn receive(socket: Arc<UdpSocket>, replies: Arc<Mutex<Replies>>) {
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            let received = socket.recv(&mut buf).await;
+            let mut replies = replies.lock().expect("Poisoned replies");
+            match received {
+                Ok(size) => {
+                    let Some(sequence) = buf[..size]
+                        .first_chunk::<SEQUENCE_SIZE>()
+                        .map(|s| u64::from_be_bytes(*s))
+                    else {
+                        continue;
+                    };
+                    if let Some(waiting) = replies.outstanding.remove(&sequence) {
+                        let reordered = sequence < replies.last_received;
+                        replies.last_received = replies.last_received.max(sequence);
+                        waiting.send(Ok(Reply { size, reordered })).ok();
+                    }
+                }
+                Err(e) => {
+                    for (_, waiting) in replies.outstanding.drain() {
+                        waiting
+                            .send(Err(io::Error::new(e.kind(), e.to_string())))
+                            .ok();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the datagram and waits for the reply to it.
+    async fn round_trip(&self, datagram: Bytes, sequence: u64) -> io::Result<Reply> {
+        let (sender, reply) = oneshot::channel();
+        self.replies
+            .lock()
+            .expect("Poisoned replies")
+            .outstanding
+            .insert(sequence, sender);
+        self.socket.send(&datagram).await?;
+        reply
+            .await
+            .map_err(|_| io::Error::other("The receiver is stopped"))?
+    }
+
+    fn forget(&self, sequence: u64) {
+        self.replies
+            .lock()
+            .expect("Poisoned replies")
+            .outstanding
+            .remove(&sequence);
+    }
+}
+
+impl Drop for UdpClient {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+#[async_trait]
+impl BenchmarkProtocolAdapter for UdpBenchAdapter {
+    type Client = UdpClient;
+
+    async fn build_client(&self) -> Result<Self::Client, String> {
+        let target = self
+            .targets
+            .choose(&mut thread_rng())
+            .expect("At least one target is required");
+        let addr = tokio::net::lookup_host(target)
+            .await
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("Cannot resolve {target}"))?;
+        let local_addr = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local_addr)
+            .await
+            .map_err(|e| e.to_string())?;
+        socket.connect(addr).await.map_err(|e| e.to_string())?;
+        let socket = Arc::new(socket);
+        let replies = Arc::new(Mutex::new(Replies::default()));
+        Ok(UdpClient {
+            receiver: tokio::spawn(UdpClient::receive(socket.clone(), replies.clone())),
+            socket,
+            sequence: AtomicU64::new(0),
+            replies,
+        })
+    }
+
+    async fn send_request(&self, client: &Self::Client) -> RequestStats {
+        let sequence = client.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        let datagram = self.datagram(sequence);
+        let start = Instant::now();
+
+        let result =
+            match tokio::time::timeout(self.timeout, client.round_trip(datagram, sequence)).await {
+                Ok(Ok(Reply { size, reordered })) => Ok(Outcome::success(
+                    size,
+                    if reordered {
+                        STATUS_REORDERED
+                    } else {
+                        STATUS_OK
+                    },
+                )),
+                Ok(Err(e)) => Err(SocketError::request(e)),
+                Err(_) => Ok(Outcome::failure(STATUS_LOST)),
+            };
+        client.forget(sequence);
+        request_stats(None, result, start, None)
+    }
+}
+
+impl fmt::Display for UdpBenchAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "UDP {:?}, payload size: {} bytes, timeout: {:?}",
+            self.targets,
+            self.message.len(),
+            self.timeout
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_run::BenchmarkProtocolAdapter;
+    use crate::socket_session::tests::parse_options;
+    use crate::udp_bench_session::{UdpBenchAdapter, UdpOptions};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+
+    /// Echoes datagrams back, except for the 2nd one, which is delayed until after the 3rd,
+    /// and the 4th one, which is dropped.
+    async fn start_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 1024];
+            let mut delayed = None;
+            while let Ok((size, peer)) = socket.recv_from(&mut buf).await {
+                let datagram = buf[..size].to_vec();
+                match datagram[7] {
+                    2 => delayed = Some(datagram),
+                    4 => {}
+                    _ => {
+                        socket.send_to(&datagram, peer).await.unwrap();
+                        if let Some(delayed) = delayed.take() {
+                            socket.send_to(&delayed, peer).await.unwrap();
+                        }
+                    }
+                }
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_udp_statuses() {
+        let addr = start_server().await;
+        let options: UdpOptions = parse_options([
+            "udp",
+            &addr.to_string(),
+            "-B",
+            "random://10",
+            "--timeout",
+            "200ms",
+        ]);
+        let adapter = UdpBenchAdapter::from_options(options).unwrap();
+        assert!(adapter.to_string().starts_with("UDP"));
+        let client = adapter.build_client().await.unwrap();
+
+        let stats = adapter.send_request(&client).await;
+        assert!(stats.is_success);
+        assert_eq!("OK", stats.status);
+        assert_eq!(18, stats.bytes_processed);
+
+        // the 2nd and the 3rd datagrams are in flight at the same time
+        let (second, third) = tokio::join!(adapter.send_request(&client), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            adapter.send_request(&client).await
+        });
+        assert_eq!("OK", third.status);
+        assert!(second.is_success);
+        assert_eq!("reordered", second.status);
+        assert_eq!(18, second.bytes_processed);
+
+        let stats = adapter.send_request(&client).await;
+        assert!(!stats.is_success);
+        assert_eq!("lost", stats.status);
+
+        let stats = adapter.send_request(&client).await;
+        assert_eq!("OK", stats.status);
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let options: UdpOptions = parse_options(["udp", "localhost", "--timeout", "soon"]);
+        let errors = UdpBenchAdapter::from_options(options).err().unwrap();
+        assert_eq!(2, errors.0.len());
+    }
+}
@@ -0,0 +1,369 @@
+/// Copyright 2020 Developers of the perf-gauge project.
+///
+/// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+/// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+/// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+/// option. This file may not be copied, modified, or distributed
+/// except according to those terms.
+use crate::bench_run::BenchmarkProtocolAdapter;
+use crate::config_error::{ConfigError, ConfigErrors};
+use crate::configuration::BenchmarkConfig;
+use crate::metrics::RequestStats;
+use crate::socket_session::{request_stats, Connection, Outcome, SocketError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use clap::Args;
+use core::fmt;
+use derive_builder::Builder;
+use futures_util::{SinkExt, StreamExt};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::Uri;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use serde::Deserialize;
+use std::io;
+use std::str::FromStr;
+use std::time::Instant;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::{Request, Response};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+#[derive(Args, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+#[command(about = "Run in WebSocket mode", long_about = None)]
+pub struct WsOptions {
+    /// Target, e.g. ws://my-service.com:8080/echo or wss://... Can be multiple ones (with random choice balancing).
+    #[arg(required = true)]
+    target: Vec<String>,
+    /// Message to send. Could be either `random://[0-9]+`, `file://$filename` or `base64://${valid_base64}`.
+    /// By default an empty message.
+    #[arg(short = 'B', long)]
+    message: Option<String>,
+    /// Send the message as a text frame instead of a binary one. The message must be valid UTF-8.
+    #[arg(long)]
+    #[serde(default)]
+    text: bool,
+    /// Handshake headers in "Name:Value1" form. E.g. `-H "authorization:Bearer token"`
+    /// It can contain multiple values, e.g. "Name:Value1:Value2:Value3". In this case a random one is chosen for each connection.
+    #[arg(short = 'H', long)]
+    #[serde(default)]
+    header: Vec<String>,
+    /// Allow self signed certificates.
+    #[arg(long = "ignore_cert")]
+    #[serde(default)]
+    ignore_cert: bool,
+}
+
+/// Sends the message over a WebSocket connection and waits for a reply (e.g. from an echo endpoint),
+/// reported as the `message` operation. Opening the connection (including the handshake) is a request
+/// of its own, reported as the `connect` operation. The connection is re-opened after an error.
+#[derive(Builder, Clone)]
+pub struct WsBenchAdapter {
+    targets: Vec<String>,
+    #[builder(default)]
+    headers: Vec<(String, Vec<String>)>,
+    #[builder(default)]
+    message: Bytes,
+    #[builder(default)]
+    text: bool,
+    #[builder(default)]
+    #[cfg_attr(not(feature = "tls-native"), allow(dead_code))]
+    ignore_cert: bool,
+}
+
+/// Connection of a single client. Re-opened after an error.
+pub struct WsClient {
+    stream: Connection<WsStream>,
+}
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+const OPERATION_CONNECT: &str = "connect";
+const OPERATION_MESSAGE: &str = "message";
+const STATUS_OK: &str = "OK";
+
+impl WsBenchAdapter {
+    pub fn from_options(options: WsOptions) -> Result<WsBenchAdapter, ConfigErrors> {
+        let mut errors = vec![];
+
+        for target in &options.target {
+            match Uri::from_str(target) {
+                Ok(uri) => match uri.scheme_str() {
+                    Some("ws") => {}
+                    Some("wss") if cfg!(feature = "tls-native") => {}
+                    Some("wss") => errors.push(ConfigError::Unsupported {
+                        flag: "target",
+                        reason: "wss:// requires the `tls-native` feature".to_string(),
+                    }),
+                    _ => errors.push(ConfigError::InvalidValue {
+                        flag: "target",
+                        value: target.clone(),
+                        reason: "expected ws:// or wss://".to_string(),
+                    }),
+                },
+                Err(e) => errors.push(ConfigError::InvalidValue {
+                    flag: "target",
+                    value: target.clone(),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+
+        let headers: Vec<(String, Vec<String>)> = options
+            .header
+            .iter()
+            .filter_map(|h| BenchmarkConfig::parse_header(h, &mut errors))
+            .collect();
+
+        let message = BenchmarkConfig::generate_body("message", options.message.as_deref())
+            .unwrap_or_else(|e| {
+                errors.push(e);
+                Bytes::new()
+            });
+        if options.text && std::str::from_utf8(&message).is_err() {
+            errors.push(ConfigError::InvalidValue {
+                flag: "message",
+                value: options.message.clone().unwrap_or_default(),
+                reason: "--text requires a valid UTF-8 message".to_string(),
+            });
+        }
+
+        if !errors.is_empty() {
+            return Err(ConfigErrors(errors));
+        }
+
+        Ok(WsBenchAdapterBuilder::default()
+            .targets(options.target)
+            .headers(headers)
+            .message(message)
+            .text(options.text)
+            .ignore_cert(options.ignore_cert)
+            .build()
+            .expect("WsBenchAdapterBuilder failed"))
+    }
+
+    async fn connect(&self) -> Result<WsStream, SocketError> {
+        let target = self
+            .targets
+            .choose(&mut thread_rng())
+            .expect("At least one target is required");
+        let mut request = target
+            .as_str()
+            .into_client_request()
+            .map_err(SocketError::connect)?;
+        for (name, values) in &self.headers {
+            let value = values
+                .choose(&mut thread_rng())
+                .expect("At least one header value is required");
+            request.headers_mut().append(
+                HeaderName::from_str(name).map_err(SocketError::connect)?,
+                HeaderValue::from_str(value).map_err(SocketError::connect)?,
+            );
+        }
+
+        let (stream, _) = self.handshake(request).await?;
+        Ok(stream)
+    }
+
+    #[cfg(feature = "tls-native")]
+    async fn handshake(&self, request: Request) -> Result<(WsStream, Response), SocketError> {
+        let connector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(self.ignore_cert)
+            .build()
+            .map_err(SocketError::connect)?;
+        tokio_tungstenite::connect_async_tls_with_config(
+            request,
+            None,
+            true,
+            Some(tokio_tungstenite::Connector::NativeTls(connector)),
+        )
+        .await
+        .map_err(SocketError::connect)
+    }
+
+    /// Only plain connections, as `wss://` targets are rejected by [`WsBenchAdapter::from_options`].
+    #[cfg(not(feature = "tls-native"))]
+    async fn handshake(&self, request: Request) -> Result<(WsStream, Response), SocketError> {
+        tokio_tungstenite::connect_async_with_config(request, None, true)
+            .await
+            .map_err(SocketError::connect)
+    }
+
+    /// Sends the message and returns the size of the reply.
+    async fn round_trip(&self, stream: &mut WsStream) -> Result<usize, SocketError> {
+        let message = if self.text {
+            Message::text(String::from_utf8_lossy(&self.message).into_owned())
+        } else {
+            Message::binary(self.message.clone())
+        };
+        stream.send(message).await.map_err(SocketError::request)?;
+
+        while let Some(reply) = stream.next().await {
+            match reply.map_err(SocketError::request)? {
+                Message::Text(text) => return Ok(text.len()),
+                Message::Binary(data) => return Ok(data.len()),
+                Message::Close(_) => break,
+                // control frames are handled by the stream
+                _ => {}
+            }
+        }
+        Err(SocketError::request(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Connection closed",
+        )))
+    }
+}
+
+#[async_trait]
+impl BenchmarkProtocolAdapter for WsBenchAdapter {
+    type Client = WsClient;
+
+    async fn build_client(&self) -> Result<Self::Client, String> {
+        Ok(WsClient {
+            stream: Connection::new(None),
+        })
+    }
+
+    /// Requests share the connection of a client, one at a time.
+    fn supports_open_model(&self) -> bool {
+        false
+    }
+
+    async fn send_request(&self, client: &Self::Client) -> RequestStats {
+        let mut stream = client.stream.lock().await;
+        let start = Instant::now();
+
+        let result = match stream.open(self.connect()).await {
+            Ok((_, Some(connect))) => {
+                return request_stats(
+                    Some(OPERATION_CONNECT),
+                    Ok(Outcome::success(0, STATUS_OK)),
+                    start,
+                    Some(connect),
+                );
+            }
+            Ok((connected, None)) => self.round_trip(connected).await,
+            Err(e) => return request_stats(Some(OPERATION_CONNECT), Err(e), start, None),
+        };
+        if result.is_err() {
+            // re-connect on the next request
+            stream.close();
+        }
+        request_stats(
+            Some(OPERATION_MESSAGE),
+            result.map(|size| Outcome::success(size, STATUS_OK)),
+            start,
+            None,
+        )
+    }
+}
+
+impl fmt::Display for WsBenchAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WebSocket {:?}, message size: {} bytes",
+            self.targets,
+            self.message.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench_run::BenchmarkProtocolAdapter;
+    use crate::config_error::ConfigError;
+    use crate::socket_session::tests::{parse_options, start_tcp_server};
+    use crate::ws_bench_session::{WsBenchAdapter, WsOptions};
+    use futures_util::StreamExt;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+
+    /// Echoes data messages back.
+    async fn start_server() -> SocketAddr {
+        start_tcp_server(|stream| async move {
+            let ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let (write, read) = ws.split();
+            read.filter(|m| futures_util::future::ready(m.as_ref().is_ok_and(|m| m.is_binary())))
+                .forward(write)
+                .await
+                .unwrap_or_default();
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_ws_round_trip() {
+        let addr = start_server().await;
+        let options: WsOptions = parse_options([
+            "ws",
+            &format!("ws://{addr}/echo"),
+            "-B",
+            "random://16",
+            "-H",
+            "x-test:1:2",
+        ]);
+        let adapter = WsBenchAdapter::from_options(options).unwrap();
+        assert!(adapter.to_string().starts_with("WebSocket"));
+
+        let client = adapter.build_client().await.unwrap();
+        // the first request opens the connection
+        let stats = adapter.send_request(&client).await;
+        assert!(stats.is_success);
+        assert_eq!("OK", stats.status);
+        assert_eq!(Some("connect"), stats.operation_name.as_deref());
+        assert!(stats.phases.unwrap().connect.is_some());
+
+        for _ in 0..3 {
+            let stats = adapter.send_request(&client).await;
+            assert!(stats.is_success);
+            assert_eq!("OK", stats.status);
+            assert_eq!(Some("message"), stats.operation_name.as_deref());
+            assert_eq!(16, stats.bytes_processed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ws_connect_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let options: WsOptions = parse_options(["ws", &format!("ws://{addr}")]);
+        let adapter = WsBenchAdapter::from_options(options).unwrap();
+        let client = adapter.build_client().await.unwrap();
+        let stats = adapter.send_request(&client).await;
+        assert!(!stats.is_success);
+        assert_eq!("connect_refused", stats.status);
+        assert_eq!(Some("connect"), stats.operation_name.as_deref());
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let options: WsOptions = parse_options([
+            "ws",
+            "http://localhost:8080",
+            "-B",
+            "base64:///w==",
+            "--text",
+        ]);
+        let errors = WsBenchAdapter::from_options(options).err().unwrap();
+        assert_eq!(
+            vec![
+                ConfigError::InvalidValue {
+                    flag: "target",
+                    value: "http://localhost:8080".to_string(),
+                    reason: "expected ws:// or wss://".to_string(),
+                },
+                ConfigError::InvalidValue {
+                    flag: "message",
+                    value: "base64:///w==".to_string(),
+                    reason: "--text requires a valid UTF-8 message".to_string(),
+                },
+            ],
+            errors.0
+        );
+    }
+}